@@ -0,0 +1,175 @@
+use teloxide::{prelude::Requester, types::ChatId};
+
+use crate::{
+    db::{Delegation, Storage, User},
+    endpoints::SharedState,
+    twitter::{info::AccountStatus, oauth2},
+};
+
+/// Finds the linked account called `handle`, with or without the leading `@`.
+pub fn find_account(users: Vec<User>, handle: &str) -> Option<User> {
+    let handle = handle.strip_prefix('@').unwrap_or(handle);
+    users
+        .into_iter()
+        .find(|user| user.username.eq_ignore_ascii_case(handle))
+}
+
+/// The account a command acts as.
+#[derive(Debug)]
+pub struct ActingAccount {
+    pub user: User,
+    /// The chat the account is linked to.
+    pub chat_id: String,
+    /// The invite code and terms when the account is used through a
+    /// delegation rather than linked to the chat itself.
+    pub delegation: Option<(String, Delegation)>,
+}
+
+/// Live delegations redeemed by `chat_id`, along with the delegated accounts.
+/// Delegations whose account was unlinked since are skipped.
+pub fn delegated_accounts(db: &dyn Storage, chat_id: &str) -> eyre::Result<Vec<ActingAccount>> {
+    let mut accounts = Vec::new();
+    for (code, delegation) in db.list_delegations(chat_id)? {
+        if delegation.delegate_chat_id.as_deref() != Some(chat_id) || delegation.is_expired() {
+            continue;
+        }
+        let user = db
+            .get_chat_users(&delegation.owner_chat_id)?
+            .into_iter()
+            .find(|user| user.x_id == delegation.x_id);
+        if let Some(user) = user {
+            accounts.push(ActingAccount {
+                user,
+                chat_id: delegation.owner_chat_id.clone(),
+                delegation: Some((code, delegation)),
+            });
+        }
+    }
+    Ok(accounts)
+}
+
+/// Outcome of checking a linked account against Twitter.
+#[derive(Debug)]
+pub enum AccountCheck {
+    Unchanged(User),
+    /// The handle or id changed since the account was linked.
+    Updated {
+        previous_username: String,
+        user: User,
+    },
+    Revoked(User),
+}
+
+impl AccountCheck {
+    /// The message to send to the chat, if the check found anything new.
+    pub fn notification(&self) -> Option<String> {
+        match self {
+            Self::Unchanged(_) => None,
+            Self::Updated {
+                previous_username,
+                user,
+            } => Some(format!(
+                "The linked Twitter account @{} is now https://x.com/{}",
+                previous_username, user.username
+            )),
+            Self::Revoked(user) => Some(format!(
+                "The bot's access to @{} was revoked on Twitter. Run /auth to link it again.",
+                user.username
+            )),
+        }
+    }
+}
+
+impl SharedState {
+    /// Fetches the current profile of `user`, persisting a changed handle or
+    /// id, or marking the account as revoked if Twitter rejects it. Transient
+    /// failures are returned as errors and leave the account untouched.
+    pub async fn validate_account(&self, chat_id: &str, user: User) -> eyre::Result<AccountCheck> {
+        let mut user = match self.refresh_credentials(chat_id, user.clone()).await {
+            Ok(user) => user,
+            Err(e) if oauth2::is_grant_rejected(&e) => {
+                return self.mark_revoked(chat_id, user);
+            }
+            Err(e) => return Err(e),
+        };
+        let status = self
+            .twitter
+            .with_auth(user.credentials.clone())
+            .get_account_status()
+            .await?;
+        let info = match status {
+            AccountStatus::Active(info) => info,
+            AccountStatus::Revoked => return self.mark_revoked(chat_id, user),
+        };
+        if info.id == user.x_id && info.username == user.username && !user.revoked {
+            return Ok(AccountCheck::Unchanged(user));
+        }
+        let previous_username = std::mem::replace(&mut user.username, info.username);
+        user.revoked = false;
+        if info.id != user.x_id {
+            // Accounts are keyed by id, so re-link under the new one.
+            let was_active = self
+                .db
+                .get_active_user(chat_id)?
+                .is_some_and(|active| active.x_id == user.x_id);
+            self.db.remove_user(chat_id, &user.x_id)?;
+            user.x_id = info.id;
+            self.db.insert_user(chat_id.to_string(), user.clone())?;
+            if was_active {
+                self.db.set_active_user(chat_id, &user.x_id)?;
+            }
+        } else {
+            self.db.insert_user(chat_id.to_string(), user.clone())?;
+        }
+        if previous_username == user.username {
+            return Ok(AccountCheck::Unchanged(user));
+        }
+        log::info!("Account @{} is now @{}", previous_username, user.username);
+        Ok(AccountCheck::Updated {
+            previous_username,
+            user,
+        })
+    }
+
+    fn mark_revoked(&self, chat_id: &str, mut user: User) -> eyre::Result<AccountCheck> {
+        log::info!("Access to @{} was revoked", user.username);
+        user.revoked = true;
+        self.db.insert_user(chat_id.to_string(), user.clone())?;
+        Ok(AccountCheck::Revoked(user))
+    }
+}
+
+/// Checks every account that is not already known to be revoked and tells
+/// the chats whose account changed.
+pub async fn validate_accounts(shared_state: SharedState) {
+    let users = match shared_state.db.list_users() {
+        Ok(users) => users,
+        Err(e) => {
+            log::error!("Failed to list accounts: {:?}", e);
+            return;
+        }
+    };
+    for (chat_id, user) in users.into_iter().filter(|(_, user)| !user.revoked) {
+        let check = match shared_state.validate_account(&chat_id, user).await {
+            Ok(check) => check,
+            Err(e) => {
+                log::warn!("Could not validate account of chat {}: {:?}", chat_id, e);
+                continue;
+            }
+        };
+        let Some(notification) = check.notification() else {
+            continue;
+        };
+        let Ok(tg_chat_id) = chat_id.parse().map(ChatId) else {
+            continue;
+        };
+        if let Err(e) = shared_state
+            .bot
+            .send_message(tg_chat_id, notification)
+            .await
+        {
+            log::warn!("Failed to notify chat {}: {:?}", chat_id, e);
+        }
+    }
+    log::info!("Finished validating linked accounts");
+}
@@ -0,0 +1,159 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// A value sealed with ChaCha20-Poly1305. Only the holder of the key can read
+/// or forge it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SealedBox {
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl SealedBox {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.nonce.as_slice(), self.ciphertext.as_slice()].concat()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> eyre::Result<Self> {
+        if bytes.len() < 12 {
+            eyre::bail!("Sealed value is too short");
+        }
+        let (nonce, ciphertext) = bytes.split_at(12);
+        Ok(Self {
+            nonce: nonce.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+}
+
+/// Seals records with the current key and opens records sealed with either the
+/// current key or one of the previous keys still being rotated out.
+#[derive(Clone)]
+pub struct TokenCipher {
+    current: ChaCha20Poly1305,
+    previous: Vec<ChaCha20Poly1305>,
+}
+
+impl std::fmt::Debug for TokenCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenCipher")
+            .field("previous_keys", &self.previous.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn parse_key(key: &str) -> eyre::Result<ChaCha20Poly1305> {
+    let bytes = STANDARD.decode(key.trim())?;
+    if bytes.len() != 32 {
+        eyre::bail!(
+            "Encryption key must be 32 bytes encoded as base64, got {} bytes",
+            bytes.len()
+        );
+    }
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&bytes)))
+}
+
+impl TokenCipher {
+    /// `current` and every entry of `previous` are base64-encoded 32 byte keys,
+    /// e.g. the output of `openssl rand -base64 32`.
+    pub fn new(current: &str, previous: &[String]) -> eyre::Result<Self> {
+        Ok(Self {
+            current: parse_key(current)?,
+            previous: previous
+                .iter()
+                .map(|k| parse_key(k))
+                .collect::<eyre::Result<_>>()?,
+        })
+    }
+
+    pub fn has_previous_keys(&self) -> bool {
+        !self.previous.is_empty()
+    }
+
+    pub fn seal<T: Serialize>(&self, value: &T) -> eyre::Result<SealedBox> {
+        let plaintext = bincode::serialize(value)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .current
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| eyre::eyre!("Failed to encrypt record"))?;
+        Ok(SealedBox {
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    pub fn open<T: DeserializeOwned>(&self, sealed: &SealedBox) -> eyre::Result<T> {
+        if sealed.nonce.len() != 12 {
+            eyre::bail!("Sealed record has an invalid nonce");
+        }
+        let nonce = Nonce::from_slice(&sealed.nonce);
+        let plaintext = std::iter::once(&self.current)
+            .chain(self.previous.iter())
+            .find_map(|key| key.decrypt(nonce, sealed.ciphertext.as_slice()).ok())
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "Failed to decrypt stored tokens: DB_ENCRYPTION_KEY does not match the key \
+                     the database was written with"
+                )
+            })?;
+        Ok(bincode::deserialize(&plaintext)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::storage_tests::{KEY, OTHER_KEY};
+
+    #[test]
+    fn seals_and_opens() {
+        let cipher = TokenCipher::new(KEY, &[]).unwrap();
+        let sealed = cipher.seal(&("token", 7u32)).unwrap();
+        let opened: (String, u32) = cipher.open(&sealed).unwrap();
+        assert_eq!(opened, ("token".to_string(), 7));
+
+        // The stored bytes hold the nonce and the ciphertext.
+        let restored = SealedBox::from_bytes(&sealed.to_bytes()).unwrap();
+        let opened: (String, u32) = cipher.open(&restored).unwrap();
+        assert_eq!(opened.1, 7);
+        assert!(SealedBox::from_bytes(&[0; 11]).is_err());
+    }
+
+    #[test]
+    fn opens_with_previous_keys() {
+        let old = TokenCipher::new(KEY, &[]).unwrap();
+        let sealed = old.seal(&"token").unwrap();
+        let rotated = TokenCipher::new(OTHER_KEY, &[KEY.to_string()]).unwrap();
+        assert!(rotated.has_previous_keys());
+        assert_eq!(rotated.open::<String>(&sealed).unwrap(), "token");
+
+        // New records are sealed with the current key only.
+        let resealed = rotated.seal(&"token").unwrap();
+        assert!(old.open::<String>(&resealed).is_err());
+    }
+
+    #[test]
+    fn refuses_the_wrong_key() {
+        let sealed = TokenCipher::new(KEY, &[]).unwrap().seal(&"token").unwrap();
+        let other = TokenCipher::new(OTHER_KEY, &[]).unwrap();
+        let err = other.open::<String>(&sealed).unwrap_err();
+        assert!(err.to_string().contains("DB_ENCRYPTION_KEY"));
+
+        let mut tampered = sealed.clone();
+        tampered.ciphertext[0] ^= 1;
+        let cipher = TokenCipher::new(KEY, &[]).unwrap();
+        assert!(cipher.open::<String>(&tampered).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert!(TokenCipher::new("not base64!", &[]).is_err());
+        assert!(TokenCipher::new("AAAA", &[]).is_err());
+        assert!(TokenCipher::new(KEY, &["AAAA".to_string()]).is_err());
+    }
+}
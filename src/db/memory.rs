@@ -0,0 +1,919 @@
+use std::{collections::BTreeMap, io::Write, sync::Mutex};
+
+use eyre::WrapErr;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    crypto::{SealedBox, TokenCipher},
+    draw_buckets,
+    migrations::{self, TableMap},
+    unix_now, AuthMethod, Bucket, BucketDraw, ChatSettings, Delegation, PendingAuth, PendingPost,
+    Role, ScheduledSend, Storage, User,
+};
+use crate::twitter::auth::Credentials;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct StoredUser {
+    x_id: String,
+    username: String,
+    credentials: SealedBox,
+    revoked: bool,
+}
+
+/// The accounts linked to one chat, keyed by Twitter user id.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct ChatAccounts {
+    active: Option<String>,
+    users: BTreeMap<String, StoredUser>,
+}
+
+/// A [`PendingPost`] with its tweet stored as JSON, which unlike bincode
+/// copes with the optional fields of the API body.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredPost {
+    chat_id: String,
+    account_chat_id: String,
+    author_id: Option<u64>,
+    x_id: String,
+    tweet: String,
+    approvals: Vec<u64>,
+    approvals_required: u32,
+    created_at: u64,
+}
+
+impl StoredPost {
+    fn seal(post: &PendingPost) -> eyre::Result<Self> {
+        Ok(Self {
+            chat_id: post.chat_id.clone(),
+            account_chat_id: post.account_chat_id.clone(),
+            author_id: post.author_id,
+            x_id: post.x_id.clone(),
+            tweet: serde_json::to_string(&post.tweet)?,
+            approvals: post.approvals.clone(),
+            approvals_required: post.approvals_required,
+            created_at: post.created_at,
+        })
+    }
+
+    fn open(self) -> eyre::Result<PendingPost> {
+        Ok(PendingPost {
+            chat_id: self.chat_id,
+            account_chat_id: self.account_chat_id,
+            author_id: self.author_id,
+            x_id: self.x_id,
+            tweet: serde_json::from_str(&self.tweet)?,
+            approvals: self.approvals,
+            approvals_required: self.approvals_required,
+            created_at: self.created_at,
+        })
+    }
+}
+
+/// A [`ScheduledSend`] with its tweet stored as JSON, like [`StoredPost`].
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredSend {
+    chat_id: String,
+    account_chat_id: String,
+    author_id: Option<u64>,
+    x_id: String,
+    tweet: String,
+    send_at: u64,
+    message_id: i32,
+}
+
+impl StoredSend {
+    fn seal(send: &ScheduledSend) -> eyre::Result<Self> {
+        Ok(Self {
+            chat_id: send.chat_id.clone(),
+            account_chat_id: send.account_chat_id.clone(),
+            author_id: send.author_id,
+            x_id: send.x_id.clone(),
+            tweet: serde_json::to_string(&send.tweet)?,
+            send_at: send.send_at,
+            message_id: send.message_id,
+        })
+    }
+
+    fn open(self) -> eyre::Result<ScheduledSend> {
+        Ok(ScheduledSend {
+            chat_id: self.chat_id,
+            account_chat_id: self.account_chat_id,
+            author_id: self.author_id,
+            x_id: self.x_id,
+            tweet: serde_json::from_str(&self.tweet)?,
+            send_at: self.send_at,
+            message_id: self.message_id,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Tables {
+    oauth_tokens: BTreeMap<String, PendingAuth>,
+    access_tokens: BTreeMap<String, ChatAccounts>,
+    /// Explicitly granted roles by chat and Telegram user id.
+    roles: BTreeMap<String, BTreeMap<u64, Role>>,
+    chat_settings: BTreeMap<String, ChatSettings>,
+    pending_posts: BTreeMap<String, PendingPost>,
+    scheduled_sends: BTreeMap<String, ScheduledSend>,
+    delegations: BTreeMap<String, Delegation>,
+    rate_limits: BTreeMap<String, Bucket>,
+}
+
+fn read_table<T: DeserializeOwned + Default>(map: &TableMap, name: &str) -> eyre::Result<T> {
+    match map.get(name) {
+        Some(bytes) => {
+            bincode::deserialize(bytes).wrap_err_with(|| format!("Failed to read table {}", name))
+        }
+        None => Ok(T::default()),
+    }
+}
+
+impl Tables {
+    fn from_payload(payload: &[u8]) -> eyre::Result<Self> {
+        let map: TableMap = bincode::deserialize(payload)?;
+        Ok(Self {
+            oauth_tokens: read_table(&map, "oauth_tokens")?,
+            access_tokens: read_table(&map, "access_tokens")?,
+            roles: read_table(&map, "roles")?,
+            chat_settings: read_table(&map, "chat_settings")?,
+            pending_posts: read_table::<BTreeMap<String, StoredPost>>(&map, "pending_posts")?
+                .into_iter()
+                .map(|(id, post)| Ok((id, post.open()?)))
+                .collect::<eyre::Result<_>>()?,
+            scheduled_sends: read_table::<BTreeMap<String, StoredSend>>(&map, "scheduled_sends")?
+                .into_iter()
+                .map(|(id, send)| Ok((id, send.open()?)))
+                .collect::<eyre::Result<_>>()?,
+            delegations: read_table(&map, "delegations")?,
+            rate_limits: read_table(&map, "rate_limits")?,
+        })
+    }
+
+    fn to_payload(&self) -> eyre::Result<Vec<u8>> {
+        let mut map = TableMap::new();
+        map.insert(
+            "oauth_tokens".to_string(),
+            bincode::serialize(&self.oauth_tokens)?,
+        );
+        map.insert(
+            "access_tokens".to_string(),
+            bincode::serialize(&self.access_tokens)?,
+        );
+        map.insert("roles".to_string(), bincode::serialize(&self.roles)?);
+        map.insert(
+            "chat_settings".to_string(),
+            bincode::serialize(&self.chat_settings)?,
+        );
+        map.insert(
+            "pending_posts".to_string(),
+            bincode::serialize(
+                &self
+                    .pending_posts
+                    .iter()
+                    .map(|(id, post)| Ok((id, StoredPost::seal(post)?)))
+                    .collect::<eyre::Result<BTreeMap<_, _>>>()?,
+            )?,
+        );
+        map.insert(
+            "scheduled_sends".to_string(),
+            bincode::serialize(
+                &self
+                    .scheduled_sends
+                    .iter()
+                    .map(|(id, send)| Ok((id, StoredSend::seal(send)?)))
+                    .collect::<eyre::Result<BTreeMap<_, _>>>()?,
+            )?,
+        );
+        map.insert(
+            "delegations".to_string(),
+            bincode::serialize(&self.delegations)?,
+        );
+        map.insert(
+            "rate_limits".to_string(),
+            bincode::serialize(&self.rate_limits)?,
+        );
+        Ok(bincode::serialize(&map)?)
+    }
+}
+
+/// Keeps every table in memory and rewrites the whole bincode file on each
+/// mutation.
+#[derive(Debug)]
+pub struct InMemoryDB {
+    path: String,
+    cipher: TokenCipher,
+    tables: Mutex<Tables>,
+}
+
+impl InMemoryDB {
+    fn save(&self, tables: &Tables) -> eyre::Result<()> {
+        // Write to a sibling file and rename it over the old one so a crash
+        // mid-write never leaves a truncated database behind.
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&migrations::encode(&tables.to_payload()?))?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Returns `None` if there is no database file yet. A file that exists but
+    /// cannot be read is an error: starting from an empty database would
+    /// silently log every chat out.
+    fn load(path: &str, cipher: &TokenCipher) -> eyre::Result<Option<(Tables, bool)>> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let (version, payload) = migrations::decode(&bytes)?;
+        let migrated = version != migrations::CURRENT_VERSION;
+        if migrated {
+            let backup_path = format!("{}.v{}.bak", path, version);
+            std::fs::write(&backup_path, &bytes)?;
+            log::info!(
+                "Backed up version {} database to {} before migrating",
+                version,
+                backup_path
+            );
+        }
+        let payload = migrations::upgrade(version, payload, cipher)?;
+        Ok(Some((Tables::from_payload(&payload)?, migrated)))
+    }
+
+    pub fn load_or_create(path: &str, cipher: TokenCipher) -> eyre::Result<Self> {
+        let loaded = Self::load(path, &cipher)
+            .wrap_err_with(|| format!("Failed to load database from {}", path))?;
+        let (tables, migrated) = match loaded {
+            Some(loaded) => {
+                log::info!("Loaded database from {}", path);
+                loaded
+            }
+            None => {
+                log::info!("Creating new database at {}", path);
+                (Tables::default(), true)
+            }
+        };
+        // Refuse to start with the wrong key rather than failing on first use.
+        for user in tables.access_tokens.values().flat_map(|c| c.users.values()) {
+            cipher.open::<Credentials>(&user.credentials)?;
+        }
+        let db = Self {
+            path: path.to_string(),
+            cipher,
+            tables: Mutex::new(tables.clone()),
+        };
+        if migrated {
+            db.save(&tables)?;
+        }
+        Ok(db)
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&Tables) -> T) -> eyre::Result<T> {
+        let tables = self
+            .tables
+            .lock()
+            .map_err(|_| eyre::eyre!("Database lock poisoned"))?;
+        Ok(f(&tables))
+    }
+
+    /// Applies `f` to a copy of the tables and only swaps it in once the copy
+    /// has been written to disk.
+    fn write<T>(&self, f: impl FnOnce(&mut Tables) -> eyre::Result<T>) -> eyre::Result<T> {
+        let mut tables = self
+            .tables
+            .lock()
+            .map_err(|_| eyre::eyre!("Database lock poisoned"))?;
+        let mut updated = tables.clone();
+        let result = f(&mut updated)?;
+        self.save(&updated)?;
+        *tables = updated;
+        Ok(result)
+    }
+
+    fn open_user(&self, user: StoredUser) -> eyre::Result<User> {
+        Ok(User {
+            credentials: self.cipher.open(&user.credentials)?,
+            x_id: user.x_id,
+            username: user.username,
+            revoked: user.revoked,
+        })
+    }
+}
+
+fn seal_user(cipher: &TokenCipher, user: User) -> eyre::Result<StoredUser> {
+    Ok(StoredUser {
+        credentials: cipher.seal(&user.credentials)?,
+        x_id: user.x_id,
+        username: user.username,
+        revoked: user.revoked,
+    })
+}
+
+impl Storage for InMemoryDB {
+    fn insert_oauth_token(&self, token: String, pending: PendingAuth) -> eyre::Result<()> {
+        self.write(|t| {
+            t.oauth_tokens.insert(token, pending);
+            Ok(())
+        })
+    }
+
+    fn take_oauth_token(&self, token: &str, chat_id: &str) -> eyre::Result<Option<PendingAuth>> {
+        self.write(|t| {
+            if t.oauth_tokens
+                .get(token)
+                .is_none_or(|p| p.chat_id != chat_id)
+            {
+                return Ok(None);
+            }
+            Ok(t.oauth_tokens.remove(token))
+        })
+    }
+
+    fn take_latest_oauth_token(
+        &self,
+        chat_id: &str,
+        method: AuthMethod,
+    ) -> eyre::Result<Option<(String, PendingAuth)>> {
+        let token = self.read(|t| {
+            t.oauth_tokens
+                .iter()
+                .filter(|(_, p)| p.chat_id == chat_id && p.method == method)
+                .max_by_key(|(_, p)| p.created_at)
+                .map(|(token, _)| token.clone())
+        })?;
+        let Some(token) = token else {
+            return Ok(None);
+        };
+        let pending = self.write(|t| Ok(t.oauth_tokens.remove(&token)))?;
+        Ok(pending.map(|p| (token, p)))
+    }
+
+    fn purge_oauth_tokens(&self, created_before: u64) -> eyre::Result<usize> {
+        let has_expired = self.read(|t| {
+            t.oauth_tokens
+                .values()
+                .any(|p| p.created_at < created_before)
+        })?;
+        if !has_expired {
+            return Ok(0);
+        }
+        self.write(|t| {
+            let before = t.oauth_tokens.len();
+            t.oauth_tokens.retain(|_, p| p.created_at >= created_before);
+            Ok(before - t.oauth_tokens.len())
+        })
+    }
+
+    fn get_active_user(&self, chat_id: &str) -> eyre::Result<Option<User>> {
+        self.read(|t| {
+            let accounts = t.access_tokens.get(chat_id)?;
+            accounts.users.get(accounts.active.as_ref()?).cloned()
+        })?
+        .map(|user| self.open_user(user))
+        .transpose()
+    }
+
+    fn get_chat_users(&self, chat_id: &str) -> eyre::Result<Vec<User>> {
+        self.read(|t| t.access_tokens.get(chat_id).cloned())?
+            .map(|accounts| accounts.users.into_values().collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|user| self.open_user(user))
+            .collect()
+    }
+
+    fn set_active_user(&self, chat_id: &str, x_id: &str) -> eyre::Result<bool> {
+        let linked = self.read(|t| {
+            t.access_tokens
+                .get(chat_id)
+                .is_some_and(|a| a.users.contains_key(x_id))
+        })?;
+        if !linked {
+            return Ok(false);
+        }
+        self.write(|t| {
+            if let Some(accounts) = t.access_tokens.get_mut(chat_id) {
+                accounts.active = Some(x_id.to_string());
+            }
+            Ok(true)
+        })
+    }
+
+    fn insert_user(&self, chat_id: String, user: User) -> eyre::Result<()> {
+        let user = seal_user(&self.cipher, user)?;
+        self.write(|t| {
+            let accounts = t.access_tokens.entry(chat_id).or_default();
+            accounts.active.get_or_insert_with(|| user.x_id.clone());
+            accounts.users.insert(user.x_id.clone(), user);
+            Ok(())
+        })
+    }
+
+    fn remove_user(&self, chat_id: &str, x_id: &str) -> eyre::Result<Option<User>> {
+        self.write(|t| {
+            let Some(accounts) = t.access_tokens.get_mut(chat_id) else {
+                return Ok(None);
+            };
+            let user = accounts.users.remove(x_id);
+            if accounts.active.as_deref() == Some(x_id) {
+                accounts.active = accounts.users.keys().next().cloned();
+            }
+            if accounts.users.is_empty() {
+                t.access_tokens.remove(chat_id);
+            }
+            Ok(user)
+        })?
+        .map(|user| self.open_user(user))
+        .transpose()
+    }
+
+    fn list_users(&self) -> eyre::Result<Vec<(String, User)>> {
+        let users: Vec<(String, StoredUser)> = self.read(|t| {
+            t.access_tokens
+                .iter()
+                .flat_map(|(chat_id, accounts)| {
+                    accounts
+                        .users
+                        .values()
+                        .map(move |user| (chat_id.clone(), user.clone()))
+                })
+                .collect()
+        })?;
+        users
+            .into_iter()
+            .map(|(chat_id, user)| Ok((chat_id, self.open_user(user)?)))
+            .collect()
+    }
+
+    fn get_role(&self, chat_id: &str, user_id: u64) -> eyre::Result<Option<Role>> {
+        self.read(|t| t.roles.get(chat_id)?.get(&user_id).copied())
+    }
+
+    fn set_role(&self, chat_id: &str, user_id: u64, role: Role) -> eyre::Result<()> {
+        self.write(|t| {
+            t.roles
+                .entry(chat_id.to_string())
+                .or_default()
+                .insert(user_id, role);
+            Ok(())
+        })
+    }
+
+    fn remove_role(&self, chat_id: &str, user_id: u64) -> eyre::Result<bool> {
+        if self.get_role(chat_id, user_id)?.is_none() {
+            return Ok(false);
+        }
+        self.write(|t| {
+            if let Some(roles) = t.roles.get_mut(chat_id) {
+                roles.remove(&user_id);
+                if roles.is_empty() {
+                    t.roles.remove(chat_id);
+                }
+            }
+            Ok(true)
+        })
+    }
+
+    fn list_roles(&self, chat_id: &str) -> eyre::Result<Vec<(u64, Role)>> {
+        self.read(|t| {
+            t.roles
+                .get(chat_id)
+                .map(|roles| roles.iter().map(|(id, role)| (*id, *role)).collect())
+                .unwrap_or_default()
+        })
+    }
+
+    fn get_chat_settings(&self, chat_id: &str) -> eyre::Result<ChatSettings> {
+        self.read(|t| t.chat_settings.get(chat_id).cloned().unwrap_or_default())
+    }
+
+    fn set_chat_settings(&self, chat_id: &str, settings: ChatSettings) -> eyre::Result<()> {
+        self.write(|t| {
+            if settings == ChatSettings::default() {
+                t.chat_settings.remove(chat_id);
+            } else {
+                t.chat_settings.insert(chat_id.to_string(), settings);
+            }
+            Ok(())
+        })
+    }
+
+    fn insert_pending_post(&self, id: String, post: PendingPost) -> eyre::Result<()> {
+        self.write(|t| {
+            t.pending_posts.insert(id, post);
+            Ok(())
+        })
+    }
+
+    fn get_pending_post(&self, id: &str) -> eyre::Result<Option<PendingPost>> {
+        self.read(|t| t.pending_posts.get(id).cloned())
+    }
+
+    fn approve_pending_post(&self, id: &str, user_id: u64) -> eyre::Result<Option<PendingPost>> {
+        self.write(|t| {
+            let Some(post) = t.pending_posts.get_mut(id) else {
+                return Ok(None);
+            };
+            if !post.approvals.contains(&user_id) {
+                post.approvals.push(user_id);
+            }
+            Ok(Some(post.clone()))
+        })
+    }
+
+    fn take_pending_post(&self, id: &str) -> eyre::Result<Option<PendingPost>> {
+        self.write(|t| Ok(t.pending_posts.remove(id)))
+    }
+
+    fn take_expired_pending_posts(
+        &self,
+        created_before: u64,
+    ) -> eyre::Result<Vec<(String, PendingPost)>> {
+        let has_expired = self.read(|t| {
+            t.pending_posts
+                .values()
+                .any(|p| p.created_at < created_before)
+        })?;
+        if !has_expired {
+            return Ok(Vec::new());
+        }
+        self.write(|t| {
+            let (expired, pending) = std::mem::take(&mut t.pending_posts)
+                .into_iter()
+                .partition(|(_, p)| p.created_at < created_before);
+            t.pending_posts = pending;
+            Ok(expired.into_iter().collect())
+        })
+    }
+
+    fn insert_scheduled_send(&self, id: String, send: ScheduledSend) -> eyre::Result<()> {
+        self.write(|t| {
+            t.scheduled_sends.insert(id, send);
+            Ok(())
+        })
+    }
+
+    fn get_scheduled_send(&self, id: &str) -> eyre::Result<Option<ScheduledSend>> {
+        self.read(|t| t.scheduled_sends.get(id).cloned())
+    }
+
+    fn take_scheduled_send(&self, id: &str) -> eyre::Result<Option<ScheduledSend>> {
+        self.write(|t| Ok(t.scheduled_sends.remove(id)))
+    }
+
+    fn list_scheduled_sends(&self) -> eyre::Result<Vec<(String, ScheduledSend)>> {
+        self.read(|t| {
+            t.scheduled_sends
+                .iter()
+                .map(|(id, send)| (id.clone(), send.clone()))
+                .collect()
+        })
+    }
+
+    fn insert_delegation(&self, code: String, delegation: Delegation) -> eyre::Result<()> {
+        self.write(|t| {
+            t.delegations.insert(code, delegation);
+            Ok(())
+        })
+    }
+
+    fn get_delegation(&self, code: &str) -> eyre::Result<Option<Delegation>> {
+        self.read(|t| t.delegations.get(code).cloned())
+    }
+
+    fn redeem_delegation(&self, code: &str, chat_id: &str) -> eyre::Result<Option<Delegation>> {
+        self.write(|t| {
+            let Some(delegation) = t.delegations.get_mut(code) else {
+                return Ok(None);
+            };
+            if delegation.delegate_chat_id.is_some() || delegation.is_expired() {
+                return Ok(None);
+            }
+            delegation.delegate_chat_id = Some(chat_id.to_string());
+            Ok(Some(delegation.clone()))
+        })
+    }
+
+    fn consume_delegation(&self, code: &str, count: u32) -> eyre::Result<Option<Delegation>> {
+        self.write(|t| {
+            let Some(delegation) = t.delegations.get_mut(code) else {
+                return Ok(None);
+            };
+            if delegation.is_expired() || !delegation.has_actions_left(count) {
+                return Ok(None);
+            }
+            delegation.actions_used += count;
+            Ok(Some(delegation.clone()))
+        })
+    }
+
+    fn remove_delegation(&self, code: &str) -> eyre::Result<Option<Delegation>> {
+        if self.get_delegation(code)?.is_none() {
+            return Ok(None);
+        }
+        self.write(|t| Ok(t.delegations.remove(code)))
+    }
+
+    fn list_delegations(&self, chat_id: &str) -> eyre::Result<Vec<(String, Delegation)>> {
+        self.read(|t| {
+            t.delegations
+                .iter()
+                .filter(|(_, d)| {
+                    d.owner_chat_id == chat_id || d.delegate_chat_id.as_deref() == Some(chat_id)
+                })
+                .map(|(code, d)| (code.clone(), d.clone()))
+                .collect()
+        })
+    }
+
+    fn draw_rate_limits(&self, draws: &[BucketDraw]) -> eyre::Result<Option<u64>> {
+        let mut tables = self
+            .tables
+            .lock()
+            .map_err(|_| eyre::eyre!("Database lock poisoned"))?;
+        let buckets: Vec<Option<Bucket>> = draws
+            .iter()
+            .map(|draw| tables.rate_limits.get(&draw.key).copied())
+            .collect();
+        // A denied draw changes nothing, so it is not worth rewriting the file.
+        let buckets = match draw_buckets(draws, &buckets, unix_now()) {
+            Ok(buckets) => buckets,
+            Err(wait_secs) => return Ok(Some(wait_secs)),
+        };
+        let mut updated = tables.clone();
+        for (draw, bucket) in draws.iter().zip(buckets) {
+            updated.rate_limits.insert(draw.key.clone(), bucket);
+        }
+        self.save(&updated)?;
+        *tables = updated;
+        Ok(None)
+    }
+
+    fn reencrypt_tokens(&self) -> eyre::Result<usize> {
+        self.write(|t| {
+            let mut count = 0;
+            for user in t
+                .access_tokens
+                .values_mut()
+                .flat_map(|c| c.users.values_mut())
+            {
+                let credentials: Credentials = self.cipher.open(&user.credentials)?;
+                user.credentials = self.cipher.seal(&credentials)?;
+                count += 1;
+            }
+            Ok(count)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Deref;
+
+    use super::*;
+    use crate::{
+        db::storage_tests::{self, storage_tests, TempPath, KEY},
+        twitter::{auth::TwitterTokenPair, tweet::Tweet},
+    };
+
+    /// A database in its own temporary file, removed on drop.
+    struct TempDb {
+        db: InMemoryDB,
+        path: TempPath,
+    }
+
+    impl TempDb {
+        fn new() -> Self {
+            let path = TempPath::new("teleport-memory");
+            Self {
+                db: Self::open(&path.0),
+                path,
+            }
+        }
+
+        fn open(path: &str) -> InMemoryDB {
+            InMemoryDB::load_or_create(path, TokenCipher::new(KEY, &[]).unwrap()).unwrap()
+        }
+
+        /// Loads the file again, as a restart would.
+        fn reopen(&self) -> InMemoryDB {
+            Self::open(&self.path.0)
+        }
+    }
+
+    impl Deref for TempDb {
+        type Target = InMemoryDB;
+
+        fn deref(&self) -> &InMemoryDB {
+            &self.db
+        }
+    }
+
+    storage_tests!(TempDb::new());
+
+    #[test]
+    fn reencrypt_tokens() {
+        let path = TempPath::new("teleport-memory");
+        storage_tests::reencrypt_tokens(|cipher| InMemoryDB::load_or_create(&path.0, cipher));
+    }
+
+    #[test]
+    fn pending_posts_survive_reopening() {
+        // Most optional fields of the tweet are left out of its API body.
+        let mut tweet = Tweet::new("hello".to_string());
+        tweet.set_reply_tweet_id("1".to_string());
+        let post = PendingPost {
+            chat_id: "-100".to_string(),
+            account_chat_id: "-100".to_string(),
+            author_id: Some(7),
+            x_id: "42".to_string(),
+            tweet,
+            approvals: vec![8],
+            approvals_required: 2,
+            created_at: 1_700_000_000,
+        };
+        let db = TempDb::new();
+        db.insert_pending_post("post".to_string(), post).unwrap();
+
+        let post = db.reopen().get_pending_post("post").unwrap().unwrap();
+        assert_eq!(post.tweet.text(), "hello");
+        assert!(post.tweet.is_reply());
+        assert_eq!(post.author_id, Some(7));
+        assert_eq!(post.approvals, vec![8]);
+        assert_eq!(post.approvals_required, 2);
+    }
+
+    /// Layouts written by older versions, as far as the tests below need them.
+    mod legacy {
+        use super::*;
+
+        #[derive(Serialize)]
+        pub struct TokenPair {
+            pub token: String,
+            pub secret: String,
+        }
+
+        #[derive(Serialize)]
+        pub struct V0User {
+            pub x_id: String,
+            pub username: String,
+            pub token_pair: TokenPair,
+        }
+
+        #[derive(Serialize)]
+        pub struct V0Tables {
+            pub oauth_tokens: BTreeMap<String, String>,
+            pub access_tokens: BTreeMap<String, V0User>,
+        }
+
+        #[derive(Serialize)]
+        pub struct V5User {
+            pub x_id: String,
+            pub username: String,
+            pub credentials: SealedBox,
+            pub revoked: bool,
+        }
+
+        #[derive(Serialize)]
+        pub struct V6ChatSettings {
+            pub approvals_required: u32,
+        }
+    }
+
+    fn write_file(path: &str, version: Option<u32>, payload: &[u8]) {
+        let bytes = match version {
+            Some(version) => [
+                migrations::MAGIC.as_slice(),
+                &version.to_le_bytes(),
+                payload,
+            ]
+            .concat(),
+            None => payload.to_vec(),
+        };
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    fn assert_oauth1(user: &User, x_id: &str, username: &str) {
+        assert_eq!(
+            (user.x_id.as_str(), user.username.as_str()),
+            (x_id, username)
+        );
+        assert!(!user.revoked);
+        let Credentials::OAuth1(pair) = &user.credentials else {
+            panic!("Expected OAuth 1.0a credentials");
+        };
+        assert_eq!(
+            (pair.token.as_str(), pair.secret.as_str()),
+            ("token", "secret")
+        );
+    }
+
+    #[test]
+    fn migrates_from_version_0() {
+        let tables = legacy::V0Tables {
+            oauth_tokens: BTreeMap::from([("request".to_string(), "secret".to_string())]),
+            access_tokens: BTreeMap::from([(
+                "-100".to_string(),
+                legacy::V0User {
+                    x_id: "42".to_string(),
+                    username: "alice".to_string(),
+                    token_pair: legacy::TokenPair {
+                        token: "token".to_string(),
+                        secret: "secret".to_string(),
+                    },
+                },
+            )]),
+        };
+        let db = TempDb::new();
+        write_file(&db.path.0, None, &bincode::serialize(&tables).unwrap());
+
+        let migrated = db.reopen();
+        let user = migrated.get_active_user("-100").unwrap().unwrap();
+        assert_oauth1(&user, "42", "alice");
+        // Pending logins from before they had an owner are dropped.
+        assert!(migrated
+            .take_oauth_token("request", "-100")
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            migrated.get_chat_settings("-100").unwrap(),
+            ChatSettings::default()
+        );
+
+        // The upgraded file is saved with the current header.
+        let bytes = std::fs::read(&db.path.0).unwrap();
+        let (version, _) = migrations::decode(&bytes).unwrap();
+        assert_eq!(version, migrations::CURRENT_VERSION);
+        let user = db.reopen().get_active_user("-100").unwrap().unwrap();
+        assert_oauth1(&user, "42", "alice");
+    }
+
+    #[test]
+    fn migrates_from_version_5() {
+        let cipher = TokenCipher::new(KEY, &[]).unwrap();
+        let credentials = cipher
+            .seal(&Credentials::OAuth1(TwitterTokenPair {
+                token: "token".to_string(),
+                secret: "secret".to_string(),
+            }))
+            .unwrap();
+        let users = BTreeMap::from([(
+            "-100".to_string(),
+            legacy::V5User {
+                x_id: "42".to_string(),
+                username: "alice".to_string(),
+                credentials,
+                revoked: false,
+            },
+        )]);
+        let settings = BTreeMap::from([(
+            "-100".to_string(),
+            legacy::V6ChatSettings {
+                approvals_required: 2,
+            },
+        )]);
+        let map = TableMap::from([
+            (
+                "access_tokens".to_string(),
+                bincode::serialize(&users).unwrap(),
+            ),
+            (
+                "chat_settings".to_string(),
+                bincode::serialize(&settings).unwrap(),
+            ),
+        ]);
+        let db = TempDb::new();
+        write_file(&db.path.0, Some(5), &bincode::serialize(&map).unwrap());
+
+        let migrated = db.reopen();
+        let users = migrated.get_chat_users("-100").unwrap();
+        assert_eq!(users.len(), 1);
+        assert_oauth1(&users[0], "42", "alice");
+        assert_eq!(
+            migrated.get_active_user("-100").unwrap().unwrap().x_id,
+            "42"
+        );
+        assert_eq!(
+            migrated.get_chat_settings("-100").unwrap(),
+            ChatSettings {
+                approvals_required: 2,
+                require_alt_text: false,
+                send_delay_secs: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let db = TempDb::new();
+        write_file(
+            &db.path.0,
+            Some(migrations::CURRENT_VERSION + 1),
+            &bincode::serialize(&TableMap::new()).unwrap(),
+        );
+        let cipher = TokenCipher::new(KEY, &[]).unwrap();
+        assert!(InMemoryDB::load_or_create(&db.path.0, cipher).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+//! Layouts the bincode database file has had, and the chain of steps that
+//! upgrades any of them to the current one.
+//!
+//! A file starts with [`MAGIC`] and a little-endian `u32` version, followed by
+//! the payload. Files without the header are version 0. Since version 2 the
+//! payload is a map from table name to the bincode encoding of that table, so a
+//! new table only needs a default, not a new version; a version bump is only
+//! required when the encoding of an existing table changes.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::crypto::{SealedBox, TokenCipher};
+
+pub const MAGIC: &[u8; 4] = b"TPDB";
+pub const CURRENT_VERSION: u32 = 8;
+
+type Step = fn(&[u8], &TokenCipher) -> eyre::Result<Vec<u8>>;
+
+/// `STEPS[n]` turns a version `n` payload into a version `n + 1` payload.
+const STEPS: &[Step] = &[
+    seal_tokens,
+    split_tables,
+    drop_unowned_oauth_tokens,
+    add_auth_methods,
+    add_revoked_flag,
+    group_accounts_by_chat,
+    add_alt_text_setting,
+    add_send_delay_setting,
+];
+
+pub type TableMap = BTreeMap<String, Vec<u8>>;
+
+/// Version 0: no header, plaintext token pairs.
+mod v0 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct TokenPair {
+        pub token: String,
+        pub secret: String,
+    }
+
+    #[derive(Deserialize)]
+    pub struct User {
+        pub x_id: String,
+        pub username: String,
+        pub token_pair: TokenPair,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Tables {
+        pub oauth_tokens: BTreeMap<String, String>,
+        pub access_tokens: BTreeMap<String, User>,
+    }
+}
+
+/// Version 1: token pairs sealed with the database key.
+mod v1 {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct User {
+        pub x_id: String,
+        pub username: String,
+        pub token_pair: SealedBox,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Tables {
+        pub oauth_tokens: BTreeMap<String, String>,
+        pub access_tokens: BTreeMap<String, User>,
+    }
+}
+
+/// Version 3: pending logins remember their chat and creation time.
+mod v3 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct PendingAuth {
+        pub secret: String,
+        pub chat_id: String,
+        pub created_at: u64,
+    }
+}
+
+/// Version 4: OAuth 2.0 logins next to OAuth 1.0a ones.
+mod v4 {
+    use super::*;
+
+    #[derive(Serialize)]
+    pub enum AuthMethod {
+        OAuth1,
+    }
+
+    #[derive(Serialize)]
+    pub struct PendingAuth {
+        pub secret: String,
+        pub chat_id: String,
+        pub created_at: u64,
+        pub method: AuthMethod,
+    }
+
+    #[derive(Serialize)]
+    pub enum Credentials<'a> {
+        OAuth1(SealedTokenPair<'a>),
+    }
+}
+
+/// Version 5: accounts can be marked as revoked.
+mod v5 {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct User {
+        pub x_id: String,
+        pub username: String,
+        pub credentials: SealedBox,
+        pub revoked: bool,
+    }
+}
+
+/// Version 6: several accounts per chat, one of them active. Chat settings
+/// only hold the number of approvals.
+mod v6 {
+    use super::*;
+
+    #[derive(Serialize)]
+    pub struct ChatAccounts {
+        pub active: Option<String>,
+        pub users: BTreeMap<String, v5::User>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ChatSettings {
+        pub approvals_required: u32,
+    }
+}
+
+/// Version 7: chats can require alt text on images.
+mod v7 {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ChatSettings {
+        pub approvals_required: u32,
+        pub require_alt_text: bool,
+    }
+}
+
+/// Version 8: chats can hold posts back for a send delay.
+mod v8 {
+    use super::*;
+
+    #[derive(Serialize)]
+    pub struct ChatSettings {
+        pub approvals_required: u32,
+        pub require_alt_text: bool,
+        pub send_delay_secs: u32,
+    }
+}
+
+#[derive(Serialize)]
+struct SealedTokenPair<'a> {
+    token: &'a str,
+    secret: &'a str,
+}
+
+fn seal_tokens(payload: &[u8], cipher: &TokenCipher) -> eyre::Result<Vec<u8>> {
+    let tables: v0::Tables = bincode::deserialize(payload)?;
+    let access_tokens = tables
+        .access_tokens
+        .into_iter()
+        .map(|(chat_id, user)| {
+            let token_pair = cipher.seal(&SealedTokenPair {
+                token: &user.token_pair.token,
+                secret: &user.token_pair.secret,
+            })?;
+            let user = v1::User {
+                x_id: user.x_id,
+                username: user.username,
+                token_pair,
+            };
+            Ok((chat_id, user))
+        })
+        .collect::<eyre::Result<_>>()?;
+    Ok(bincode::serialize(&v1::Tables {
+        oauth_tokens: tables.oauth_tokens,
+        access_tokens,
+    })?)
+}
+
+fn split_tables(payload: &[u8], _: &TokenCipher) -> eyre::Result<Vec<u8>> {
+    let tables: v1::Tables = bincode::deserialize(payload)?;
+    let mut map = TableMap::new();
+    map.insert(
+        "oauth_tokens".to_string(),
+        bincode::serialize(&tables.oauth_tokens)?,
+    );
+    map.insert(
+        "access_tokens".to_string(),
+        bincode::serialize(&tables.access_tokens)?,
+    );
+    Ok(bincode::serialize(&map)?)
+}
+
+/// Pending request tokens gained an owning chat and a creation time. Tokens
+/// issued before that cannot be attributed to a chat, so they are dropped and
+/// the affected users simply run `/auth` again.
+fn drop_unowned_oauth_tokens(payload: &[u8], _: &TokenCipher) -> eyre::Result<Vec<u8>> {
+    let mut map: TableMap = bincode::deserialize(payload)?;
+    if let Some(bytes) = map.remove("oauth_tokens") {
+        let tokens: BTreeMap<String, String> = bincode::deserialize(&bytes)?;
+        log::info!("Dropped {} pending logins without an owner", tokens.len());
+    }
+    Ok(bincode::serialize(&map)?)
+}
+
+/// Tags existing pending logins and sealed token pairs as OAuth 1.0a.
+fn add_auth_methods(payload: &[u8], cipher: &TokenCipher) -> eyre::Result<Vec<u8>> {
+    let mut map: TableMap = bincode::deserialize(payload)?;
+    if let Some(bytes) = map.get_mut("oauth_tokens") {
+        let tokens: BTreeMap<String, v3::PendingAuth> = bincode::deserialize(bytes)?;
+        let tokens: BTreeMap<String, v4::PendingAuth> = tokens
+            .into_iter()
+            .map(|(token, p)| {
+                let pending = v4::PendingAuth {
+                    secret: p.secret,
+                    chat_id: p.chat_id,
+                    created_at: p.created_at,
+                    method: v4::AuthMethod::OAuth1,
+                };
+                (token, pending)
+            })
+            .collect();
+        *bytes = bincode::serialize(&tokens)?;
+    }
+    if let Some(bytes) = map.get_mut("access_tokens") {
+        let mut users: BTreeMap<String, v1::User> = bincode::deserialize(bytes)?;
+        for user in users.values_mut() {
+            let token_pair: v0::TokenPair = cipher.open(&user.token_pair)?;
+            user.token_pair = cipher.seal(&v4::Credentials::OAuth1(SealedTokenPair {
+                token: &token_pair.token,
+                secret: &token_pair.secret,
+            }))?;
+        }
+        *bytes = bincode::serialize(&users)?;
+    }
+    Ok(bincode::serialize(&map)?)
+}
+
+fn add_revoked_flag(payload: &[u8], _: &TokenCipher) -> eyre::Result<Vec<u8>> {
+    let mut map: TableMap = bincode::deserialize(payload)?;
+    if let Some(bytes) = map.get_mut("access_tokens") {
+        let users: BTreeMap<String, v1::User> = bincode::deserialize(bytes)?;
+        let users: BTreeMap<String, v5::User> = users
+            .into_iter()
+            .map(|(chat_id, user)| {
+                let user = v5::User {
+                    x_id: user.x_id,
+                    username: user.username,
+                    credentials: user.token_pair,
+                    revoked: false,
+                };
+                (chat_id, user)
+            })
+            .collect();
+        *bytes = bincode::serialize(&users)?;
+    }
+    Ok(bincode::serialize(&map)?)
+}
+
+fn group_accounts_by_chat(payload: &[u8], _: &TokenCipher) -> eyre::Result<Vec<u8>> {
+    let mut map: TableMap = bincode::deserialize(payload)?;
+    if let Some(bytes) = map.get_mut("access_tokens") {
+        let users: BTreeMap<String, v5::User> = bincode::deserialize(bytes)?;
+        let accounts: BTreeMap<String, v6::ChatAccounts> = users
+            .into_iter()
+            .map(|(chat_id, user)| {
+                let accounts = v6::ChatAccounts {
+                    active: Some(user.x_id.clone()),
+                    users: BTreeMap::from([(user.x_id.clone(), user)]),
+                };
+                (chat_id, accounts)
+            })
+            .collect();
+        *bytes = bincode::serialize(&accounts)?;
+    }
+    Ok(bincode::serialize(&map)?)
+}
+
+fn add_alt_text_setting(payload: &[u8], _: &TokenCipher) -> eyre::Result<Vec<u8>> {
+    let mut map: TableMap = bincode::deserialize(payload)?;
+    if let Some(bytes) = map.get_mut("chat_settings") {
+        let settings: BTreeMap<String, v6::ChatSettings> = bincode::deserialize(bytes)?;
+        let settings: BTreeMap<String, v7::ChatSettings> = settings
+            .into_iter()
+            .map(|(chat_id, s)| {
+                let settings = v7::ChatSettings {
+                    approvals_required: s.approvals_required,
+                    require_alt_text: false,
+                };
+                (chat_id, settings)
+            })
+            .collect();
+        *bytes = bincode::serialize(&settings)?;
+    }
+    Ok(bincode::serialize(&map)?)
+}
+
+fn add_send_delay_setting(payload: &[u8], _: &TokenCipher) -> eyre::Result<Vec<u8>> {
+    let mut map: TableMap = bincode::deserialize(payload)?;
+    if let Some(bytes) = map.get_mut("chat_settings") {
+        let settings: BTreeMap<String, v7::ChatSettings> = bincode::deserialize(bytes)?;
+        let settings: BTreeMap<String, v8::ChatSettings> = settings
+            .into_iter()
+            .map(|(chat_id, s)| {
+                let settings = v8::ChatSettings {
+                    approvals_required: s.approvals_required,
+                    require_alt_text: s.require_alt_text,
+                    send_delay_secs: 0,
+                };
+                (chat_id, settings)
+            })
+            .collect();
+        *bytes = bincode::serialize(&settings)?;
+    }
+    Ok(bincode::serialize(&map)?)
+}
+
+/// Splits a database file into its version and payload.
+pub fn decode(bytes: &[u8]) -> eyre::Result<(u32, &[u8])> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Ok((0, bytes));
+    };
+    if rest.len() < 4 {
+        eyre::bail!("Database header is truncated");
+    }
+    let (version, payload) = rest.split_at(4);
+    let version = u32::from_le_bytes(version.try_into()?);
+    Ok((version, payload))
+}
+
+pub fn encode(payload: &[u8]) -> Vec<u8> {
+    [MAGIC.as_slice(), &CURRENT_VERSION.to_le_bytes(), payload].concat()
+}
+
+/// Runs every step needed to bring a `version` payload up to
+/// [`CURRENT_VERSION`].
+pub fn upgrade(version: u32, payload: &[u8], cipher: &TokenCipher) -> eyre::Result<Vec<u8>> {
+    if version > CURRENT_VERSION {
+        eyre::bail!(
+            "Database version {} is newer than supported version {}",
+            version,
+            CURRENT_VERSION
+        );
+    }
+    let mut payload = payload.to_vec();
+    for (from, step) in STEPS.iter().enumerate().skip(version as usize) {
+        payload = step(&payload, cipher)
+            .map_err(|e| e.wrap_err(format!("Failed to migrate database from version {}", from)))?;
+        log::info!("Migrated database from version {} to {}", from, from + 1);
+    }
+    Ok(payload)
+}
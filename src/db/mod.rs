@@ -0,0 +1,521 @@
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::twitter::{auth::Credentials, tweet::Tweet};
+
+pub mod crypto;
+pub mod memory;
+mod migrations;
+pub mod sqlite;
+#[cfg(test)]
+mod storage_tests;
+
+pub use crypto::TokenCipher;
+pub use memory::InMemoryDB;
+pub use sqlite::SqliteDB;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub x_id: String,
+    pub username: String,
+    pub credentials: Credentials,
+    /// Set once Twitter rejected the credentials. The account stays linked so
+    /// the chat can be told, but nothing is sent with it anymore.
+    pub revoked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    OAuth1,
+    OAuth2,
+}
+
+impl AuthMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::OAuth1 => "oauth1",
+            Self::OAuth2 => "oauth2",
+        }
+    }
+}
+
+impl FromStr for AuthMethod {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oauth1" => Ok(Self::OAuth1),
+            "oauth2" => Ok(Self::OAuth2),
+            _ => eyre::bail!("Unknown auth method: {}", s),
+        }
+    }
+}
+
+/// What a member of a chat may do with the chat's accounts. Each role can do
+/// everything the roles before it can.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Can see which accounts are linked and read poll results.
+    Viewer,
+    /// Can like and retweet.
+    Reacter,
+    /// Can tweet, reply and quote.
+    Poster,
+    /// Can link and unlink accounts and manage roles.
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Reacter => "reacter",
+            Self::Poster => "poster",
+            Self::Owner => "owner",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "viewer" => Ok(Self::Viewer),
+            "reacter" => Ok(Self::Reacter),
+            "poster" => Ok(Self::Poster),
+            "owner" => Ok(Self::Owner),
+            _ => eyre::bail!("Unknown role: {}", s),
+        }
+    }
+}
+
+/// Something a chat can do with an account, as restricted by delegations.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Tweet,
+    Reply,
+    Quote,
+    Like,
+    Retweet,
+    /// Deleting any tweet of the account, not only those the delegate posted.
+    Delete,
+}
+
+impl Action {
+    pub const ALL: &'static [Action] = &[
+        Self::Tweet,
+        Self::Reply,
+        Self::Quote,
+        Self::Like,
+        Self::Retweet,
+        Self::Delete,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tweet => "tweet",
+            Self::Reply => "reply",
+            Self::Quote => "quote",
+            Self::Like => "like",
+            Self::Retweet => "retweet",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|action| action.as_str() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| eyre::eyre!("Unknown action: {}", s))
+    }
+}
+
+/// Access to an account of `owner_chat_id` granted to another chat through an
+/// invite code.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Delegation {
+    pub owner_chat_id: String,
+    pub x_id: String,
+    /// The chat that redeemed the code, if any. A code can only be redeemed
+    /// once.
+    pub delegate_chat_id: Option<String>,
+    pub actions: Vec<Action>,
+    /// Unix timestamp in seconds.
+    pub expires_at: u64,
+    pub max_actions: Option<u32>,
+    pub actions_used: u32,
+}
+
+impl Delegation {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= unix_now()
+    }
+
+    /// Whether `count` more actions fit in `max_actions`.
+    pub fn has_actions_left(&self, count: u32) -> bool {
+        self.max_actions
+            .is_none_or(|max| self.actions_used.saturating_add(count) <= max)
+    }
+}
+
+/// A token bucket holding up to `capacity` tokens that refills completely
+/// over `period_secs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub period_secs: u64,
+}
+
+/// The persisted fill level of one bucket.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    /// Unix timestamp in seconds.
+    pub updated_at: u64,
+}
+
+/// `cost` tokens to take from the bucket stored under `key`.
+#[derive(Debug, Clone)]
+pub struct BucketDraw {
+    pub key: String,
+    pub limit: RateLimit,
+    pub cost: u32,
+}
+
+impl BucketDraw {
+    /// Costs above the capacity are capped so they pass once the bucket is
+    /// full.
+    fn cost(&self) -> f64 {
+        self.cost.min(self.limit.capacity) as f64
+    }
+
+    /// The tokens in `bucket` at `now`; a bucket that was never drawn from is
+    /// full.
+    fn available(&self, bucket: Option<Bucket>, now: u64) -> f64 {
+        let capacity = self.limit.capacity as f64;
+        let Some(bucket) = bucket else {
+            return capacity;
+        };
+        let rate = capacity / self.limit.period_secs.max(1) as f64;
+        let elapsed = now.saturating_sub(bucket.updated_at) as f64;
+        (bucket.tokens + elapsed * rate).min(capacity)
+    }
+
+    /// Seconds until the bucket refills from `tokens` to this draw's cost.
+    fn wait_secs(&self, tokens: f64) -> u64 {
+        let rate = self.limit.capacity as f64 / self.limit.period_secs.max(1) as f64;
+        ((self.cost() - tokens) / rate).ceil().max(1.0) as u64
+    }
+}
+
+/// Takes every draw from its bucket in `buckets`, in the same order, or none
+/// of them if any bucket is short. Returns the buckets to store, or how many
+/// seconds to wait until all draws would succeed.
+pub fn draw_buckets(
+    draws: &[BucketDraw],
+    buckets: &[Option<Bucket>],
+    now: u64,
+) -> Result<Vec<Bucket>, u64> {
+    let available: Vec<f64> = draws
+        .iter()
+        .zip(buckets)
+        .map(|(draw, bucket)| draw.available(*bucket, now))
+        .collect();
+    let wait_secs = draws
+        .iter()
+        .zip(&available)
+        .filter(|(draw, tokens)| **tokens < draw.cost())
+        .map(|(draw, tokens)| draw.wait_secs(*tokens))
+        .max();
+    if let Some(wait_secs) = wait_secs {
+        return Err(wait_secs);
+    }
+    Ok(draws
+        .iter()
+        .zip(available)
+        .map(|(draw, tokens)| Bucket {
+            tokens: tokens - draw.cost(),
+            updated_at: now,
+        })
+        .collect())
+}
+
+/// Per chat configuration, changed by the chat's owners.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ChatSettings {
+    /// How many members other than the author must approve a tweet, reply or
+    /// quote before it is sent. Zero sends posts right away.
+    pub approvals_required: u32,
+    /// Refuse to post images and GIFs without alt text.
+    pub require_alt_text: bool,
+    /// How long a tweet, reply or quote waits, with a chance to cancel it,
+    /// before it is sent. Zero sends posts right away.
+    pub send_delay_secs: u32,
+}
+
+/// A post held back until enough members of its chat approve it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingPost {
+    pub chat_id: String,
+    /// The chat the account is linked to, which is not `chat_id` when the post
+    /// was written through a delegation.
+    pub account_chat_id: String,
+    /// Telegram user id of the member who wrote the post, unless they posted
+    /// anonymously.
+    pub author_id: Option<u64>,
+    /// The linked account the post is sent as.
+    pub x_id: String,
+    /// Ready to send, with any media already uploaded.
+    pub tweet: Tweet,
+    /// Telegram user ids of the members who approved so far.
+    pub approvals: Vec<u64>,
+    pub approvals_required: u32,
+    /// Unix timestamp in seconds.
+    pub created_at: u64,
+}
+
+/// A tweet held back for its chat's send delay, during which it can still be
+/// cancelled.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScheduledSend {
+    pub chat_id: String,
+    /// The chat the account is linked to, see [`PendingPost::account_chat_id`].
+    pub account_chat_id: String,
+    pub author_id: Option<u64>,
+    pub x_id: String,
+    pub tweet: Tweet,
+    /// Unix timestamp in seconds.
+    pub send_at: u64,
+    /// The countdown message in `chat_id`.
+    pub message_id: i32,
+}
+
+/// A login started by `/auth` that has not been completed yet. For OAuth 1.0a
+/// it is keyed by the request token and `secret` is the request token secret;
+/// for OAuth 2.0 it is keyed by the callback state and `secret` is the PKCE
+/// code verifier.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PendingAuth {
+    pub secret: String,
+    pub chat_id: String,
+    /// Unix timestamp in seconds.
+    pub created_at: u64,
+    pub method: AuthMethod,
+}
+
+impl PendingAuth {
+    pub fn new(method: AuthMethod, secret: String, chat_id: String) -> Self {
+        Self {
+            secret,
+            chat_id,
+            created_at: unix_now(),
+            method,
+        }
+    }
+
+    pub fn is_expired(&self, ttl_secs: u64) -> bool {
+        self.created_at + ttl_secs <= unix_now()
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Persistent state of the bot. Every mutating call must be durable by the
+/// time it returns, so a crash never loses an authenticated account. Account
+/// credentials are sealed with the backend's [`TokenCipher`] before they are
+/// written.
+pub trait Storage: Send + Sync {
+    fn insert_oauth_token(&self, token: String, pending: PendingAuth) -> eyre::Result<()>;
+    /// Removes the pending request token and returns it, unless it was
+    /// started by another chat than `chat_id`, in which case it is left alone.
+    fn take_oauth_token(&self, token: &str, chat_id: &str) -> eyre::Result<Option<PendingAuth>>;
+    /// Removes the most recent pending login of `method` started by `chat_id`
+    /// and returns it along with its key.
+    fn take_latest_oauth_token(
+        &self,
+        chat_id: &str,
+        method: AuthMethod,
+    ) -> eyre::Result<Option<(String, PendingAuth)>>;
+    /// Removes every pending request token created before `created_before`,
+    /// returning how many were removed.
+    fn purge_oauth_tokens(&self, created_before: u64) -> eyre::Result<usize>;
+
+    /// The account commands in `chat_id` use unless they name another one.
+    fn get_active_user(&self, chat_id: &str) -> eyre::Result<Option<User>>;
+    /// Every account linked to `chat_id`.
+    fn get_chat_users(&self, chat_id: &str) -> eyre::Result<Vec<User>>;
+    /// Makes the linked account `x_id` the active one, returning `false` if
+    /// the chat has no such account.
+    fn set_active_user(&self, chat_id: &str, x_id: &str) -> eyre::Result<bool>;
+    /// Links `user` to the chat, replacing an earlier link of the same
+    /// account. The first account linked to a chat becomes its active one.
+    fn insert_user(&self, chat_id: String, user: User) -> eyre::Result<()>;
+    /// Unlinks an account. If it was the active one, another linked account,
+    /// if any, takes its place.
+    fn remove_user(&self, chat_id: &str, x_id: &str) -> eyre::Result<Option<User>>;
+    /// Every linked account with the chat it belongs to.
+    fn list_users(&self) -> eyre::Result<Vec<(String, User)>>;
+
+    /// The role explicitly granted to Telegram user `user_id` in `chat_id`.
+    fn get_role(&self, chat_id: &str, user_id: u64) -> eyre::Result<Option<Role>>;
+    fn set_role(&self, chat_id: &str, user_id: u64, role: Role) -> eyre::Result<()>;
+    /// Removes an explicitly granted role, returning whether there was one.
+    fn remove_role(&self, chat_id: &str, user_id: u64) -> eyre::Result<bool>;
+    fn list_roles(&self, chat_id: &str) -> eyre::Result<Vec<(u64, Role)>>;
+
+    fn get_chat_settings(&self, chat_id: &str) -> eyre::Result<ChatSettings>;
+    fn set_chat_settings(&self, chat_id: &str, settings: ChatSettings) -> eyre::Result<()>;
+
+    fn insert_pending_post(&self, id: String, post: PendingPost) -> eyre::Result<()>;
+    fn get_pending_post(&self, id: &str) -> eyre::Result<Option<PendingPost>>;
+    /// Records `user_id`'s approval of a pending post and returns the updated
+    /// post, or `None` if it is no longer pending.
+    fn approve_pending_post(&self, id: &str, user_id: u64) -> eyre::Result<Option<PendingPost>>;
+    /// Removes a pending post and returns it, so only one caller ever gets to
+    /// send or reject it.
+    fn take_pending_post(&self, id: &str) -> eyre::Result<Option<PendingPost>>;
+    /// Removes and returns every pending post created before `created_before`.
+    fn take_expired_pending_posts(
+        &self,
+        created_before: u64,
+    ) -> eyre::Result<Vec<(String, PendingPost)>>;
+
+    fn insert_scheduled_send(&self, id: String, send: ScheduledSend) -> eyre::Result<()>;
+    fn get_scheduled_send(&self, id: &str) -> eyre::Result<Option<ScheduledSend>>;
+    /// Removes a scheduled send and returns it, so only one caller ever gets
+    /// to post or cancel it.
+    fn take_scheduled_send(&self, id: &str) -> eyre::Result<Option<ScheduledSend>>;
+    fn list_scheduled_sends(&self) -> eyre::Result<Vec<(String, ScheduledSend)>>;
+
+    fn insert_delegation(&self, code: String, delegation: Delegation) -> eyre::Result<()>;
+    fn get_delegation(&self, code: &str) -> eyre::Result<Option<Delegation>>;
+    /// Binds an unredeemed, unexpired delegation to `chat_id` and returns it.
+    fn redeem_delegation(&self, code: &str, chat_id: &str) -> eyre::Result<Option<Delegation>>;
+    /// Counts `count` actions against a delegation, returning `None` without
+    /// counting any if it expired or has fewer actions left.
+    fn consume_delegation(&self, code: &str, count: u32) -> eyre::Result<Option<Delegation>>;
+    fn remove_delegation(&self, code: &str) -> eyre::Result<Option<Delegation>>;
+    /// Delegations issued by or redeemed in `chat_id`.
+    fn list_delegations(&self, chat_id: &str) -> eyre::Result<Vec<(String, Delegation)>>;
+
+    /// Draws from every rate limit bucket at once, see [`draw_buckets`].
+    /// Returns `None` once the tokens are taken, or the seconds to wait
+    /// without taking any.
+    fn draw_rate_limits(&self, draws: &[BucketDraw]) -> eyre::Result<Option<u64>>;
+
+    /// Re-seals every stored credential with the cipher's current key, returning the
+    /// number of records rewritten.
+    fn reencrypt_tokens(&self) -> eyre::Result<usize>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    Memory,
+    Sqlite,
+}
+
+impl FromStr for StorageBackend {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "memory" | "bincode" => Ok(Self::Memory),
+            "sqlite" => Ok(Self::Sqlite),
+            _ => eyre::bail!("Unknown storage backend: {}", s),
+        }
+    }
+}
+
+pub fn open(
+    backend: StorageBackend,
+    path: &str,
+    cipher: TokenCipher,
+) -> eyre::Result<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match backend {
+        StorageBackend::Memory => Arc::new(InMemoryDB::load_or_create(path, cipher)?),
+        StorageBackend::Sqlite => Arc::new(SqliteDB::open(path, cipher)?),
+    };
+    log::info!("Using {:?} storage at {}", backend, path);
+    Ok(storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(key: &str, capacity: u32, period_secs: u64, cost: u32) -> BucketDraw {
+        BucketDraw {
+            key: key.to_string(),
+            limit: RateLimit {
+                capacity,
+                period_secs,
+            },
+            cost,
+        }
+    }
+
+    fn bucket(tokens: f64, updated_at: u64) -> Option<Bucket> {
+        Some(Bucket { tokens, updated_at })
+    }
+
+    #[test]
+    fn draws_from_full_buckets() {
+        let draws = [draw("a", 10, 100, 3)];
+        let buckets = draw_buckets(&draws, &[None], 1000).unwrap();
+        assert_eq!(
+            buckets,
+            [Bucket {
+                tokens: 7.0,
+                updated_at: 1000
+            }]
+        );
+    }
+
+    #[test]
+    fn refills_over_the_period() {
+        // One token every 10 seconds.
+        let draws = [draw("a", 10, 100, 1)];
+        assert_eq!(draw_buckets(&draws, &[bucket(0.0, 1000)], 1004), Err(6));
+        let buckets = draw_buckets(&draws, &[bucket(0.0, 1000)], 1010).unwrap();
+        assert_eq!(buckets[0].tokens, 0.0);
+        // Refilling stops at the capacity.
+        let buckets = draw_buckets(&draws, &[bucket(5.0, 0)], 1000).unwrap();
+        assert_eq!(buckets[0].tokens, 9.0);
+    }
+
+    #[test]
+    fn takes_all_draws_or_none() {
+        let draws = [draw("a", 10, 100, 1), draw("b", 2, 60, 2)];
+        let buckets = [None, bucket(1.0, 1000)];
+        // The second bucket is a token short, which takes 30 seconds.
+        assert_eq!(draw_buckets(&draws, &buckets, 1000), Err(30));
+        let buckets = draw_buckets(&draws, &buckets, 1030).unwrap();
+        assert_eq!(
+            buckets.iter().map(|b| b.tokens).collect::<Vec<_>>(),
+            [9.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn caps_costs_at_the_capacity() {
+        let draws = [draw("a", 2, 60, 5)];
+        assert_eq!(draw_buckets(&draws, &[bucket(1.0, 0)], 0), Err(30));
+        assert_eq!(draw_buckets(&draws, &[None], 0).unwrap()[0].tokens, 0.0);
+    }
+}
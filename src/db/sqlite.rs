@@ -0,0 +1,1042 @@
+use std::sync::Mutex;
+
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, OptionalExtension, Row, ToSql, Transaction,
+};
+
+use super::{
+    crypto::{SealedBox, TokenCipher},
+    draw_buckets, unix_now, Action, AuthMethod, Bucket, BucketDraw, ChatSettings, Delegation,
+    PendingAuth, PendingPost, Role, ScheduledSend, Storage, User,
+};
+use crate::twitter::auth::{Credentials, TwitterTokenPair};
+
+enum Migration {
+    Sql(&'static str),
+    Code(fn(&Transaction, &TokenCipher) -> eyre::Result<()>),
+}
+
+/// Schema migrations, applied in order. The index of the last applied entry
+/// is tracked in `PRAGMA user_version`.
+const MIGRATIONS: &[Migration] = &[
+    Migration::Sql(
+        "CREATE TABLE oauth_tokens (
+            token TEXT PRIMARY KEY NOT NULL,
+            secret TEXT NOT NULL
+        );
+        CREATE TABLE users (
+            chat_id TEXT PRIMARY KEY NOT NULL,
+            x_id TEXT NOT NULL,
+            username TEXT NOT NULL,
+            token TEXT NOT NULL,
+            secret TEXT NOT NULL
+        );",
+    ),
+    Migration::Code(seal_plaintext_tokens),
+    // Request tokens issued before this migration have no owning chat and
+    // are dropped.
+    Migration::Sql(
+        "DROP TABLE oauth_tokens;
+        CREATE TABLE oauth_tokens (
+            token TEXT PRIMARY KEY NOT NULL,
+            secret TEXT NOT NULL,
+            chat_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX oauth_tokens_created_at ON oauth_tokens (created_at);",
+    ),
+    Migration::Code(add_auth_methods),
+    Migration::Sql("ALTER TABLE users ADD COLUMN revoked INTEGER NOT NULL DEFAULT 0;"),
+    // Several accounts per chat; every existing account becomes its chat's
+    // active one.
+    Migration::Sql(
+        "CREATE TABLE linked_accounts (
+            chat_id TEXT NOT NULL,
+            x_id TEXT NOT NULL,
+            username TEXT NOT NULL,
+            tokens BLOB NOT NULL,
+            revoked INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (chat_id, x_id)
+        );
+        INSERT INTO linked_accounts (chat_id, x_id, username, tokens, revoked)
+            SELECT chat_id, x_id, username, tokens, revoked FROM users;
+        DROP TABLE users;
+        ALTER TABLE linked_accounts RENAME TO users;
+        CREATE TABLE active_accounts (
+            chat_id TEXT PRIMARY KEY NOT NULL,
+            x_id TEXT NOT NULL
+        );
+        INSERT INTO active_accounts (chat_id, x_id) SELECT chat_id, x_id FROM users;",
+    ),
+    Migration::Sql(
+        "CREATE TABLE roles (
+            chat_id TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            role TEXT NOT NULL,
+            PRIMARY KEY (chat_id, user_id)
+        );",
+    ),
+    Migration::Sql(
+        "CREATE TABLE chat_settings (
+            chat_id TEXT PRIMARY KEY NOT NULL,
+            approvals_required INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE pending_posts (
+            id TEXT PRIMARY KEY NOT NULL,
+            chat_id TEXT NOT NULL,
+            author_id INTEGER,
+            x_id TEXT NOT NULL,
+            tweet TEXT NOT NULL,
+            approvals_required INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX pending_posts_created_at ON pending_posts (created_at);
+        CREATE TABLE post_approvals (
+            post_id TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            PRIMARY KEY (post_id, user_id)
+        );",
+    ),
+    Migration::Sql(
+        "ALTER TABLE pending_posts ADD COLUMN account_chat_id TEXT NOT NULL DEFAULT '';
+        UPDATE pending_posts SET account_chat_id = chat_id;
+        CREATE TABLE delegations (
+            code TEXT PRIMARY KEY NOT NULL,
+            owner_chat_id TEXT NOT NULL,
+            x_id TEXT NOT NULL,
+            delegate_chat_id TEXT,
+            actions TEXT NOT NULL,
+            expires_at INTEGER NOT NULL,
+            max_actions INTEGER,
+            actions_used INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX delegations_owner_chat_id ON delegations (owner_chat_id);
+        CREATE INDEX delegations_delegate_chat_id ON delegations (delegate_chat_id);",
+    ),
+    Migration::Sql(
+        "CREATE TABLE rate_limits (
+            key TEXT PRIMARY KEY NOT NULL,
+            tokens REAL NOT NULL,
+            updated_at INTEGER NOT NULL
+        );",
+    ),
+    Migration::Sql(
+        "ALTER TABLE chat_settings ADD COLUMN require_alt_text INTEGER NOT NULL DEFAULT 0;",
+    ),
+    Migration::Sql(
+        "ALTER TABLE chat_settings ADD COLUMN send_delay_secs INTEGER NOT NULL DEFAULT 0;
+        CREATE TABLE scheduled_sends (
+            id TEXT PRIMARY KEY NOT NULL,
+            chat_id TEXT NOT NULL,
+            account_chat_id TEXT NOT NULL,
+            author_id INTEGER,
+            x_id TEXT NOT NULL,
+            tweet TEXT NOT NULL,
+            send_at INTEGER NOT NULL,
+            message_id INTEGER NOT NULL
+        );",
+    ),
+];
+
+/// Replaces the plaintext `token`/`secret` columns with a single sealed blob.
+fn seal_plaintext_tokens(tx: &Transaction, cipher: &TokenCipher) -> eyre::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE users_sealed (
+            chat_id TEXT PRIMARY KEY NOT NULL,
+            x_id TEXT NOT NULL,
+            username TEXT NOT NULL,
+            tokens BLOB NOT NULL
+        );",
+    )?;
+    let mut stmt = tx.prepare("SELECT chat_id, x_id, username, token, secret FROM users")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            TwitterTokenPair {
+                token: row.get(3)?,
+                secret: row.get(4)?,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (chat_id, x_id, username, token_pair) = row?;
+        tx.execute(
+            "INSERT INTO users_sealed (chat_id, x_id, username, tokens) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                chat_id,
+                x_id,
+                username,
+                cipher.seal(&token_pair)?.to_bytes()
+            ],
+        )?;
+    }
+    tx.execute_batch("DROP TABLE users; ALTER TABLE users_sealed RENAME TO users;")?;
+    Ok(())
+}
+
+/// Tags existing pending logins and sealed token pairs as OAuth 1.0a.
+fn add_auth_methods(tx: &Transaction, cipher: &TokenCipher) -> eyre::Result<()> {
+    tx.execute_batch("ALTER TABLE oauth_tokens ADD COLUMN method TEXT NOT NULL DEFAULT 'oauth1';")?;
+    let users = {
+        let mut stmt = tx.prepare("SELECT chat_id, tokens FROM users")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+    for (chat_id, tokens) in users {
+        let token_pair: TwitterTokenPair = cipher.open(&SealedBox::from_bytes(&tokens)?)?;
+        let credentials = cipher.seal(&Credentials::OAuth1(token_pair))?;
+        tx.execute(
+            "UPDATE users SET tokens = ?1 WHERE chat_id = ?2",
+            params![credentials.to_bytes(), chat_id],
+        )?;
+    }
+    Ok(())
+}
+
+impl ToSql for AuthMethod {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for AuthMethod {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e: eyre::Report| FromSqlError::Other(e.into()))
+    }
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Role {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e: eyre::Report| FromSqlError::Other(e.into()))
+    }
+}
+
+/// Reads the `secret, chat_id, created_at, method` columns starting at `first`.
+fn pending_from_row(row: &Row, first: usize) -> rusqlite::Result<PendingAuth> {
+    Ok(PendingAuth {
+        secret: row.get(first)?,
+        chat_id: row.get(first + 1)?,
+        created_at: row.get(first + 2)?,
+        method: row.get(first + 3)?,
+    })
+}
+
+#[derive(Debug)]
+pub struct SqliteDB {
+    conn: Mutex<Connection>,
+    cipher: TokenCipher,
+}
+
+impl SqliteDB {
+    pub fn open(path: &str, cipher: TokenCipher) -> eyre::Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "FULL")?;
+        Self::migrate(&mut conn, path, &cipher)?;
+        let db = Self {
+            conn: Mutex::new(conn),
+            cipher,
+        };
+        // Refuse to start with the wrong key rather than failing on first use.
+        db.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT tokens FROM users")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let sealed = SealedBox::from_bytes(&row.get::<_, Vec<u8>>(0)?)?;
+                db.cipher.open::<Credentials>(&sealed)?;
+            }
+            Ok(())
+        })?;
+        Ok(db)
+    }
+
+    fn migrate(conn: &mut Connection, path: &str, cipher: &TokenCipher) -> eyre::Result<()> {
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > MIGRATIONS.len() {
+            eyre::bail!(
+                "Database schema version {} is newer than supported version {}",
+                version,
+                MIGRATIONS.len()
+            );
+        }
+        if version > 0 && version < MIGRATIONS.len() {
+            let backup_path = format!("{}.v{}.bak", path, version);
+            let _ = std::fs::remove_file(&backup_path);
+            conn.execute("VACUUM INTO ?1", params![backup_path])?;
+            log::info!(
+                "Backed up version {} database to {} before migrating",
+                version,
+                backup_path
+            );
+        }
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            match migration {
+                Migration::Sql(sql) => tx.execute_batch(sql)?,
+                Migration::Code(f) => f(&tx, cipher)?,
+            }
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+            log::info!("Applied database migration {}", i + 1);
+        }
+        Ok(())
+    }
+
+    fn with_conn<T>(&self, f: impl FnOnce(&mut Connection) -> eyre::Result<T>) -> eyre::Result<T> {
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|_| eyre::eyre!("Database lock poisoned"))?;
+        f(&mut conn)
+    }
+}
+
+impl Storage for SqliteDB {
+    fn insert_oauth_token(&self, token: String, pending: PendingAuth) -> eyre::Result<()> {
+        self.with_conn(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO oauth_tokens (token, secret, chat_id, created_at, method)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    token,
+                    pending.secret,
+                    pending.chat_id,
+                    pending.created_at,
+                    pending.method
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn take_oauth_token(&self, token: &str, chat_id: &str) -> eyre::Result<Option<PendingAuth>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let pending = tx
+                .query_row(
+                    "SELECT secret, chat_id, created_at, method FROM oauth_tokens
+                     WHERE token = ?1 AND chat_id = ?2",
+                    params![token, chat_id],
+                    |row| pending_from_row(row, 0),
+                )
+                .optional()?;
+            tx.execute(
+                "DELETE FROM oauth_tokens WHERE token = ?1 AND chat_id = ?2",
+                params![token, chat_id],
+            )?;
+            tx.commit()?;
+            Ok(pending)
+        })
+    }
+
+    fn take_latest_oauth_token(
+        &self,
+        chat_id: &str,
+        method: AuthMethod,
+    ) -> eyre::Result<Option<(String, PendingAuth)>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let pending = tx
+                .query_row(
+                    "SELECT token, secret, chat_id, created_at, method FROM oauth_tokens
+                     WHERE chat_id = ?1 AND method = ?2 ORDER BY created_at DESC LIMIT 1",
+                    params![chat_id, method],
+                    |row| Ok((row.get::<_, String>(0)?, pending_from_row(row, 1)?)),
+                )
+                .optional()?;
+            if let Some((token, _)) = &pending {
+                tx.execute("DELETE FROM oauth_tokens WHERE token = ?1", params![token])?;
+            }
+            tx.commit()?;
+            Ok(pending)
+        })
+    }
+
+    fn purge_oauth_tokens(&self, created_before: u64) -> eyre::Result<usize> {
+        self.with_conn(|conn| {
+            let purged = conn.execute(
+                "DELETE FROM oauth_tokens WHERE created_at < ?1",
+                params![created_before],
+            )?;
+            Ok(purged)
+        })
+    }
+
+    fn get_active_user(&self, chat_id: &str) -> eyre::Result<Option<User>> {
+        self.with_conn(|conn| {
+            let users = query_users(
+                conn,
+                &self.cipher,
+                "SELECT u.x_id, u.username, u.tokens, u.revoked FROM users u
+                 JOIN active_accounts a ON a.chat_id = u.chat_id AND a.x_id = u.x_id
+                 WHERE u.chat_id = ?1",
+                params![chat_id],
+            )?;
+            Ok(users.into_iter().next())
+        })
+    }
+
+    fn get_chat_users(&self, chat_id: &str) -> eyre::Result<Vec<User>> {
+        self.with_conn(|conn| {
+            query_users(
+                conn,
+                &self.cipher,
+                "SELECT x_id, username, tokens, revoked FROM users
+                 WHERE chat_id = ?1 ORDER BY x_id",
+                params![chat_id],
+            )
+        })
+    }
+
+    fn set_active_user(&self, chat_id: &str, x_id: &str) -> eyre::Result<bool> {
+        self.with_conn(|conn| {
+            let updated = conn.execute(
+                "INSERT OR REPLACE INTO active_accounts (chat_id, x_id)
+                 SELECT chat_id, x_id FROM users WHERE chat_id = ?1 AND x_id = ?2",
+                params![chat_id, x_id],
+            )?;
+            Ok(updated > 0)
+        })
+    }
+
+    fn insert_user(&self, chat_id: String, user: User) -> eyre::Result<()> {
+        let sealed = self.cipher.seal(&user.credentials)?;
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT OR REPLACE INTO users (chat_id, x_id, username, tokens, revoked)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    chat_id,
+                    user.x_id,
+                    user.username,
+                    sealed.to_bytes(),
+                    user.revoked
+                ],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO active_accounts (chat_id, x_id) VALUES (?1, ?2)",
+                params![chat_id, user.x_id],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn remove_user(&self, chat_id: &str, x_id: &str) -> eyre::Result<Option<User>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let user = query_users(
+                &tx,
+                &self.cipher,
+                "SELECT x_id, username, tokens, revoked FROM users
+                 WHERE chat_id = ?1 AND x_id = ?2",
+                params![chat_id, x_id],
+            )?
+            .into_iter()
+            .next();
+            tx.execute(
+                "DELETE FROM users WHERE chat_id = ?1 AND x_id = ?2",
+                params![chat_id, x_id],
+            )?;
+            tx.execute(
+                "DELETE FROM active_accounts WHERE chat_id = ?1 AND x_id = ?2",
+                params![chat_id, x_id],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO active_accounts (chat_id, x_id)
+                 SELECT chat_id, x_id FROM users WHERE chat_id = ?1 ORDER BY x_id LIMIT 1",
+                params![chat_id],
+            )?;
+            tx.commit()?;
+            Ok(user)
+        })
+    }
+
+    fn list_users(&self) -> eyre::Result<Vec<(String, User)>> {
+        self.with_conn(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT chat_id, x_id, username, tokens, revoked FROM users")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, user_columns(row, 1)?))
+            })?;
+            let mut users = Vec::new();
+            for row in rows {
+                let (chat_id, columns) = row?;
+                users.push((chat_id, columns.open(&self.cipher)?));
+            }
+            Ok(users)
+        })
+    }
+
+    fn get_role(&self, chat_id: &str, user_id: u64) -> eyre::Result<Option<Role>> {
+        self.with_conn(|conn| {
+            let role = conn
+                .query_row(
+                    "SELECT role FROM roles WHERE chat_id = ?1 AND user_id = ?2",
+                    params![chat_id, user_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(role)
+        })
+    }
+
+    fn set_role(&self, chat_id: &str, user_id: u64, role: Role) -> eyre::Result<()> {
+        self.with_conn(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO roles (chat_id, user_id, role) VALUES (?1, ?2, ?3)",
+                params![chat_id, user_id, role],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn remove_role(&self, chat_id: &str, user_id: u64) -> eyre::Result<bool> {
+        self.with_conn(|conn| {
+            let removed = conn.execute(
+                "DELETE FROM roles WHERE chat_id = ?1 AND user_id = ?2",
+                params![chat_id, user_id],
+            )?;
+            Ok(removed > 0)
+        })
+    }
+
+    fn list_roles(&self, chat_id: &str) -> eyre::Result<Vec<(u64, Role)>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn
+                .prepare("SELECT user_id, role FROM roles WHERE chat_id = ?1 ORDER BY user_id")?;
+            let rows = stmt.query_map(params![chat_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            Ok(rows.collect::<Result<Vec<_>, _>>()?)
+        })
+    }
+
+    fn get_chat_settings(&self, chat_id: &str) -> eyre::Result<ChatSettings> {
+        self.with_conn(|conn| {
+            let settings = conn
+                .query_row(
+                    "SELECT approvals_required, require_alt_text, send_delay_secs
+                     FROM chat_settings WHERE chat_id = ?1",
+                    params![chat_id],
+                    |row| {
+                        Ok(ChatSettings {
+                            approvals_required: row.get(0)?,
+                            require_alt_text: row.get(1)?,
+                            send_delay_secs: row.get(2)?,
+                        })
+                    },
+                )
+                .optional()?;
+            Ok(settings.unwrap_or_default())
+        })
+    }
+
+    fn set_chat_settings(&self, chat_id: &str, settings: ChatSettings) -> eyre::Result<()> {
+        self.with_conn(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO chat_settings
+                 (chat_id, approvals_required, require_alt_text, send_delay_secs)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    chat_id,
+                    settings.approvals_required,
+                    settings.require_alt_text,
+                    settings.send_delay_secs
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn insert_pending_post(&self, id: String, post: PendingPost) -> eyre::Result<()> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT OR REPLACE INTO pending_posts
+                 (id, chat_id, account_chat_id, author_id, x_id, tweet, approvals_required,
+                  created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    id,
+                    post.chat_id,
+                    post.account_chat_id,
+                    post.author_id,
+                    post.x_id,
+                    serde_json::to_string(&post.tweet)?,
+                    post.approvals_required,
+                    post.created_at
+                ],
+            )?;
+            tx.execute("DELETE FROM post_approvals WHERE post_id = ?1", params![id])?;
+            for user_id in &post.approvals {
+                tx.execute(
+                    "INSERT OR IGNORE INTO post_approvals (post_id, user_id) VALUES (?1, ?2)",
+                    params![id, user_id],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn get_pending_post(&self, id: &str) -> eyre::Result<Option<PendingPost>> {
+        self.with_conn(|conn| query_pending_post(conn, id))
+    }
+
+    fn approve_pending_post(&self, id: &str, user_id: u64) -> eyre::Result<Option<PendingPost>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            if query_pending_post(&tx, id)?.is_none() {
+                return Ok(None);
+            }
+            tx.execute(
+                "INSERT OR IGNORE INTO post_approvals (post_id, user_id) VALUES (?1, ?2)",
+                params![id, user_id],
+            )?;
+            let post = query_pending_post(&tx, id)?;
+            tx.commit()?;
+            Ok(post)
+        })
+    }
+
+    fn take_pending_post(&self, id: &str) -> eyre::Result<Option<PendingPost>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let post = query_pending_post(&tx, id)?;
+            tx.execute("DELETE FROM post_approvals WHERE post_id = ?1", params![id])?;
+            tx.execute("DELETE FROM pending_posts WHERE id = ?1", params![id])?;
+            tx.commit()?;
+            Ok(post)
+        })
+    }
+
+    fn take_expired_pending_posts(
+        &self,
+        created_before: u64,
+    ) -> eyre::Result<Vec<(String, PendingPost)>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let ids = {
+                let mut stmt = tx.prepare("SELECT id FROM pending_posts WHERE created_at < ?1")?;
+                let rows =
+                    stmt.query_map(params![created_before], |row| row.get::<_, String>(0))?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+            let mut posts = Vec::new();
+            for id in ids {
+                if let Some(post) = query_pending_post(&tx, &id)? {
+                    posts.push((id.clone(), post));
+                }
+                tx.execute("DELETE FROM post_approvals WHERE post_id = ?1", params![id])?;
+                tx.execute("DELETE FROM pending_posts WHERE id = ?1", params![id])?;
+            }
+            tx.commit()?;
+            Ok(posts)
+        })
+    }
+
+    fn insert_scheduled_send(&self, id: String, send: ScheduledSend) -> eyre::Result<()> {
+        self.with_conn(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO scheduled_sends
+                 (id, chat_id, account_chat_id, author_id, x_id, tweet, send_at, message_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    id,
+                    send.chat_id,
+                    send.account_chat_id,
+                    send.author_id,
+                    send.x_id,
+                    serde_json::to_string(&send.tweet)?,
+                    send.send_at,
+                    send.message_id
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn get_scheduled_send(&self, id: &str) -> eyre::Result<Option<ScheduledSend>> {
+        self.with_conn(|conn| {
+            let sends = query_scheduled_sends(conn, "WHERE id = ?1", params![id])?;
+            Ok(sends.into_iter().next().map(|(_, send)| send))
+        })
+    }
+
+    fn take_scheduled_send(&self, id: &str) -> eyre::Result<Option<ScheduledSend>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let sends = query_scheduled_sends(&tx, "WHERE id = ?1", params![id])?;
+            tx.execute("DELETE FROM scheduled_sends WHERE id = ?1", params![id])?;
+            tx.commit()?;
+            Ok(sends.into_iter().next().map(|(_, send)| send))
+        })
+    }
+
+    fn list_scheduled_sends(&self) -> eyre::Result<Vec<(String, ScheduledSend)>> {
+        self.with_conn(|conn| query_scheduled_sends(conn, "ORDER BY send_at", params![]))
+    }
+
+    fn insert_delegation(&self, code: String, delegation: Delegation) -> eyre::Result<()> {
+        self.with_conn(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO delegations
+                 (code, owner_chat_id, x_id, delegate_chat_id, actions, expires_at, max_actions,
+                  actions_used)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    code,
+                    delegation.owner_chat_id,
+                    delegation.x_id,
+                    delegation.delegate_chat_id,
+                    actions_to_sql(&delegation.actions),
+                    delegation.expires_at,
+                    delegation.max_actions,
+                    delegation.actions_used
+                ],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn get_delegation(&self, code: &str) -> eyre::Result<Option<Delegation>> {
+        self.with_conn(|conn| query_delegation(conn, code))
+    }
+
+    fn redeem_delegation(&self, code: &str, chat_id: &str) -> eyre::Result<Option<Delegation>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let redeemed = tx.execute(
+                "UPDATE delegations SET delegate_chat_id = ?2
+                 WHERE code = ?1 AND delegate_chat_id IS NULL AND expires_at > ?3",
+                params![code, chat_id, unix_now()],
+            )?;
+            let delegation = if redeemed > 0 {
+                query_delegation(&tx, code)?
+            } else {
+                None
+            };
+            tx.commit()?;
+            Ok(delegation)
+        })
+    }
+
+    fn consume_delegation(&self, code: &str, count: u32) -> eyre::Result<Option<Delegation>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let consumed = tx.execute(
+                "UPDATE delegations SET actions_used = actions_used + ?3
+                 WHERE code = ?1 AND expires_at > ?2
+                 AND (max_actions IS NULL OR actions_used + ?3 <= max_actions)",
+                params![code, unix_now(), count],
+            )?;
+            let delegation = if consumed > 0 {
+                query_delegation(&tx, code)?
+            } else {
+                None
+            };
+            tx.commit()?;
+            Ok(delegation)
+        })
+    }
+
+    fn remove_delegation(&self, code: &str) -> eyre::Result<Option<Delegation>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let delegation = query_delegation(&tx, code)?;
+            tx.execute("DELETE FROM delegations WHERE code = ?1", params![code])?;
+            tx.commit()?;
+            Ok(delegation)
+        })
+    }
+
+    fn list_delegations(&self, chat_id: &str) -> eyre::Result<Vec<(String, Delegation)>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT code, owner_chat_id, x_id, delegate_chat_id, actions, expires_at,
+                        max_actions, actions_used
+                 FROM delegations WHERE owner_chat_id = ?1 OR delegate_chat_id = ?1
+                 ORDER BY code",
+            )?;
+            let rows = stmt.query_map(params![chat_id], |row| {
+                Ok((row.get::<_, String>(0)?, delegation_from_row(row, 1)?))
+            })?;
+            Ok(rows.collect::<Result<Vec<_>, _>>()?)
+        })
+    }
+
+    fn draw_rate_limits(&self, draws: &[BucketDraw]) -> eyre::Result<Option<u64>> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let buckets = draws
+                .iter()
+                .map(|draw| {
+                    tx.query_row(
+                        "SELECT tokens, updated_at FROM rate_limits WHERE key = ?1",
+                        params![draw.key],
+                        |row| {
+                            Ok(Bucket {
+                                tokens: row.get(0)?,
+                                updated_at: row.get(1)?,
+                            })
+                        },
+                    )
+                    .optional()
+                })
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let buckets = match draw_buckets(draws, &buckets, unix_now()) {
+                Ok(buckets) => buckets,
+                Err(wait_secs) => return Ok(Some(wait_secs)),
+            };
+            for (draw, bucket) in draws.iter().zip(buckets) {
+                tx.execute(
+                    "INSERT INTO rate_limits (key, tokens, updated_at) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (key) DO UPDATE
+                     SET tokens = excluded.tokens, updated_at = excluded.updated_at",
+                    params![draw.key, bucket.tokens, bucket.updated_at],
+                )?;
+            }
+            tx.commit()?;
+            Ok(None)
+        })
+    }
+
+    fn reencrypt_tokens(&self) -> eyre::Result<usize> {
+        self.with_conn(|conn| {
+            let tx = conn.transaction()?;
+            let sealed_rows = {
+                let mut stmt = tx.prepare("SELECT chat_id, x_id, tokens FROM users")?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+            for (chat_id, x_id, tokens) in &sealed_rows {
+                let credentials: Credentials = self.cipher.open(&SealedBox::from_bytes(tokens)?)?;
+                tx.execute(
+                    "UPDATE users SET tokens = ?1 WHERE chat_id = ?2 AND x_id = ?3",
+                    params![self.cipher.seal(&credentials)?.to_bytes(), chat_id, x_id],
+                )?;
+            }
+            tx.commit()?;
+            Ok(sealed_rows.len())
+        })
+    }
+}
+
+/// A `users` row whose credentials are still sealed.
+struct UserColumns {
+    x_id: String,
+    username: String,
+    tokens: Vec<u8>,
+    revoked: bool,
+}
+
+impl UserColumns {
+    fn open(self, cipher: &TokenCipher) -> eyre::Result<User> {
+        Ok(User {
+            x_id: self.x_id,
+            username: self.username,
+            credentials: cipher.open(&SealedBox::from_bytes(&self.tokens)?)?,
+            revoked: self.revoked,
+        })
+    }
+}
+
+/// Reads the `x_id, username, tokens, revoked` columns starting at `first`.
+fn user_columns(row: &Row, first: usize) -> rusqlite::Result<UserColumns> {
+    Ok(UserColumns {
+        x_id: row.get(first)?,
+        username: row.get(first + 1)?,
+        tokens: row.get(first + 2)?,
+        revoked: row.get(first + 3)?,
+    })
+}
+
+fn query_users(
+    conn: &Connection,
+    cipher: &TokenCipher,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> eyre::Result<Vec<User>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(params, |row| user_columns(row, 0))?;
+    let mut users = Vec::new();
+    for row in rows {
+        users.push(row?.open(cipher)?);
+    }
+    Ok(users)
+}
+
+fn query_pending_post(conn: &Connection, id: &str) -> eyre::Result<Option<PendingPost>> {
+    let row = conn
+        .query_row(
+            "SELECT chat_id, account_chat_id, author_id, x_id, tweet, approvals_required,
+                    created_at
+             FROM pending_posts WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok((
+                    (row.get::<_, String>(0)?, row.get::<_, String>(1)?),
+                    row.get::<_, Option<u64>>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, u32>(5)?,
+                    row.get::<_, u64>(6)?,
+                ))
+            },
+        )
+        .optional()?;
+    let Some(((chat_id, account_chat_id), author_id, x_id, tweet, approvals_required, created_at)) =
+        row
+    else {
+        return Ok(None);
+    };
+    let mut stmt =
+        conn.prepare("SELECT user_id FROM post_approvals WHERE post_id = ?1 ORDER BY rowid")?;
+    let approvals = stmt
+        .query_map(params![id], |row| row.get(0))?
+        .collect::<Result<Vec<u64>, _>>()?;
+    Ok(Some(PendingPost {
+        chat_id,
+        account_chat_id,
+        author_id,
+        x_id,
+        tweet: serde_json::from_str(&tweet)?,
+        approvals,
+        approvals_required,
+        created_at,
+    }))
+}
+
+/// The scheduled sends matching `filter`, an SQL clause over the
+/// `scheduled_sends` columns.
+fn query_scheduled_sends(
+    conn: &Connection,
+    filter: &str,
+    params: &[&dyn ToSql],
+) -> eyre::Result<Vec<(String, ScheduledSend)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, chat_id, account_chat_id, author_id, x_id, tweet, send_at, message_id
+         FROM scheduled_sends {}",
+        filter
+    ))?;
+    let rows = stmt.query_map(params, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            (
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<u64>>(3)?,
+                row.get::<_, String>(4)?,
+            ),
+            row.get::<_, String>(5)?,
+            row.get::<_, u64>(6)?,
+            row.get::<_, i32>(7)?,
+        ))
+    })?;
+    let mut sends = Vec::new();
+    for row in rows {
+        let (id, (chat_id, account_chat_id, author_id, x_id), tweet, send_at, message_id) = row?;
+        let send = ScheduledSend {
+            chat_id,
+            account_chat_id,
+            author_id,
+            x_id,
+            tweet: serde_json::from_str(&tweet)?,
+            send_at,
+            message_id,
+        };
+        sends.push((id, send));
+    }
+    Ok(sends)
+}
+
+/// Actions are stored as a comma separated list of their names.
+fn actions_to_sql(actions: &[Action]) -> String {
+    actions
+        .iter()
+        .map(Action::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Reads the `owner_chat_id, x_id, delegate_chat_id, actions, expires_at,
+/// max_actions, actions_used` columns starting at `first`.
+fn delegation_from_row(row: &Row, first: usize) -> rusqlite::Result<Delegation> {
+    let actions: String = row.get(first + 3)?;
+    let actions = actions
+        .split(',')
+        .filter(|a| !a.is_empty())
+        .map(str::parse)
+        .collect::<eyre::Result<Vec<Action>>>()
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(
+                first + 3,
+                rusqlite::types::Type::Text,
+                e.into(),
+            )
+        })?;
+    Ok(Delegation {
+        owner_chat_id: row.get(first)?,
+        x_id: row.get(first + 1)?,
+        delegate_chat_id: row.get(first + 2)?,
+        actions,
+        expires_at: row.get(first + 4)?,
+        max_actions: row.get(first + 5)?,
+        actions_used: row.get(first + 6)?,
+    })
+}
+
+fn query_delegation(conn: &Connection, code: &str) -> eyre::Result<Option<Delegation>> {
+    let delegation = conn
+        .query_row(
+            "SELECT owner_chat_id, x_id, delegate_chat_id, actions, expires_at, max_actions,
+                    actions_used
+             FROM delegations WHERE code = ?1",
+            params![code],
+            |row| delegation_from_row(row, 0),
+        )
+        .optional()?;
+    Ok(delegation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::storage_tests::{self, storage_tests, TempPath, KEY};
+
+    fn open() -> Box<SqliteDB> {
+        Box::new(SqliteDB::open(":memory:", TokenCipher::new(KEY, &[]).unwrap()).unwrap())
+    }
+
+    storage_tests!(open());
+
+    #[test]
+    fn reencrypt_tokens() {
+        let path = TempPath::new("teleport-sqlite");
+        storage_tests::reencrypt_tokens(|cipher| SqliteDB::open(&path.0, cipher));
+    }
+}
@@ -0,0 +1,391 @@
+//! Round trips every [`Storage`] backend has to pass, run by the tests of each
+//! backend through [`storage_tests`].
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::*;
+use crate::twitter::{auth::TwitterTokenPair, oauth2::OAuth2Token};
+
+/// A fixed, valid database key.
+pub const KEY: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+/// A second valid key, to rotate [`KEY`] out.
+pub const OTHER_KEY: &str = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+/// A unique path in the temporary directory, whose files are removed on drop.
+pub struct TempPath(pub String);
+
+impl TempPath {
+    pub fn new(prefix: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "{}-{}-{}.db",
+            prefix,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let path = Self(path.to_str().unwrap().to_string());
+        path.remove();
+        path
+    }
+
+    fn remove(&self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.0, suffix));
+        }
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Generates one test per round trip, each on a fresh database from `$open`.
+macro_rules! storage_tests {
+    ($open:expr) => {
+        $crate::db::storage_tests::storage_tests!(
+            $open;
+            oauth_tokens,
+            users,
+            roles,
+            chat_settings,
+            pending_posts,
+            scheduled_sends,
+            delegations,
+            rate_limits,
+        );
+    };
+    ($open:expr; $($name:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                let db = $open;
+                $crate::db::storage_tests::$name(&*db);
+            }
+        )*
+    };
+}
+pub(crate) use storage_tests;
+
+pub fn oauth1_user(x_id: &str, username: &str) -> User {
+    User {
+        x_id: x_id.to_string(),
+        username: username.to_string(),
+        credentials: Credentials::OAuth1(TwitterTokenPair {
+            token: format!("token-{}", x_id),
+            secret: format!("secret-{}", x_id),
+        }),
+        revoked: false,
+    }
+}
+
+pub fn oauth_tokens(db: &dyn Storage) {
+    let pending = |method, secret: &str, created_at| PendingAuth {
+        secret: secret.to_string(),
+        chat_id: "1".to_string(),
+        created_at,
+        method,
+    };
+    db.insert_oauth_token("old".to_string(), pending(AuthMethod::OAuth1, "a", 100))
+        .unwrap();
+    db.insert_oauth_token("new".to_string(), pending(AuthMethod::OAuth1, "b", 200))
+        .unwrap();
+    db.insert_oauth_token("state".to_string(), pending(AuthMethod::OAuth2, "c", 300))
+        .unwrap();
+
+    let (token, latest) = db
+        .take_latest_oauth_token("1", AuthMethod::OAuth1)
+        .unwrap()
+        .unwrap();
+    assert_eq!((token.as_str(), latest.secret.as_str()), ("new", "b"));
+    // Logins expire `ttl_secs` after they were started.
+    assert!(latest.is_expired(600));
+    assert!(!pending(AuthMethod::OAuth1, "d", unix_now()).is_expired(600));
+    assert!(db
+        .take_latest_oauth_token("2", AuthMethod::OAuth2)
+        .unwrap()
+        .is_none());
+
+    assert_eq!(db.purge_oauth_tokens(250).unwrap(), 1);
+    assert!(db.take_oauth_token("old", "1").unwrap().is_none());
+    // Another chat cannot take the login, nor use it up.
+    assert!(db.take_oauth_token("state", "2").unwrap().is_none());
+    let state = db.take_oauth_token("state", "1").unwrap().unwrap();
+    assert_eq!(state.method, AuthMethod::OAuth2);
+    assert!(db.take_oauth_token("state", "1").unwrap().is_none());
+}
+
+pub fn users(db: &dyn Storage) {
+    assert!(db.get_active_user("1").unwrap().is_none());
+    db.insert_user("1".to_string(), oauth1_user("10", "first"))
+        .unwrap();
+    let mut second = oauth1_user("20", "second");
+    second.credentials = Credentials::OAuth2(OAuth2Token {
+        access_token: "access".to_string(),
+        refresh_token: Some("refresh".to_string()),
+        expires_at: 1_700_000_000,
+        scopes: vec!["tweet.write".to_string()],
+    });
+    db.insert_user("1".to_string(), second).unwrap();
+    db.insert_user("2".to_string(), oauth1_user("10", "first"))
+        .unwrap();
+
+    // The first linked account becomes the active one.
+    assert_eq!(db.get_active_user("1").unwrap().unwrap().x_id, "10");
+    assert!(db.set_active_user("1", "20").unwrap());
+    assert!(!db.set_active_user("1", "30").unwrap());
+    let active = db.get_active_user("1").unwrap().unwrap();
+    let Credentials::OAuth2(token) = active.credentials else {
+        panic!("Expected OAuth 2.0 credentials");
+    };
+    assert_eq!(token.refresh_token.as_deref(), Some("refresh"));
+    assert_eq!(token.scopes, ["tweet.write"]);
+
+    // Linking an account again replaces it.
+    let mut renamed = oauth1_user("10", "renamed");
+    renamed.revoked = true;
+    db.insert_user("1".to_string(), renamed).unwrap();
+    let mut users = db.get_chat_users("1").unwrap();
+    users.sort_by(|a, b| a.x_id.cmp(&b.x_id));
+    assert_eq!(users.len(), 2);
+    assert_eq!(users[0].username, "renamed");
+    assert!(users[0].revoked);
+
+    let mut all: Vec<(String, String)> = db
+        .list_users()
+        .unwrap()
+        .into_iter()
+        .map(|(chat_id, user)| (chat_id, user.x_id))
+        .collect();
+    all.sort();
+    assert_eq!(
+        all,
+        [("1", "10"), ("1", "20"), ("2", "10")].map(|(c, x)| (c.to_string(), x.to_string()))
+    );
+
+    // Removing the active account activates the other one.
+    assert_eq!(db.remove_user("1", "20").unwrap().unwrap().x_id, "20");
+    assert!(db.remove_user("1", "20").unwrap().is_none());
+    assert_eq!(db.get_active_user("1").unwrap().unwrap().x_id, "10");
+    db.remove_user("1", "10").unwrap();
+    assert!(db.get_active_user("1").unwrap().is_none());
+    assert_eq!(db.get_chat_users("2").unwrap().len(), 1);
+}
+
+pub fn roles(db: &dyn Storage) {
+    assert!(db.get_role("1", 5).unwrap().is_none());
+    db.set_role("1", 5, Role::Poster).unwrap();
+    db.set_role("1", 6, Role::Viewer).unwrap();
+    db.set_role("1", 5, Role::Owner).unwrap();
+    db.set_role("2", 5, Role::Reacter).unwrap();
+    assert_eq!(db.get_role("1", 5).unwrap(), Some(Role::Owner));
+    let mut roles = db.list_roles("1").unwrap();
+    roles.sort();
+    assert_eq!(roles, [(5, Role::Owner), (6, Role::Viewer)]);
+    assert!(db.remove_role("1", 5).unwrap());
+    assert!(!db.remove_role("1", 5).unwrap());
+    assert_eq!(db.get_role("2", 5).unwrap(), Some(Role::Reacter));
+}
+
+pub fn chat_settings(db: &dyn Storage) {
+    assert_eq!(db.get_chat_settings("1").unwrap(), ChatSettings::default());
+    let settings = ChatSettings {
+        approvals_required: 2,
+        require_alt_text: true,
+        send_delay_secs: 30,
+    };
+    db.set_chat_settings("1", settings.clone()).unwrap();
+    assert_eq!(db.get_chat_settings("1").unwrap(), settings);
+    assert_eq!(db.get_chat_settings("2").unwrap(), ChatSettings::default());
+    db.set_chat_settings("1", ChatSettings::default()).unwrap();
+    assert_eq!(db.get_chat_settings("1").unwrap(), ChatSettings::default());
+}
+
+fn reply(text: &str) -> Tweet {
+    let mut tweet = Tweet::new(text.to_string());
+    tweet.set_reply_tweet_id("99".to_string());
+    tweet
+}
+
+pub fn pending_posts(db: &dyn Storage) {
+    let post = |created_at| PendingPost {
+        chat_id: "1".to_string(),
+        account_chat_id: "2".to_string(),
+        author_id: Some(7),
+        x_id: "10".to_string(),
+        tweet: reply("hello"),
+        approvals: Vec::new(),
+        approvals_required: 2,
+        created_at,
+    };
+    db.insert_pending_post("old".to_string(), post(100))
+        .unwrap();
+    db.insert_pending_post("new".to_string(), post(200))
+        .unwrap();
+
+    let stored = db.get_pending_post("new").unwrap().unwrap();
+    assert_eq!(stored.account_chat_id, "2");
+    assert_eq!(stored.author_id, Some(7));
+    assert_eq!(stored.tweet.text(), "hello");
+    assert!(stored.tweet.is_reply());
+
+    db.approve_pending_post("new", 8).unwrap();
+    let approved = db.approve_pending_post("new", 9).unwrap().unwrap();
+    assert_eq!(approved.approvals, [8, 9]);
+    assert!(db.approve_pending_post("gone", 8).unwrap().is_none());
+
+    let expired = db.take_expired_pending_posts(150).unwrap();
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].0, "old");
+    assert!(db.get_pending_post("old").unwrap().is_none());
+
+    assert!(db.take_pending_post("new").unwrap().is_some());
+    assert!(db.take_pending_post("new").unwrap().is_none());
+}
+
+pub fn scheduled_sends(db: &dyn Storage) {
+    let send = |message_id| ScheduledSend {
+        chat_id: "1".to_string(),
+        account_chat_id: "1".to_string(),
+        author_id: None,
+        x_id: "10".to_string(),
+        tweet: reply("soon"),
+        send_at: 1_700_000_000,
+        message_id,
+    };
+    db.insert_scheduled_send("a".to_string(), send(1)).unwrap();
+    db.insert_scheduled_send("b".to_string(), send(2)).unwrap();
+
+    let stored = db.get_scheduled_send("a").unwrap().unwrap();
+    assert_eq!(stored.message_id, 1);
+    assert_eq!(stored.send_at, 1_700_000_000);
+    assert_eq!(stored.author_id, None);
+    assert!(stored.tweet.is_reply());
+
+    let mut ids: Vec<String> = db
+        .list_scheduled_sends()
+        .unwrap()
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    ids.sort();
+    assert_eq!(ids, ["a", "b"]);
+
+    assert_eq!(db.take_scheduled_send("b").unwrap().unwrap().message_id, 2);
+    assert!(db.take_scheduled_send("b").unwrap().is_none());
+    assert!(db.get_scheduled_send("b").unwrap().is_none());
+    assert_eq!(db.list_scheduled_sends().unwrap().len(), 1);
+}
+
+pub fn delegation(expires_at: u64, max_actions: Option<u32>) -> Delegation {
+    Delegation {
+        owner_chat_id: "1".to_string(),
+        x_id: "10".to_string(),
+        delegate_chat_id: None,
+        actions: vec![Action::Tweet, Action::Delete],
+        expires_at,
+        max_actions,
+        actions_used: 0,
+    }
+}
+
+pub fn delegations(db: &dyn Storage) {
+    let later = unix_now() + 3600;
+    db.insert_delegation("open".to_string(), delegation(later, Some(3)))
+        .unwrap();
+    db.insert_delegation("expired".to_string(), delegation(100, None))
+        .unwrap();
+    assert_eq!(
+        db.get_delegation("open").unwrap().unwrap().actions,
+        [Action::Tweet, Action::Delete]
+    );
+
+    assert!(db.redeem_delegation("expired", "2").unwrap().is_none());
+    let redeemed = db.redeem_delegation("open", "2").unwrap().unwrap();
+    assert_eq!(redeemed.delegate_chat_id.as_deref(), Some("2"));
+    // A code can only be redeemed once.
+    assert!(db.redeem_delegation("open", "3").unwrap().is_none());
+
+    // Consuming takes all the actions asked for or none of them.
+    assert!(db.consume_delegation("open", 4).unwrap().is_none());
+    assert_eq!(
+        db.consume_delegation("open", 2)
+            .unwrap()
+            .unwrap()
+            .actions_used,
+        2
+    );
+    assert!(db.consume_delegation("open", 2).unwrap().is_none());
+    assert!(db.consume_delegation("open", 1).unwrap().is_some());
+    assert!(db.consume_delegation("open", 1).unwrap().is_none());
+    assert!(db.consume_delegation("expired", 1).unwrap().is_none());
+    assert!(db.consume_delegation("missing", 1).unwrap().is_none());
+
+    let mut owned: Vec<String> = db
+        .list_delegations("1")
+        .unwrap()
+        .into_iter()
+        .map(|(code, _)| code)
+        .collect();
+    owned.sort();
+    assert_eq!(owned, ["expired", "open"]);
+    assert_eq!(db.list_delegations("2").unwrap().len(), 1);
+    assert!(db.list_delegations("3").unwrap().is_empty());
+
+    assert!(db.remove_delegation("open").unwrap().is_some());
+    assert!(db.remove_delegation("open").unwrap().is_none());
+    assert!(db.list_delegations("2").unwrap().is_empty());
+}
+
+/// Rotates the key of a database that `open` loads from a file: records sealed
+/// with the old key stay readable while it is a previous key, and once they
+/// are re-sealed the old key can go.
+pub fn reencrypt_tokens<D: Storage>(open: impl Fn(TokenCipher) -> eyre::Result<D>) {
+    let old = TokenCipher::new(KEY, &[]).unwrap();
+    let db = open(old).unwrap();
+    db.insert_user("1".to_string(), oauth1_user("10", "first"))
+        .unwrap();
+    db.insert_user("2".to_string(), oauth1_user("20", "second"))
+        .unwrap();
+    drop(db);
+
+    let new_only = || TokenCipher::new(OTHER_KEY, &[]).unwrap();
+    assert!(open(new_only()).is_err());
+
+    let rotating = TokenCipher::new(OTHER_KEY, &[KEY.to_string()]).unwrap();
+    let db = open(rotating).unwrap();
+    assert_eq!(db.reencrypt_tokens().unwrap(), 2);
+    drop(db);
+
+    let db = open(new_only()).unwrap();
+    let user = db.get_active_user("2").unwrap().unwrap();
+    let Credentials::OAuth1(pair) = user.credentials else {
+        panic!("Expected OAuth 1.0a credentials");
+    };
+    assert_eq!(pair.token, "token-20");
+}
+
+pub fn rate_limits(db: &dyn Storage) {
+    let draw = |key: &str, period_secs| BucketDraw {
+        key: key.to_string(),
+        limit: RateLimit {
+            capacity: 1,
+            period_secs,
+        },
+        cost: 1,
+    };
+    assert_eq!(db.draw_rate_limits(&[draw("a", 3600)]).unwrap(), None);
+    assert!(db.draw_rate_limits(&[draw("a", 3600)]).unwrap().is_some());
+    // A denied draw takes nothing from the other buckets.
+    let both = [draw("b", 3600), draw("a", 3600)];
+    assert!(db.draw_rate_limits(&both).unwrap().is_some());
+    assert_eq!(db.draw_rate_limits(&[draw("b", 3600)]).unwrap(), None);
+
+    // Half a token refills per second, so the bucket is still short right
+    // after the draw, even across a second boundary.
+    assert_eq!(db.draw_rate_limits(&[draw("c", 2)]).unwrap(), None);
+    assert!(db.draw_rate_limits(&[draw("c", 2)]).unwrap().is_some());
+    std::thread::sleep(std::time::Duration::from_millis(2100));
+    assert_eq!(db.draw_rate_limits(&[draw("c", 2)]).unwrap(), None);
+}
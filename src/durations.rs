@@ -0,0 +1,39 @@
+/// Parses durations such as `30s`, `30m`, `12h` or `7d` into seconds.
+pub fn parse_duration(s: &str) -> Option<u64> {
+    let (split, _) = s.char_indices().last()?;
+    let (amount, unit) = s.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    amount.checked_mul(unit_secs).filter(|secs| *secs > 0)
+}
+
+/// Formats seconds in the largest whole unit, rounding minutes up from a
+/// minute on.
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        secs if secs >= 24 * 60 * 60 => format!("{}d", secs / (24 * 60 * 60)),
+        secs if secs >= 60 * 60 => format!("{}h", secs / (60 * 60)),
+        secs if secs >= 60 => format!("{}m", secs.div_ceil(60)),
+        secs => format!("{}s", secs),
+    }
+}
+
+/// Formats a wait for people, e.g. "5 minutes" or "3 hours".
+pub fn format_wait(secs: u64) -> String {
+    let (amount, unit) = if secs < 2 * 60 * 60 {
+        (secs.div_ceil(60), "minute")
+    } else {
+        (secs.div_ceil(60 * 60), "hour")
+    };
+    if amount == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", amount, unit)
+    }
+}
@@ -1,75 +1,271 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use axum::extract::{Query, State};
-use eyre::OptionExt;
 use serde::Deserialize;
 use teloxide::{prelude::Requester, types::ChatId, Bot};
-use tokio::sync::Mutex;
 
 use crate::{
-    db::{InMemoryDB, User},
-    twitter::{auth::authorize_token, builder::TwitterBuilder},
+    db::{unix_now, AuthMethod, PendingAuth, Storage, User},
+    handlers::{media::AlbumCollector, scheduled::Countdowns},
+    policy::Policy,
+    rate_limits::RateLimits,
+    signed_state::StateSigner,
+    twitter::{
+        auth::{authorize_token, Credentials},
+        builder::TwitterBuilder,
+        oauth2,
+    },
 };
 
 #[derive(Deserialize)]
 pub struct CallbackQuery {
     oauth_token: String,
     oauth_verifier: String,
-    chat_id: String,
+    state: String,
+}
+
+/// Twitter redirects here with either `code` or `error` set.
+#[derive(Deserialize)]
+pub struct OAuth2CallbackQuery {
+    code: Option<String>,
+    error: Option<String>,
+    state: String,
 }
 
 #[derive(Clone)]
 pub struct SharedState {
-    pub db: Arc<Mutex<InMemoryDB>>,
+    pub db: Arc<dyn Storage>,
     pub bot: Bot,
     pub twitter: TwitterBuilder,
     pub bot_name: String,
+    /// How long a login link from `/auth` stays valid.
+    pub oauth_token_ttl_secs: u64,
+    pub state_signer: StateSigner,
+    /// Public base URL of the callback server, or `None` when logins are
+    /// completed out of band with `/verify`.
+    pub callback_url: Option<String>,
+    /// How long a post waits for approval before it is dropped.
+    pub approval_ttl_secs: u64,
+    /// Content rules checked before anything is posted.
+    pub policy: Arc<Policy>,
+    pub rate_limits: RateLimits,
+    pub albums: AlbumCollector,
+    pub countdowns: Countdowns,
+    /// One lock per Twitter user id, held while its OAuth 2.0 token is
+    /// refreshed.
+    pub refresh_locks: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl SharedState {
+    /// Returns `user` with credentials that are valid for at least a few more
+    /// minutes, refreshing and persisting an expiring OAuth 2.0 token.
+    pub async fn refresh_credentials(&self, chat_id: &str, user: User) -> eyre::Result<User> {
+        let Credentials::OAuth2(token) = &user.credentials else {
+            return Ok(user);
+        };
+        if !token.needs_refresh() {
+            return Ok(user);
+        }
+        // A refresh token only works once, so concurrent refreshes of the same
+        // account wait for the first one and pick up the token it stored.
+        let lock = self
+            .refresh_locks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(user.x_id.clone())
+            .or_default()
+            .clone();
+        let _guard = lock.lock().await;
+        let Some(mut user) = self
+            .db
+            .get_chat_users(chat_id)?
+            .into_iter()
+            .find(|u| u.x_id == user.x_id)
+        else {
+            eyre::bail!("@{} was unlinked from this chat", user.username);
+        };
+        let Credentials::OAuth2(token) = &user.credentials else {
+            return Ok(user);
+        };
+        if !token.needs_refresh() {
+            return Ok(user);
+        }
+        let config = self
+            .twitter
+            .oauth2
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("OAuth 2.0 is not configured"))?;
+        let token = oauth2::refresh_token(config, token).await?;
+        log::info!("Refreshed OAuth 2.0 token of {}", user.username);
+        user.credentials = Credentials::OAuth2(token);
+        self.db.insert_user(chat_id.to_string(), user.clone())?;
+        Ok(user)
+    }
+}
+
+/// Checks that a pending login taken out of the database is still valid and
+/// was started by `tg_chat_id` with `method`, telling the chat if it expired.
+async fn check_pending(
+    shared_state: &SharedState,
+    tg_chat_id: ChatId,
+    pending: Option<PendingAuth>,
+    method: AuthMethod,
+) -> eyre::Result<PendingAuth> {
+    let pending = pending.filter(|p| !p.is_expired(shared_state.oauth_token_ttl_secs));
+    let Some(pending) = pending else {
+        shared_state
+            .bot
+            .send_message(
+                tg_chat_id,
+                "Your login link expired, please run /auth again",
+            )
+            .await?;
+        eyre::bail!("Received callback for an expired or unknown login");
+    };
+    if pending.chat_id != tg_chat_id.to_string() {
+        eyre::bail!(
+            "Login was started by chat {} but the callback is for chat {}",
+            pending.chat_id,
+            tg_chat_id
+        );
+    }
+    if pending.method != method {
+        eyre::bail!("Login was started with {:?}", pending.method);
+    }
+    Ok(pending)
 }
 
+/// Exchanges a request token that `tg_chat_id` took out of the database for an
+/// access token and links the resulting account to the chat. `oauth_verifier`
+/// is either the callback's verifier or the PIN sent with `/verify`.
 pub async fn complete_auth_flow(
     shared_state: SharedState,
-    query: CallbackQuery,
+    tg_chat_id: ChatId,
+    oauth_token: String,
+    pending: Option<PendingAuth>,
+    oauth_verifier: String,
 ) -> eyre::Result<()> {
-    let oauth_token = query.oauth_token;
-    let oauth_verifier = query.oauth_verifier;
-    let chat_id = query.chat_id;
+    let pending = check_pending(&shared_state, tg_chat_id, pending, AuthMethod::OAuth1).await?;
+    let token_pair = authorize_token(oauth_token, pending.secret, oauth_verifier).await?;
+    link_account(shared_state, tg_chat_id, Credentials::OAuth1(token_pair)).await
+}
 
-    let mut db = shared_state.db.lock().await;
-    let oauth_access_secret = db
-        .oauth_tokens
-        .remove(&oauth_token)
-        .ok_or_eyre("Failed to find oauth_access_secret in database")?;
+/// Exchanges the authorization code of an OAuth 2.0 login for tokens and links
+/// the resulting account to the chat.
+pub async fn complete_oauth2_flow(
+    shared_state: SharedState,
+    tg_chat_id: ChatId,
+    pending: Option<PendingAuth>,
+    code: String,
+) -> eyre::Result<()> {
+    let pending = check_pending(&shared_state, tg_chat_id, pending, AuthMethod::OAuth2).await?;
+    let config = shared_state
+        .twitter
+        .oauth2
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("OAuth 2.0 is not configured"))?;
+    let token = oauth2::exchange_code(config, &code, &pending.secret).await?;
+    link_account(shared_state, tg_chat_id, Credentials::OAuth2(token)).await
+}
 
-    let token_pair = authorize_token(oauth_token, oauth_access_secret, oauth_verifier).await?;
+async fn link_account(
+    shared_state: SharedState,
+    tg_chat_id: ChatId,
+    credentials: Credentials,
+) -> eyre::Result<()> {
     let x_info = shared_state
         .twitter
-        .with_auth(token_pair.clone())
+        .with_auth(credentials.clone())
         .get_user_info()
         .await?;
     let user = User {
         x_id: x_info.id.clone(),
         username: x_info.username.clone(),
-        token_pair,
+        credentials,
+        revoked: false,
     };
     let user_profile_url = format!("https://x.com/{}", x_info.username);
     let msg = format!(
-        "Succesfully authenticated user: {}",
+        "Succesfully authenticated user: {}\nIt is now the active account of this chat.",
         user_profile_url.clone()
     );
     log::info!("{}", msg);
-    let tg_chat_id = chat_id.parse::<i64>().unwrap();
-    let tg_chat_id = ChatId(tg_chat_id);
+    let chat_id = tg_chat_id.to_string();
+    shared_state.db.insert_user(chat_id.clone(), user)?;
+    shared_state.db.set_active_user(&chat_id, &x_info.id)?;
     shared_state.bot.send_message(tg_chat_id, msg).await?;
-    db.access_tokens.insert(chat_id, user);
-    drop(db);
     Ok(())
 }
 
+/// Periodically drops request tokens whose login was never completed.
+pub async fn purge_expired_oauth_tokens(db: Arc<dyn Storage>, ttl_secs: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(ttl_secs.clamp(1, 60)));
+    loop {
+        interval.tick().await;
+        match db.purge_oauth_tokens(unix_now().saturating_sub(ttl_secs)) {
+            Ok(0) => {}
+            Ok(purged) => log::info!("Purged {} expired oauth tokens", purged),
+            Err(e) => log::error!("Failed to purge expired oauth tokens: {:?}", e),
+        }
+    }
+}
+
+async fn handle_callback(shared_state: SharedState, query: CallbackQuery) -> eyre::Result<()> {
+    let tg_chat_id = shared_state.state_signer.verify(&query.state)?;
+    // Only the chat the state was signed for can use up its login.
+    let pending = shared_state
+        .db
+        .take_oauth_token(&query.oauth_token, &tg_chat_id.to_string())?;
+    complete_auth_flow(
+        shared_state,
+        tg_chat_id,
+        query.oauth_token,
+        pending,
+        query.oauth_verifier,
+    )
+    .await
+}
+
 pub async fn callback(
     State(shared_state): State<SharedState>,
     Query(query): Query<CallbackQuery>,
 ) -> &'static str {
-    match complete_auth_flow(shared_state, query).await {
+    match handle_callback(shared_state, query).await {
+        Ok(_) => "Success",
+        Err(e) => {
+            log::error!("{:?}", e);
+            "Failed"
+        }
+    }
+}
+
+async fn handle_oauth2_callback(
+    shared_state: SharedState,
+    query: OAuth2CallbackQuery,
+) -> eyre::Result<()> {
+    let tg_chat_id = shared_state.state_signer.verify(&query.state)?;
+    let pending = shared_state
+        .db
+        .take_oauth_token(&query.state, &tg_chat_id.to_string())?;
+    let Some(code) = query.code else {
+        shared_state
+            .bot
+            .send_message(tg_chat_id, "Login was cancelled on Twitter")
+            .await?;
+        eyre::bail!("OAuth 2.0 authorization failed: {:?}", query.error);
+    };
+    complete_oauth2_flow(shared_state, tg_chat_id, pending, code).await
+}
+
+pub async fn oauth2_callback(
+    State(shared_state): State<SharedState>,
+    Query(query): Query<OAuth2CallbackQuery>,
+) -> &'static str {
+    match handle_oauth2_callback(shared_state, query).await {
         Ok(_) => "Success",
         Err(e) => {
             log::error!("{:?}", e);
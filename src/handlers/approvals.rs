@@ -0,0 +1,307 @@
+use std::time::Duration;
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    requests::{Requester, ResponseResult},
+    types::{CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message},
+    Bot,
+};
+
+use crate::{
+    accounts::{delegated_accounts, ActingAccount},
+    db::{unix_now, Action, PendingPost, Role},
+    endpoints::SharedState,
+    handlers::{
+        scheduled::{handle_send_button, SEND},
+        twitter_commands::twitter_error_message,
+        undo::{handle_undo, undo_keyboard, Undo, UNDO},
+    },
+    permissions::member_role,
+    twitter::{error::TwitterError, tweet::Tweet},
+};
+
+const APPROVE: &str = "approve";
+const REJECT: &str = "reject";
+
+pub fn new_post_id() -> String {
+    format!("{:016x}", OsRng.next_u64())
+}
+
+fn keyboard(id: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback("Approve", format!("{}:{}", APPROVE, id)),
+        InlineKeyboardButton::callback("Reject", format!("{}:{}", REJECT, id)),
+    ]])
+}
+
+pub fn describe_tweet(tweet: &Tweet) -> &'static str {
+    if tweet.is_reply() {
+        "Reply"
+    } else if tweet.is_quote() {
+        "Quote tweet"
+    } else {
+        "Tweet"
+    }
+}
+
+fn preview_text(post: &PendingPost, username: &str) -> String {
+    format!(
+        "{} as @{} waiting for approval ({}/{}):\n\n{}",
+        describe_tweet(&post.tweet),
+        username,
+        post.approvals.len(),
+        post.approvals_required,
+        post.tweet.text()
+    )
+}
+
+/// Holds `tweet` until `approvals_required` other members approve it and
+/// posts a preview with Approve/Reject buttons.
+pub async fn submit_for_approval(
+    bot: &Bot,
+    shared_state: &SharedState,
+    msg: &Message,
+    account: &ActingAccount,
+    tweet: Tweet,
+    approvals_required: u32,
+) -> eyre::Result<()> {
+    let id = new_post_id();
+    let post = PendingPost {
+        chat_id: msg.chat.id.to_string(),
+        account_chat_id: account.chat_id.clone(),
+        author_id: msg.from().map(|from| from.id.0),
+        x_id: account.user.x_id.clone(),
+        tweet,
+        approvals: Vec::new(),
+        approvals_required,
+        created_at: unix_now(),
+    };
+    let text = preview_text(&post, &account.user.username);
+    shared_state.db.insert_pending_post(id.clone(), post)?;
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(keyboard(&id))
+        .await?;
+    Ok(())
+}
+
+/// Sends a held back tweet from `chat_id` as the account `x_id` linked to
+/// `account_chat_id`, and returns its URL and id.
+pub async fn send_post(
+    shared_state: &SharedState,
+    chat_id: &str,
+    account_chat_id: &str,
+    x_id: &str,
+    tweet: Tweet,
+) -> eyre::Result<(String, String)> {
+    let user = shared_state
+        .db
+        .get_chat_users(account_chat_id)?
+        .into_iter()
+        .find(|user| user.x_id == x_id)
+        .ok_or_else(|| eyre::eyre!("The account is no longer linked"))?;
+    if user.revoked {
+        eyre::bail!("The bot's access to @{} was revoked", user.username);
+    }
+    // The policy may have changed since the post was submitted, and the
+    // delegation it was submitted through may have been revoked or expired.
+    let delegated = account_chat_id != chat_id;
+    shared_state.policy.check(&tweet, delegated)?;
+    if delegated {
+        let action = tweet_action(&tweet);
+        let permitted = delegated_accounts(shared_state.db.as_ref(), chat_id)?
+            .into_iter()
+            .any(|account| {
+                account.chat_id == account_chat_id
+                    && account.user.x_id == x_id
+                    && account
+                        .delegation
+                        .is_some_and(|(_, delegation)| delegation.actions.contains(&action))
+            });
+        if !permitted {
+            eyre::bail!(
+                "The delegation of @{} no longer allows {}",
+                user.username,
+                action.as_str()
+            );
+        }
+    }
+    let user = shared_state
+        .refresh_credentials(account_chat_id, user)
+        .await?;
+    let id = shared_state
+        .twitter
+        .with_auth(user.credentials.clone())
+        .raw_tweet(tweet)
+        .await?;
+    let url = format!("https://x.com/{}/status/{}", user.username, id);
+    Ok((url, id))
+}
+
+/// The delegated action posting `tweet` takes.
+fn tweet_action(tweet: &Tweet) -> Action {
+    if tweet.is_reply() {
+        Action::Reply
+    } else if tweet.is_quote() {
+        Action::Quote
+    } else {
+        Action::Tweet
+    }
+}
+
+async fn account_name(shared_state: &SharedState, post: &PendingPost) -> eyre::Result<String> {
+    Ok(shared_state
+        .db
+        .get_chat_users(&post.account_chat_id)?
+        .into_iter()
+        .find(|user| user.x_id == post.x_id)
+        .map(|user| user.username)
+        .unwrap_or_else(|| "unlinked account".to_string()))
+}
+
+/// Handles a press of Approve or Reject and returns the text to show the
+/// member who pressed it.
+async fn handle_button(
+    bot: &Bot,
+    shared_state: &SharedState,
+    q: &CallbackQuery,
+) -> eyre::Result<String> {
+    let (Some(data), Some(message)) = (&q.data, &q.message) else {
+        return Ok("Unknown button".to_string());
+    };
+    let Some((action, id)) = data.split_once(':') else {
+        return Ok("Unknown button".to_string());
+    };
+    let Some(post) = shared_state.db.get_pending_post(id)? else {
+        return Ok("This post is no longer pending".to_string());
+    };
+    let role = member_role(bot, shared_state.db.as_ref(), &message.chat, q.from.id).await?;
+    let is_author = post.author_id == Some(q.from.id.0);
+    match action {
+        APPROVE => {
+            if role < Role::Poster {
+                return Ok("You need the poster role to approve posts".to_string());
+            }
+            if is_author {
+                return Ok("You cannot approve your own post".to_string());
+            }
+            let Some(post) = shared_state.db.approve_pending_post(id, q.from.id.0)? else {
+                return Ok("This post is no longer pending".to_string());
+            };
+            if (post.approvals.len() as u32) < post.approvals_required {
+                let text = preview_text(&post, &account_name(shared_state, &post).await?);
+                bot.edit_message_text(message.chat.id, message.id, text)
+                    .reply_markup(keyboard(id))
+                    .await?;
+                return Ok("Approved".to_string());
+            }
+            // Only the approval that takes the post out of the queue sends it.
+            let Some(post) = shared_state.db.take_pending_post(id)? else {
+                return Ok("Approved".to_string());
+            };
+            let kind = describe_tweet(&post.tweet);
+            let sent = send_post(
+                shared_state,
+                &post.chat_id,
+                &post.account_chat_id,
+                &post.x_id,
+                post.tweet,
+            )
+            .await;
+            let (text, undo) = match sent {
+                Ok((url, tweet_id)) => (
+                    format!("{} approved and sent: {}", kind, url),
+                    Some(undo_keyboard(Undo::Delete, &post.x_id, &tweet_id)),
+                ),
+                Err(e) => {
+                    log::error!("Failed to send approved post: {:?}", e);
+                    let reason = match e.downcast_ref::<TwitterError>() {
+                        Some(twitter_error) => twitter_error_message(twitter_error),
+                        None => e.to_string(),
+                    };
+                    let text = format!("{} approved, but sending it failed: {}", kind, reason);
+                    (text, None)
+                }
+            };
+            let edit = bot.edit_message_text(message.chat.id, message.id, text);
+            match undo {
+                Some(undo) => edit.reply_markup(undo).await?,
+                None => edit.await?,
+            };
+            Ok("Approved".to_string())
+        }
+        REJECT => {
+            if role < Role::Poster && !is_author {
+                return Ok("You need the poster role to reject posts".to_string());
+            }
+            let Some(post) = shared_state.db.take_pending_post(id)? else {
+                return Ok("This post is no longer pending".to_string());
+            };
+            let text = format!(
+                "{} rejected by {}:\n\n{}",
+                describe_tweet(&post.tweet),
+                q.from.full_name(),
+                post.tweet.text()
+            );
+            bot.edit_message_text(message.chat.id, message.id, text)
+                .await?;
+            Ok("Rejected".to_string())
+        }
+        _ => Ok("Unknown button".to_string()),
+    }
+}
+
+pub async fn callback_handler(
+    bot: Bot,
+    shared_state: SharedState,
+    q: CallbackQuery,
+) -> ResponseResult<()> {
+    let result = match q.data.as_deref().and_then(|data| data.split_once(':')) {
+        Some((UNDO, data)) => handle_undo(&bot, &shared_state, &q, data).await,
+        Some((SEND, data)) => handle_send_button(&bot, &shared_state, &q, data).await,
+        _ => handle_button(&bot, &shared_state, &q).await,
+    };
+    let answer = match result {
+        Ok(answer) => answer,
+        Err(e) => {
+            log::error!("Error handling button press: {:?}", e);
+            match e.downcast_ref::<TwitterError>() {
+                Some(twitter_error) => twitter_error_message(twitter_error),
+                None => "Something went wrong".to_string(),
+            }
+        }
+    };
+    bot.answer_callback_query(q.id).text(answer).await?;
+    Ok(())
+}
+
+/// Periodically drops posts that were not approved in time and tells their
+/// chats.
+pub async fn expire_pending_posts(shared_state: SharedState) {
+    let ttl_secs = shared_state.approval_ttl_secs;
+    let mut interval = tokio::time::interval(Duration::from_secs(ttl_secs.clamp(1, 60)));
+    loop {
+        interval.tick().await;
+        let created_before = unix_now().saturating_sub(ttl_secs);
+        let expired = match shared_state.db.take_expired_pending_posts(created_before) {
+            Ok(expired) => expired,
+            Err(e) => {
+                log::error!("Failed to expire pending posts: {:?}", e);
+                continue;
+            }
+        };
+        for (_, post) in expired {
+            let Ok(chat_id) = post.chat_id.parse().map(ChatId) else {
+                continue;
+            };
+            let text = format!(
+                "{} expired without enough approvals:\n\n{}",
+                describe_tweet(&post.tweet),
+                post.tweet.text()
+            );
+            if let Err(e) = shared_state.bot.send_message(chat_id, text).await {
+                log::warn!("Failed to notify chat {}: {:?}", post.chat_id, e);
+            }
+        }
+    }
+}
@@ -1,17 +1,27 @@
 use teloxide::{
     macros::BotCommands,
     requests::{Requester, ResponseResult},
-    types::Message,
+    types::{Message, UserId},
     utils::command::BotCommands as _,
-    Bot,
+    Bot, RequestError,
 };
 
-use super::twitter_commands;
+use super::{delegations, scheduled::MAX_SEND_DELAY_SECS, twitter_commands};
 use crate::{
-    endpoints::{complete_auth_flow, CallbackQuery, SharedState},
-    twitter,
+    accounts::{delegated_accounts, find_account, AccountCheck},
+    db::{AuthMethod, PendingAuth, Role},
+    durations::{format_duration, parse_duration},
+    endpoints::{complete_auth_flow, SharedState},
+    permissions::{default_role, is_chat_creator, member_role, require_role},
+    twitter::{
+        self,
+        auth::{Credentials, OOB_CALLBACK},
+        oauth2,
+    },
 };
 
+const CONNECTED_APPS_URL: &str = "https://x.com/settings/connected_apps";
+
 #[derive(BotCommands, Clone, Debug)]
 #[command(
     rename_rule = "lowercase",
@@ -20,22 +30,139 @@ use crate::{
 pub enum BasicCommand {
     #[command(description = "Show this help message")]
     Help,
-    #[command(description = "Get the current logged in user")]
-    Account,
-    #[command(description = "Remove the authenticated Twitter account from the chat")]
-    Logout,
-    #[command(description = "Authenticate a chat with Twitter")]
-    Auth,
-    #[command(description = "Pass the 0.0.0.0:3000 callback URL for auth completion")]
-    Prank(String),
+    #[command(
+        description = "Get the active Twitter account. Use /account refresh to check it with Twitter"
+    )]
+    Account(String),
+    #[command(description = "List the Twitter accounts linked to this chat")]
+    Accounts,
+    #[command(description = "Switch the active account by providing its handle")]
+    Use(String),
+    #[command(
+        description = "Revoke the bot's access and remove the active (or given @handle) Twitter account from the chat. Add keep to only unlink it"
+    )]
+    Logout(String),
+    #[command(
+        description = "Link a Twitter account to the chat. Use /auth oauth2 [scopes...] to log in with OAuth 2.0"
+    )]
+    Auth(String),
+    #[command(description = "Complete authentication with the PIN shown by Twitter")]
+    Verify(String),
+    #[command(
+        description = "Give a member a role (viewer, reacter, poster, or owner if you created the chat). Reply to their message or pass their user id first"
+    )]
+    Grant(String),
+    #[command(
+        description = "Remove a member's granted role. Reply to their message or pass their user id"
+    )]
+    Revoke(String),
+    #[command(description = "List the roles granted in this chat")]
+    Roles,
+    #[command(
+        description = "Show or set how many other members must approve a post before it is sent (0 to disable)"
+    )]
+    Approvals(String),
+    #[command(description = "Show, or turn on or off, refusing images without alt text")]
+    AltText(String),
+    #[command(
+        description = "Show or set how long tweets wait, with Cancel and Send now buttons, before they are sent (e.g. 30s, 2m or off)"
+    )]
+    SendDelay(String),
+    #[command(
+        description = "Let another chat use an account: /delegate [@handle] <duration> [max actions] [actions...]"
+    )]
+    Delegate(String),
+    #[command(
+        description = "Use an account delegated by another chat by providing the invite code"
+    )]
+    Join(String),
+    #[command(description = "List the delegations issued by or given to this chat")]
+    Delegations,
+    #[command(description = "Revoke a delegation by providing its code")]
+    Undelegate(String),
+    #[command(description = "Start the bot, or join a delegation from an invite link")]
+    Start(String),
+}
+
+impl BasicCommand {
+    fn required_role(&self) -> Role {
+        match self {
+            Self::Help | Self::Account(_) | Self::Accounts | Self::Roles => Role::Viewer,
+            Self::Start(code) if code.trim().is_empty() => Role::Viewer,
+            Self::Use(_)
+            | Self::Logout(_)
+            | Self::Auth(_)
+            | Self::Verify(_)
+            | Self::Grant(_)
+            | Self::Revoke(_)
+            | Self::Approvals(_)
+            | Self::AltText(_)
+            | Self::SendDelay(_)
+            | Self::Delegate(_)
+            | Self::Join(_)
+            | Self::Delegations
+            | Self::Undelegate(_)
+            | Self::Start(_) => Role::Owner,
+        }
+    }
+}
+
+/// Splits the target of `/grant` or `/revoke` off `args`: a leading numeric
+/// user id, or else the author of the message being replied to.
+fn role_target<'a>(msg: &Message, args: &'a str) -> (Option<u64>, Vec<&'a str>) {
+    let mut args: Vec<&str> = args.split_whitespace().collect();
+    if let Some(user_id) = args.first().and_then(|arg| arg.parse().ok()) {
+        args.remove(0);
+        return (Some(user_id), args);
+    }
+    let replied_to = msg
+        .reply_to_message()
+        .and_then(|reply| reply.from())
+        .map(|user| user.id.0);
+    (replied_to, args)
 }
 
+/// Whether the sender of `msg` may move `target` to `new_role`, or back to
+/// their default role when `None`. Moving anyone to or from owner takes the
+/// chat creator.
+async fn may_change_role(
+    bot: &Bot,
+    shared_state: &SharedState,
+    msg: &Message,
+    target: u64,
+    new_role: Option<Role>,
+) -> eyre::Result<bool> {
+    let target = UserId(target);
+    let current = member_role(bot, shared_state.db.as_ref(), &msg.chat, target).await?;
+    let new_role = match new_role {
+        Some(role) => role,
+        None => default_role(bot, &msg.chat, target).await?,
+    };
+    if current < Role::Owner && new_role < Role::Owner {
+        return Ok(true);
+    }
+    is_chat_creator(bot, msg).await
+}
+
+const OWNER_CHANGE_REFUSED: &str = "Only the chat creator can make or unmake owners";
+
 pub async fn command_handler(
     bot: Bot,
     shared_state: SharedState,
     msg: Message,
     cmd: BasicCommand,
 ) -> ResponseResult<()> {
+    let allowed = require_role(&bot, shared_state.db.as_ref(), &msg, cmd.required_role())
+        .await
+        .map_err(log_role_error)?;
+    if !allowed {
+        return Ok(());
+    }
+    // A plain /start is what Telegram sends when a chat first opens the bot.
+    let cmd = match cmd {
+        BasicCommand::Start(code) if code.trim().is_empty() => BasicCommand::Help,
+        cmd => cmd,
+    };
     match cmd {
         BasicCommand::Help => {
             let basic_command_descriptions = BasicCommand::descriptions().to_string();
@@ -47,66 +174,539 @@ pub async fn command_handler(
             );
             bot.send_message(msg.chat.id, all_descriptions).await?;
         }
-        BasicCommand::Auth => {
+        BasicCommand::Auth(args) => {
             let chat_id = msg.chat.id.to_string();
-            let db = shared_state.db.lock().await;
-            if db.access_tokens.contains_key(&chat_id) {
-                let user = db.access_tokens.get(&chat_id).unwrap();
-                let user_profile_url = format!("https://x.com/{}", user.username);
+            let (method, scopes) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+            let method = match method {
+                "" => AuthMethod::OAuth1,
+                method => match method.parse::<AuthMethod>() {
+                    Ok(method) => method,
+                    Err(_) => {
+                        bot.send_message(msg.chat.id, "Usage: /auth [oauth1|oauth2 [scopes...]]")
+                            .await?;
+                        return Ok(());
+                    }
+                },
+            };
+            if method == AuthMethod::OAuth2 {
+                let (Some(config), Some(_)) =
+                    (&shared_state.twitter.oauth2, &shared_state.callback_url)
+                else {
+                    bot.send_message(msg.chat.id, "OAuth 2.0 login is not enabled on this bot")
+                        .await?;
+                    return Ok(());
+                };
+                let scopes = match oauth2::parse_scopes(scopes) {
+                    Ok(scopes) => scopes,
+                    Err(e) => {
+                        bot.send_message(msg.chat.id, e.to_string()).await?;
+                        return Ok(());
+                    }
+                };
+                let pkce = oauth2::Pkce::generate();
+                let state = shared_state.state_signer.sign(msg.chat.id);
+                let url = match oauth2::authorize_url(config, &scopes, &state, &pkce.challenge) {
+                    Ok(url) => url,
+                    Err(e) => {
+                        log::error!("Failed to build authorize url: {:?}", e);
+                        bot.send_message(msg.chat.id, "Failed to start login, please try again")
+                            .await?;
+                        return Ok(());
+                    }
+                };
+                shared_state
+                    .db
+                    .insert_oauth_token(
+                        state,
+                        PendingAuth::new(AuthMethod::OAuth2, pkce.verifier, chat_id),
+                    )
+                    .map_err(log_db_error)?;
                 let to_send = format!(
-                    "You are already authenticated as: {}",
-                    user_profile_url.clone()
+                    "Please visit: {}\nThe link expires in {} minutes.",
+                    url,
+                    shared_state.oauth_token_ttl_secs / 60
                 );
-                log::info!("{}", to_send);
                 bot.send_message(msg.chat.id, to_send).await?;
-                drop(db);
             } else {
-                drop(db);
-                let token_pair = twitter::auth::request_oauth_token(chat_id).await.unwrap();
+                let oauth_callback = match &shared_state.callback_url {
+                    Some(callback_url) => format!(
+                        "{}/callback?state={}",
+                        callback_url,
+                        shared_state.state_signer.sign(msg.chat.id)
+                    ),
+                    None => OOB_CALLBACK.to_string(),
+                };
+                let token_pair = match twitter::auth::request_oauth_token(oauth_callback).await {
+                    Ok(token_pair) => token_pair,
+                    Err(e) => {
+                        log::error!("Failed to request oauth token: {:?}", e);
+                        bot.send_message(msg.chat.id, "Failed to start login, please try again")
+                            .await?;
+                        return Ok(());
+                    }
+                };
                 let url = format!(
                     "https://api.twitter.com/oauth/authenticate?oauth_token={}",
                     token_pair.token.clone()
                 );
-                let mut db = shared_state.db.lock().await;
-                db.oauth_tokens.insert(token_pair.token, token_pair.secret);
-                drop(db);
-                bot.send_message(msg.chat.id, format!("Please visit: {}", url))
-                    .await?;
+                shared_state
+                    .db
+                    .insert_oauth_token(
+                        token_pair.token,
+                        PendingAuth::new(AuthMethod::OAuth1, token_pair.secret, chat_id),
+                    )
+                    .map_err(log_db_error)?;
+                let next_step = if shared_state.callback_url.is_some() {
+                    ""
+                } else {
+                    "\nThen send /verify followed by the PIN Twitter shows you."
+                };
+                let to_send = format!(
+                    "Please visit: {}{}\nThe link expires in {} minutes.",
+                    url,
+                    next_step,
+                    shared_state.oauth_token_ttl_secs / 60
+                );
+                bot.send_message(msg.chat.id, to_send).await?;
             }
         }
-        BasicCommand::Prank(url) => {
-            let url = url::Url::parse(&url).expect("Failed to parse URL");
-            let query_params = url.query().expect("Failed to get query params");
-            let callback_query: CallbackQuery =
-                serde_qs::from_str(query_params).expect("Failed to parse query");
-            complete_auth_flow(shared_state, callback_query)
-                .await
-                .expect("Failed to complete auth flow");
+        BasicCommand::Verify(pin) => {
+            let pin = pin.trim().to_string();
+            if pin.is_empty() {
+                bot.send_message(msg.chat.id, "Usage: /verify <PIN>")
+                    .await?;
+                return Ok(());
+            }
+            let chat_id = msg.chat.id.to_string();
+            let pending = shared_state
+                .db
+                .take_latest_oauth_token(&chat_id, AuthMethod::OAuth1)
+                .map_err(log_db_error)?;
+            let Some((oauth_token, pending)) = pending else {
+                bot.send_message(msg.chat.id, "No login in progress, please run /auth first")
+                    .await?;
+                return Ok(());
+            };
+            let res =
+                complete_auth_flow(shared_state, msg.chat.id, oauth_token, Some(pending), pin)
+                    .await;
+            if let Err(e) = res {
+                log::error!("Failed to verify PIN: {:?}", e);
+                bot.send_message(
+                    msg.chat.id,
+                    "Could not verify the PIN, please run /auth again",
+                )
+                .await?;
+            }
         }
-        BasicCommand::Logout => {
+        BasicCommand::Logout(args) => {
+            let mut keep = false;
+            let mut handle = None;
+            for arg in args.split_whitespace() {
+                match arg {
+                    "keep" => keep = true,
+                    arg if arg.starts_with('@') && handle.is_none() => handle = Some(arg),
+                    _ => {
+                        bot.send_message(msg.chat.id, "Usage: /logout [@handle] [keep]")
+                            .await?;
+                        return Ok(());
+                    }
+                }
+            }
             let chat_id = msg.chat.id.to_string();
-            let mut db = shared_state.db.lock().await;
-            db.access_tokens.remove(&chat_id);
-            drop(db);
-            bot.send_message(msg.chat.id, "Successfully logged out")
+            let target = match handle {
+                Some(handle) => {
+                    let users = shared_state
+                        .db
+                        .get_chat_users(&chat_id)
+                        .map_err(log_db_error)?;
+                    find_account(users, handle)
+                }
+                None => shared_state
+                    .db
+                    .get_active_user(&chat_id)
+                    .map_err(log_db_error)?,
+            };
+            let Some(target) = target else {
+                bot.send_message(
+                    msg.chat.id,
+                    "No such Twitter account is linked to this chat.",
+                )
                 .await?;
+                return Ok(());
+            };
+            let user = shared_state
+                .db
+                .remove_user(&chat_id, &target.x_id)
+                .map_err(log_db_error)?;
+            let Some(user) = user else {
+                bot.send_message(msg.chat.id, "No Twitter account is currently logged in.")
+                    .await?;
+                return Ok(());
+            };
+            // OAuth 1.0a access tokens are the same for every link of an
+            // account, so revoking one would log out the other chats too.
+            let other_links = match user.credentials {
+                Credentials::OAuth1(_) => shared_state
+                    .db
+                    .list_users()
+                    .map_err(log_db_error)?
+                    .into_iter()
+                    .filter(|(_, other)| {
+                        other.x_id == user.x_id
+                            && matches!(other.credentials, Credentials::OAuth1(_))
+                    })
+                    .count(),
+                Credentials::OAuth2(_) => 0,
+            };
+            let to_send = if keep {
+                "Successfully logged out, the bot's access on Twitter was kept".to_string()
+            } else if other_links > 0 {
+                format!(
+                    "Logged out. The bot's access on Twitter was kept because @{} is still linked in {} other chat(s), which revoking it would log out as well",
+                    user.username, other_links
+                )
+            } else {
+                match shared_state
+                    .twitter
+                    .with_auth(user.credentials)
+                    .invalidate_token()
+                    .await
+                {
+                    Ok(()) => "Successfully logged out and revoked the bot's access".to_string(),
+                    Err(e) => {
+                        log::error!("Failed to revoke token of {}: {:?}", user.username, e);
+                        format!(
+                            "Logged out, but Twitter did not confirm the revocation. \
+                             You can revoke access manually at {}",
+                            CONNECTED_APPS_URL
+                        )
+                    }
+                }
+            };
+            bot.send_message(msg.chat.id, to_send).await?;
         }
-        BasicCommand::Account => {
+        BasicCommand::Account(args) => {
+            let refresh = match args.trim() {
+                "" => false,
+                "refresh" => true,
+                _ => {
+                    bot.send_message(msg.chat.id, "Usage: /account [refresh]")
+                        .await?;
+                    return Ok(());
+                }
+            };
             let chat_id = msg.chat.id.to_string();
-            let db = shared_state.db.lock().await;
-            let user = db.access_tokens.get(&chat_id).map(|u| u.clone());
-            drop(db);
-            if user.is_none() {
+            let user = shared_state
+                .db
+                .get_active_user(&chat_id)
+                .map_err(log_db_error)?;
+            let Some(mut user) = user else {
                 bot.send_message(msg.chat.id, "No Twitter account is currently logged in.")
                     .await?;
                 return Ok(());
+            };
+            if refresh {
+                match shared_state.validate_account(&chat_id, user.clone()).await {
+                    Ok(check) => {
+                        if let Some(notification) = check.notification() {
+                            bot.send_message(msg.chat.id, notification).await?;
+                        }
+                        user = match check {
+                            AccountCheck::Unchanged(user)
+                            | AccountCheck::Updated { user, .. }
+                            | AccountCheck::Revoked(user) => user,
+                        };
+                    }
+                    Err(e) => {
+                        log::error!("Failed to refresh account: {:?}", e);
+                        bot.send_message(
+                            msg.chat.id,
+                            "Could not reach Twitter, showing the last known account",
+                        )
+                        .await?;
+                    }
+                }
             }
-            let user = user.unwrap();
             let user_profile_url = format!("https://x.com/{}", user.username);
-            let to_send = format!("You are authenticated as: {}", user_profile_url);
+            let to_send = if user.revoked {
+                format!(
+                    "Access to {} was revoked, run /auth to link it again",
+                    user_profile_url
+                )
+            } else {
+                format!("You are authenticated as: {}", user_profile_url)
+            };
+            bot.send_message(msg.chat.id, to_send).await?;
+        }
+        BasicCommand::Accounts => {
+            let chat_id = msg.chat.id.to_string();
+            let users = shared_state
+                .db
+                .get_chat_users(&chat_id)
+                .map_err(log_db_error)?;
+            let delegated =
+                delegated_accounts(shared_state.db.as_ref(), &chat_id).map_err(log_db_error)?;
+            if users.is_empty() && delegated.is_empty() {
+                bot.send_message(msg.chat.id, "No Twitter account is currently logged in.")
+                    .await?;
+                return Ok(());
+            }
+            let active = shared_state
+                .db
+                .get_active_user(&chat_id)
+                .map_err(log_db_error)?
+                .map(|user| user.x_id);
+            let mut lines: Vec<String> = users
+                .iter()
+                .map(|user| {
+                    let mut line = format!("@{}", user.username);
+                    if active.as_ref() == Some(&user.x_id) {
+                        line.push_str(" (active)");
+                    }
+                    if user.revoked {
+                        line.push_str(" (revoked)");
+                    }
+                    line
+                })
+                .collect();
+            lines.extend(
+                delegated
+                    .iter()
+                    .map(|account| format!("@{} (delegated)", account.user.username)),
+            );
+            let to_send = format!("Linked accounts:\n{}", lines.join("\n"));
+            bot.send_message(msg.chat.id, to_send).await?;
+        }
+        BasicCommand::Use(handle) => {
+            let handle = handle.trim();
+            if handle.is_empty() {
+                bot.send_message(msg.chat.id, "Usage: /use <handle>")
+                    .await?;
+                return Ok(());
+            }
+            let chat_id = msg.chat.id.to_string();
+            let users = shared_state
+                .db
+                .get_chat_users(&chat_id)
+                .map_err(log_db_error)?;
+            let Some(user) = find_account(users, handle) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "No such Twitter account is linked to this chat.",
+                )
+                .await?;
+                return Ok(());
+            };
+            shared_state
+                .db
+                .set_active_user(&chat_id, &user.x_id)
+                .map_err(log_db_error)?;
+            let to_send = format!("Now using https://x.com/{}", user.username);
+            bot.send_message(msg.chat.id, to_send).await?;
+        }
+        BasicCommand::Grant(args) => {
+            let (target, args) = role_target(&msg, &args);
+            let role = match args.as_slice() {
+                [role] => role.parse::<Role>().ok(),
+                _ => None,
+            };
+            let (Some(target), Some(role)) = (target, role) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Usage: reply to a member with /grant <role>, or /grant <user id> <role>",
+                )
+                .await?;
+                return Ok(());
+            };
+            let allowed = may_change_role(&bot, &shared_state, &msg, target, Some(role))
+                .await
+                .map_err(log_role_error)?;
+            if !allowed {
+                bot.send_message(msg.chat.id, OWNER_CHANGE_REFUSED).await?;
+                return Ok(());
+            }
+            shared_state
+                .db
+                .set_role(&msg.chat.id.to_string(), target, role)
+                .map_err(log_db_error)?;
+            let to_send = format!("User {} is now a {}", target, role.as_str());
+            bot.send_message(msg.chat.id, to_send).await?;
+        }
+        BasicCommand::Revoke(args) => {
+            let (target, args) = role_target(&msg, &args);
+            let Some(target) = target.filter(|_| args.is_empty()) else {
+                bot.send_message(
+                    msg.chat.id,
+                    "Usage: reply to a member with /revoke, or /revoke <user id>",
+                )
+                .await?;
+                return Ok(());
+            };
+            let allowed = may_change_role(&bot, &shared_state, &msg, target, None)
+                .await
+                .map_err(log_role_error)?;
+            if !allowed {
+                bot.send_message(msg.chat.id, OWNER_CHANGE_REFUSED).await?;
+                return Ok(());
+            }
+            let removed = shared_state
+                .db
+                .remove_role(&msg.chat.id.to_string(), target)
+                .map_err(log_db_error)?;
+            let to_send = if removed {
+                format!("Removed the role granted to user {}", target)
+            } else {
+                format!("User {} has no granted role", target)
+            };
+            bot.send_message(msg.chat.id, to_send).await?;
+        }
+        BasicCommand::Roles => {
+            let roles = shared_state
+                .db
+                .list_roles(&msg.chat.id.to_string())
+                .map_err(log_db_error)?;
+            let mut lines: Vec<String> = roles
+                .iter()
+                .map(|(user_id, role)| format!("{}: {}", user_id, role.as_str()))
+                .collect();
+            if lines.is_empty() {
+                lines.push("No roles granted.".to_string());
+            }
+            let to_send = format!(
+                "{}\nChat admins without a granted role are owners, everyone else is a viewer.",
+                lines.join("\n")
+            );
+            bot.send_message(msg.chat.id, to_send).await?;
+        }
+        BasicCommand::Approvals(args) => {
+            let chat_id = msg.chat.id.to_string();
+            let mut settings = shared_state
+                .db
+                .get_chat_settings(&chat_id)
+                .map_err(log_db_error)?;
+            let args = args.trim();
+            if !args.is_empty() {
+                let Ok(approvals_required) = args.parse() else {
+                    bot.send_message(msg.chat.id, "Usage: /approvals [number]")
+                        .await?;
+                    return Ok(());
+                };
+                settings.approvals_required = approvals_required;
+                shared_state
+                    .db
+                    .set_chat_settings(&chat_id, settings.clone())
+                    .map_err(log_db_error)?;
+            }
+            let to_send = match settings.approvals_required {
+                0 => "Posts are sent without approval".to_string(),
+                n => format!("Posts need {} approval(s) from other members", n),
+            };
             bot.send_message(msg.chat.id, to_send).await?;
         }
+        BasicCommand::AltText(args) => {
+            let chat_id = msg.chat.id.to_string();
+            let mut settings = shared_state
+                .db
+                .get_chat_settings(&chat_id)
+                .map_err(log_db_error)?;
+            let require_alt_text = match args.trim().to_lowercase().as_str() {
+                "" => None,
+                "on" => Some(true),
+                "off" => Some(false),
+                _ => {
+                    bot.send_message(msg.chat.id, "Usage: /alttext [on|off]")
+                        .await?;
+                    return Ok(());
+                }
+            };
+            if let Some(require_alt_text) = require_alt_text {
+                settings.require_alt_text = require_alt_text;
+                shared_state
+                    .db
+                    .set_chat_settings(&chat_id, settings.clone())
+                    .map_err(log_db_error)?;
+            }
+            let to_send = if settings.require_alt_text {
+                "Images need alt text. Add a line \"alt: <description>\" per image to the caption."
+            } else {
+                "Images can be posted without alt text. Add a line \"alt: <description>\" per image to the caption to describe them."
+            };
+            bot.send_message(msg.chat.id, to_send).await?;
+        }
+        BasicCommand::SendDelay(args) => {
+            let chat_id = msg.chat.id.to_string();
+            let mut settings = shared_state
+                .db
+                .get_chat_settings(&chat_id)
+                .map_err(log_db_error)?;
+            let send_delay_secs = match args.trim().to_lowercase().as_str() {
+                "" => None,
+                "off" | "0" => Some(0),
+                args => match parse_duration(args) {
+                    Some(secs) if secs <= MAX_SEND_DELAY_SECS => Some(secs as u32),
+                    _ => {
+                        let to_send = format!(
+                            "Usage: /senddelay [duration|off], at most {}",
+                            format_duration(MAX_SEND_DELAY_SECS)
+                        );
+                        bot.send_message(msg.chat.id, to_send).await?;
+                        return Ok(());
+                    }
+                },
+            };
+            if let Some(send_delay_secs) = send_delay_secs {
+                settings.send_delay_secs = send_delay_secs;
+                shared_state
+                    .db
+                    .set_chat_settings(&chat_id, settings.clone())
+                    .map_err(log_db_error)?;
+            }
+            let to_send = match settings.send_delay_secs {
+                0 => "Tweets are sent right away".to_string(),
+                secs => format!(
+                    "Tweets wait {} before they are sent and can be cancelled meanwhile",
+                    format_duration(secs as u64)
+                ),
+            };
+            bot.send_message(msg.chat.id, to_send).await?;
+        }
+        BasicCommand::Delegate(args) => {
+            delegations::delegate(&bot, &shared_state, &msg, &args)
+                .await
+                .map_err(log_command_error)?;
+        }
+        BasicCommand::Join(code) | BasicCommand::Start(code) => {
+            delegations::join(&bot, &shared_state, &msg, code.trim())
+                .await
+                .map_err(log_command_error)?;
+        }
+        BasicCommand::Delegations => {
+            delegations::list(&bot, &shared_state, &msg)
+                .await
+                .map_err(log_command_error)?;
+        }
+        BasicCommand::Undelegate(code) => {
+            delegations::undelegate(&bot, &shared_state, &msg, code.trim())
+                .await
+                .map_err(log_command_error)?;
+        }
     };
 
     Ok(())
 }
+
+fn log_command_error(e: eyre::Report) -> RequestError {
+    log::error!("Error handling command: {:?}", e);
+    RequestError::Io(std::io::Error::other(e.to_string()))
+}
+
+fn log_role_error(e: eyre::Report) -> RequestError {
+    log::error!("Failed to check role: {:?}", e);
+    RequestError::Io(std::io::Error::other(e.to_string()))
+}
+
+fn log_db_error(e: eyre::Report) -> RequestError {
+    log::error!("Database error: {:?}", e);
+    RequestError::Io(std::io::Error::other(e.to_string()))
+}
@@ -0,0 +1,209 @@
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use teloxide::{requests::Requester, types::Message, Bot};
+
+use crate::{
+    accounts::find_account,
+    db::{unix_now, Action, Delegation},
+    durations::{format_duration, parse_duration},
+    endpoints::SharedState,
+};
+
+const DELEGATE_USAGE: &str =
+    "Usage: /delegate [@handle] <duration, e.g. 12h or 7d> [max actions] [actions...]\n\
+     Actions are tweet, reply, quote, like, retweet and delete; all but delete if none are given.";
+
+fn describe(shared_state: &SharedState, code: &str, delegation: &Delegation) -> String {
+    let username = shared_state
+        .db
+        .get_chat_users(&delegation.owner_chat_id)
+        .ok()
+        .and_then(|users| users.into_iter().find(|u| u.x_id == delegation.x_id))
+        .map(|user| format!("@{}", user.username))
+        .unwrap_or_else(|| "an unlinked account".to_string());
+    let actions: Vec<&str> = delegation.actions.iter().map(Action::as_str).collect();
+    let used = match delegation.max_actions {
+        Some(max) => format!("{}/{} actions used", delegation.actions_used, max),
+        None => format!("{} actions used", delegation.actions_used),
+    };
+    let expiry = match delegation.expires_at.checked_sub(unix_now()) {
+        Some(left) if left > 0 => format!("expires in {}", format_duration(left)),
+        _ => "expired".to_string(),
+    };
+    let redeemed = match &delegation.delegate_chat_id {
+        Some(chat_id) => format!("used by chat {}", chat_id),
+        None => "not redeemed yet".to_string(),
+    };
+    format!(
+        "{}: {} ({}), {}, {}, {}",
+        code,
+        username,
+        actions.join(", "),
+        used,
+        expiry,
+        redeemed
+    )
+}
+
+/// `/delegate`: mints an invite code for one of the chat's accounts.
+pub async fn delegate(
+    bot: &Bot,
+    shared_state: &SharedState,
+    msg: &Message,
+    args: &str,
+) -> eyre::Result<()> {
+    let chat_id = msg.chat.id.to_string();
+    let mut args = args.split_whitespace().peekable();
+    let user = match args.peek() {
+        Some(handle) if handle.starts_with('@') => {
+            let user = find_account(shared_state.db.get_chat_users(&chat_id)?, handle);
+            args.next();
+            user
+        }
+        _ => shared_state.db.get_active_user(&chat_id)?,
+    };
+    let Some(user) = user else {
+        bot.send_message(
+            msg.chat.id,
+            "No such Twitter account is linked to this chat.",
+        )
+        .await?;
+        return Ok(());
+    };
+    let Some(ttl_secs) = args.next().and_then(parse_duration) else {
+        bot.send_message(msg.chat.id, DELEGATE_USAGE).await?;
+        return Ok(());
+    };
+    let mut max_actions = None;
+    let mut actions = Vec::new();
+    for arg in args {
+        if let Ok(max) = arg.parse::<u32>() {
+            if max_actions.replace(max).is_some() || !actions.is_empty() {
+                bot.send_message(msg.chat.id, DELEGATE_USAGE).await?;
+                return Ok(());
+            }
+            continue;
+        }
+        match arg.parse::<Action>() {
+            Ok(action) if !actions.contains(&action) => actions.push(action),
+            Ok(_) => {}
+            Err(_) => {
+                bot.send_message(msg.chat.id, DELEGATE_USAGE).await?;
+                return Ok(());
+            }
+        }
+    }
+    if actions.is_empty() {
+        // Deleting can remove tweets the delegate never posted, so it has to
+        // be granted explicitly.
+        actions = Action::ALL
+            .iter()
+            .copied()
+            .filter(|action| *action != Action::Delete)
+            .collect();
+    }
+    actions.sort();
+
+    let code = format!("{:016x}", OsRng.next_u64());
+    let delegation = Delegation {
+        owner_chat_id: chat_id,
+        x_id: user.x_id.clone(),
+        delegate_chat_id: None,
+        actions,
+        expires_at: unix_now() + ttl_secs,
+        max_actions,
+        actions_used: 0,
+    };
+    let description = describe(shared_state, &code, &delegation);
+    shared_state
+        .db
+        .insert_delegation(code.clone(), delegation)?;
+    let to_send = format!(
+        "Created delegation {}\n\nShare https://t.me/{}?start={} or have the other chat send /join {}",
+        description, shared_state.bot_name, code, code
+    );
+    bot.send_message(msg.chat.id, to_send).await?;
+    Ok(())
+}
+
+/// `/join` and `/start` with a code: redeems a delegation in this chat.
+pub async fn join(
+    bot: &Bot,
+    shared_state: &SharedState,
+    msg: &Message,
+    code: &str,
+) -> eyre::Result<()> {
+    let chat_id = msg.chat.id.to_string();
+    let owned = shared_state
+        .db
+        .get_delegation(code)?
+        .is_some_and(|d| d.owner_chat_id == chat_id);
+    if owned {
+        bot.send_message(msg.chat.id, "This delegation was issued by this chat")
+            .await?;
+        return Ok(());
+    }
+    let Some(delegation) = shared_state.db.redeem_delegation(code, &chat_id)? else {
+        bot.send_message(
+            msg.chat.id,
+            "This invite code is unknown, expired or was already used",
+        )
+        .await?;
+        return Ok(());
+    };
+    let to_send = format!(
+        "Joined delegation {}\nStart commands with the account's @handle to use it.",
+        describe(shared_state, code, &delegation)
+    );
+    bot.send_message(msg.chat.id, to_send).await?;
+    Ok(())
+}
+
+/// `/delegations`: lists what this chat issued and what it was given.
+pub async fn list(bot: &Bot, shared_state: &SharedState, msg: &Message) -> eyre::Result<()> {
+    let chat_id = msg.chat.id.to_string();
+    let (issued, received): (Vec<_>, Vec<_>) = shared_state
+        .db
+        .list_delegations(&chat_id)?
+        .into_iter()
+        .partition(|(_, d)| d.owner_chat_id == chat_id);
+    let mut sections = Vec::new();
+    for (title, delegations) in [("Issued", issued), ("Received", received)] {
+        if delegations.is_empty() {
+            continue;
+        }
+        let lines: Vec<String> = delegations
+            .iter()
+            .map(|(code, d)| describe(shared_state, code, d))
+            .collect();
+        sections.push(format!("{}:\n{}", title, lines.join("\n")));
+    }
+    let to_send = if sections.is_empty() {
+        "No delegations.".to_string()
+    } else {
+        sections.join("\n\n")
+    };
+    bot.send_message(msg.chat.id, to_send).await?;
+    Ok(())
+}
+
+/// `/undelegate`: revokes a delegation issued by or given to this chat.
+pub async fn undelegate(
+    bot: &Bot,
+    shared_state: &SharedState,
+    msg: &Message,
+    code: &str,
+) -> eyre::Result<()> {
+    let chat_id = msg.chat.id.to_string();
+    let involved = shared_state.db.get_delegation(code)?.is_some_and(|d| {
+        d.owner_chat_id == chat_id || d.delegate_chat_id.as_deref() == Some(chat_id.as_str())
+    });
+    if !involved {
+        bot.send_message(msg.chat.id, "Usage: /undelegate <code from /delegations>")
+            .await?;
+        return Ok(());
+    }
+    shared_state.db.remove_delegation(code)?;
+    bot.send_message(msg.chat.id, format!("Revoked delegation {}", code))
+        .await?;
+    Ok(())
+}
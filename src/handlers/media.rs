@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{future::try_join_all, StreamExt};
+use teloxide::{
+    net::Download,
+    requests::{Requester, ResponseResult},
+    types::{FileMeta, Message},
+    utils::command::BotCommands,
+    Bot,
+};
+use tokio::time::Instant;
+
+use crate::{
+    endpoints::SharedState,
+    handlers::twitter_commands::{strip_alt_prefix, twitter_command_handler, TwitterCommand},
+    twitter::media::{MediaFile, MAX_IMAGES},
+};
+
+/// The Bot API does not let bots download larger files.
+const MAX_DOWNLOAD_BYTES: u32 = 20 * 1024 * 1024;
+/// How long to wait for more messages of an album after the last one.
+const ALBUM_WINDOW: Duration = Duration::from_millis(1500);
+
+/// The file attached to `msg`, with the MIME type and duration Telegram
+/// reported. Photos come in several sizes, of which the largest is used.
+fn attachment(msg: &Message) -> Option<(&FileMeta, Option<String>, Option<u32>)> {
+    if let Some(photos) = msg.photo() {
+        let photo = photos.iter().max_by_key(|p| p.file.size)?;
+        return Some((&photo.file, None, None));
+    }
+    if let Some(video) = msg.video() {
+        let mime_type = video.mime_type.as_ref().map(|m| m.to_string());
+        return Some((&video.file, mime_type, Some(video.duration)));
+    }
+    // Animations are also sent as documents, so they are checked first.
+    if let Some(animation) = msg.animation() {
+        let mime_type = animation.mime_type.as_ref().map(|m| m.to_string());
+        return Some((&animation.file, mime_type, Some(animation.duration)));
+    }
+    if let Some(document) = msg.document() {
+        let mime_type = document.mime_type.as_ref().map(|m| m.to_string());
+        return Some((&document.file, mime_type, None));
+    }
+    None
+}
+
+pub fn has_attachment(msg: Message) -> bool {
+    attachment(&msg).is_some()
+}
+
+async fn download(bot: &Bot, file: &FileMeta) -> eyre::Result<Vec<u8>> {
+    let file = bot.get_file(&file.id).await?;
+    let mut stream = bot.download_file_stream(&file.path);
+    let mut bytes = Vec::with_capacity(file.size as usize);
+    while let Some(chunk) = stream.next().await {
+        bytes.extend_from_slice(&chunk?);
+    }
+    Ok(bytes)
+}
+
+/// Downloads the file attached to `msg`.
+async fn download_attachment(bot: &Bot, msg: &Message) -> eyre::Result<MediaFile> {
+    let (file, mime_type, duration_secs) =
+        attachment(msg).ok_or_else(|| eyre::eyre!("The message has no file"))?;
+    Ok(MediaFile {
+        bytes: download(bot, file).await?,
+        mime_type,
+        duration_secs,
+        alt_text: None,
+    })
+}
+
+#[derive(Debug)]
+struct PendingAlbum {
+    messages: Vec<Message>,
+    last_seen: Instant,
+}
+
+/// Gathers the messages of Telegram albums, which arrive one by one with a
+/// shared `media_group_id`.
+#[derive(Debug, Clone, Default)]
+pub struct AlbumCollector {
+    albums: Arc<Mutex<HashMap<String, PendingAlbum>>>,
+}
+
+impl AlbumCollector {
+    /// Adds `msg` to its album, returning whether it is the album's first
+    /// message.
+    fn add(&self, group_id: &str, msg: Message) -> bool {
+        let mut albums = self.albums.lock().unwrap_or_else(|e| e.into_inner());
+        match albums.get_mut(group_id) {
+            Some(album) => {
+                album.messages.push(msg);
+                album.last_seen = Instant::now();
+                false
+            }
+            None => {
+                let album = PendingAlbum {
+                    messages: vec![msg],
+                    last_seen: Instant::now(),
+                };
+                albums.insert(group_id.to_string(), album);
+                true
+            }
+        }
+    }
+
+    /// Waits until no message joined the album for [`ALBUM_WINDOW`], then
+    /// takes its messages in the order they were sent.
+    async fn collect(&self, group_id: &str) -> Vec<Message> {
+        loop {
+            let deadline = {
+                let mut albums = self.albums.lock().unwrap_or_else(|e| e.into_inner());
+                let Some(album) = albums.get(group_id) else {
+                    return Vec::new();
+                };
+                let deadline = album.last_seen + ALBUM_WINDOW;
+                if deadline <= Instant::now() {
+                    let mut messages = albums
+                        .remove(group_id)
+                        .map(|album| album.messages)
+                        .unwrap_or_default();
+                    messages.sort_by_key(|msg| msg.id.0);
+                    return messages;
+                }
+                deadline
+            };
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+}
+
+/// Runs the Twitter command in the caption of a photo, video, GIF or file,
+/// attaching the file to the tweet. The messages of an album are collected
+/// first and attached together.
+pub async fn media_message_handler(
+    bot: Bot,
+    msg: Message,
+    shared_state: SharedState,
+) -> ResponseResult<()> {
+    let Some(group_id) = msg.media_group_id().map(str::to_string) else {
+        return handle_album(bot, shared_state, vec![msg]).await;
+    };
+    // Updates from one chat are handled in order, so the rest of the album
+    // only arrives once this handler returns.
+    if shared_state.albums.add(&group_id, msg) {
+        tokio::spawn(async move {
+            let messages = shared_state.albums.collect(&group_id).await;
+            if let Err(e) = handle_album(bot, shared_state, messages).await {
+                log::error!("Error handling album: {:?}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_album(
+    bot: Bot,
+    shared_state: SharedState,
+    messages: Vec<Message>,
+) -> ResponseResult<()> {
+    let Some((cmd, msg)) = messages.iter().find_map(|msg| {
+        let cmd = TwitterCommand::parse(msg.caption()?, &shared_state.bot_name).ok()?;
+        Some((cmd, msg.clone()))
+    }) else {
+        return Ok(());
+    };
+    // A thread spreads an album over several tweets.
+    if !matches!(cmd, TwitterCommand::Thread(_)) && messages.len() > MAX_IMAGES {
+        let to_send = format!(
+            "A tweet can have at most {} images, this album has {}",
+            MAX_IMAGES,
+            messages.len()
+        );
+        bot.send_message(msg.chat.id, to_send).await?;
+        return Ok(());
+    }
+    let too_large = messages
+        .iter()
+        .filter_map(attachment)
+        .any(|(file, _, _)| file.size > MAX_DOWNLOAD_BYTES);
+    if too_large {
+        bot.send_message(
+            msg.chat.id,
+            "Telegram only lets bots download files of up to 20 MB",
+        )
+        .await?;
+        return Ok(());
+    }
+    let downloads = messages.iter().map(|msg| download_attachment(&bot, msg));
+    let mut media = match try_join_all(downloads).await {
+        Ok(media) => media,
+        Err(e) => {
+            log::error!("Failed to download media: {:?}", e);
+            bot.send_message(msg.chat.id, "Failed to download the file from Telegram")
+                .await?;
+            return Ok(());
+        }
+    };
+    // Other captions in an album describe their own image.
+    for (file, item) in media.iter_mut().zip(&messages) {
+        if item.id == msg.id {
+            continue;
+        }
+        if let Some(caption) = item.caption().map(str::trim).filter(|c| !c.is_empty()) {
+            let alt_text = strip_alt_prefix(caption).unwrap_or(caption);
+            file.alt_text = Some(alt_text.trim().to_string());
+        }
+    }
+    if let Err(e) = twitter_command_handler(bot, shared_state, cmd, msg, media).await {
+        log::error!("Error handling twitter command: {:?}", e);
+    }
+    Ok(())
+}
@@ -1,2 +1,8 @@
+pub mod approvals;
 pub mod basic_commands;
+pub mod delegations;
+pub mod media;
+pub mod scheduled;
+pub mod threads;
 pub mod twitter_commands;
+pub mod undo;
@@ -0,0 +1,390 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use teloxide::{
+    payloads::{EditMessageTextSetters, SendMessageSetters},
+    requests::Requester,
+    types::{
+        CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId,
+    },
+    ApiError, Bot, RequestError,
+};
+
+use crate::{
+    accounts::ActingAccount,
+    db::{unix_now, Role, ScheduledSend},
+    durations::format_duration,
+    endpoints::SharedState,
+    handlers::{
+        approvals::{describe_tweet, new_post_id, send_post},
+        twitter_commands::twitter_error_message,
+        undo::{undo_keyboard, Undo},
+    },
+    permissions::member_role,
+    twitter::{error::TwitterError, tweet::Tweet},
+};
+
+/// Prefix of the callback data of Cancel and Send now buttons.
+pub const SEND: &str = "send";
+const CANCEL: &str = "cancel";
+const NOW: &str = "now";
+
+/// The longest send delay a chat can set.
+pub const MAX_SEND_DELAY_SECS: u64 = 15 * 60;
+/// How often the countdown message is updated.
+const COUNTDOWN_INTERVAL_SECS: u64 = 10;
+/// Sends that were due longer ago than this when the bot starts are cancelled
+/// instead of being posted late.
+const MAX_LATE_SECS: u64 = 10 * 60;
+
+/// What the message of a send taken out of the queue ends up showing.
+#[derive(Debug, Clone)]
+struct FinalText {
+    chat_id: ChatId,
+    message_id: MessageId,
+    text: String,
+    keyboard: Option<InlineKeyboardMarkup>,
+}
+
+/// The running countdowns, by send id, with the final text of those whose
+/// send was taken out of the queue. A countdown update can land after the
+/// edit of whoever took the send, so the countdown puts that text back when
+/// it finds its send gone.
+#[derive(Debug, Clone, Default)]
+pub struct Countdowns {
+    running: Arc<Mutex<HashMap<String, Option<FinalText>>>>,
+}
+
+impl Countdowns {
+    fn running(&self) -> std::sync::MutexGuard<'_, HashMap<String, Option<FinalText>>> {
+        self.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn start(&self, id: &str) {
+        self.running().insert(id.to_string(), None);
+    }
+
+    /// Records the final text of `id` if its countdown may still edit it.
+    fn finish(&self, id: &str, text: &FinalText) {
+        if let Some(entry) = self.running().get_mut(id) {
+            *entry = Some(text.clone());
+        }
+    }
+
+    /// Forgets the countdown of `id`, returning the final text recorded for
+    /// it.
+    fn stop(&self, id: &str) -> Option<FinalText> {
+        self.running().remove(id).flatten()
+    }
+}
+
+/// Shows `text` on the message of a send that was taken out of the queue.
+async fn show_final(bot: &Bot, text: &FinalText) -> Result<(), RequestError> {
+    let edit = bot.edit_message_text(text.chat_id, text.message_id, text.text.clone());
+    match text.keyboard.clone() {
+        Some(keyboard) => edit.reply_markup(keyboard).await?,
+        None => edit.await?,
+    };
+    Ok(())
+}
+
+/// Replaces the countdown of the send `id`, which the caller took out of the
+/// queue, with `text`.
+async fn finish_send(
+    shared_state: &SharedState,
+    id: &str,
+    text: FinalText,
+) -> Result<(), RequestError> {
+    shared_state.countdowns.finish(id, &text);
+    show_final(&shared_state.bot, &text).await
+}
+
+fn keyboard(id: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback("Cancel", format!("{}:{}:{}", SEND, CANCEL, id)),
+        InlineKeyboardButton::callback("Send now", format!("{}:{}:{}", SEND, NOW, id)),
+    ]])
+}
+
+fn countdown_text(tweet: &Tweet, username: &str, left_secs: u64) -> String {
+    format!(
+        "{} as @{} goes out in {}:\n\n{}",
+        describe_tweet(tweet),
+        username,
+        format_duration(left_secs),
+        tweet.text()
+    )
+}
+
+fn account_name(shared_state: &SharedState, send: &ScheduledSend) -> eyre::Result<String> {
+    Ok(shared_state
+        .db
+        .get_chat_users(&send.account_chat_id)?
+        .into_iter()
+        .find(|user| user.x_id == send.x_id)
+        .map(|user| user.username)
+        .unwrap_or_else(|| "unlinked account".to_string()))
+}
+
+fn chat_message(send: &ScheduledSend) -> eyre::Result<(ChatId, MessageId)> {
+    Ok((ChatId(send.chat_id.parse()?), MessageId(send.message_id)))
+}
+
+/// Holds `tweet` for `delay_secs` with a countdown message that lets members
+/// cancel it or send it right away.
+pub async fn schedule_send(
+    bot: &Bot,
+    shared_state: &SharedState,
+    msg: &Message,
+    account: &ActingAccount,
+    tweet: Tweet,
+    delay_secs: u32,
+) -> eyre::Result<()> {
+    let id = new_post_id();
+    let send_at = unix_now() + delay_secs as u64;
+    let kind = describe_tweet(&tweet);
+    let text = countdown_text(&tweet, &account.user.username, delay_secs as u64);
+    let message = bot
+        .send_message(msg.chat.id, text)
+        .reply_markup(keyboard(&id))
+        .await?;
+    let send = ScheduledSend {
+        chat_id: msg.chat.id.to_string(),
+        account_chat_id: account.chat_id.clone(),
+        author_id: msg.from().map(|from| from.id.0),
+        x_id: account.user.x_id.clone(),
+        tweet,
+        send_at,
+        message_id: message.id.0,
+    };
+    // The countdown has to be sent first for its id, so take its buttons
+    // away again if the send cannot be saved.
+    if let Err(e) = shared_state.db.insert_scheduled_send(id.clone(), send) {
+        let text = format!("{} was not sent, it could not be saved", kind);
+        bot.edit_message_text(msg.chat.id, message.id, text).await?;
+        return Err(e);
+    }
+    tokio::spawn(run_countdown(shared_state.clone(), id, send_at));
+    Ok(())
+}
+
+fn load(shared_state: &SharedState, id: &str) -> Option<ScheduledSend> {
+    shared_state.db.get_scheduled_send(id).unwrap_or_else(|e| {
+        log::error!("Failed to load scheduled send {}: {:?}", id, e);
+        None
+    })
+}
+
+/// Updates the countdown of a scheduled send until it is due, then posts it
+/// unless it was cancelled or sent in the meantime.
+async fn run_countdown(shared_state: SharedState, id: String, send_at: u64) {
+    shared_state.countdowns.start(&id);
+    let mut due = false;
+    loop {
+        let wait_secs = send_at
+            .saturating_sub(unix_now())
+            .min(COUNTDOWN_INTERVAL_SECS);
+        tokio::time::sleep(Duration::from_secs(wait_secs)).await;
+        let Some(send) = load(&shared_state, &id) else {
+            break;
+        };
+        let left_secs = send.send_at.saturating_sub(unix_now());
+        if left_secs == 0 {
+            due = true;
+            break;
+        }
+        let edited = match (chat_message(&send), account_name(&shared_state, &send)) {
+            (Ok((chat_id, message_id)), Ok(username)) => shared_state
+                .bot
+                .edit_message_text(
+                    chat_id,
+                    message_id,
+                    countdown_text(&send.tweet, &username, left_secs),
+                )
+                .reply_markup(keyboard(&id))
+                .await
+                .map(|_| ())
+                .map_err(eyre::Report::from),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+        if let Err(e) = edited {
+            log::warn!("Failed to update countdown of {}: {:?}", id, e);
+        }
+        // Check again right away, so a send taken during the update gets its
+        // final text back without waiting for the next one.
+        if load(&shared_state, &id).is_none() {
+            break;
+        }
+    }
+    if let Some(text) = shared_state.countdowns.stop(&id) {
+        match show_final(&shared_state.bot, &text).await {
+            // The final edit landed last after all.
+            Ok(()) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+            Err(e) => log::warn!("Failed to restore the message of {}: {:?}", id, e),
+        }
+    }
+    if !due {
+        return;
+    }
+    match shared_state.db.take_scheduled_send(&id) {
+        Ok(Some(send)) => deliver(&shared_state, &id, send).await,
+        Ok(None) => {}
+        Err(e) => log::error!("Failed to take scheduled send {}: {:?}", id, e),
+    }
+}
+
+/// Posts a send taken out of the queue and replaces its countdown with the
+/// outcome.
+async fn deliver(shared_state: &SharedState, id: &str, send: ScheduledSend) {
+    let Ok((chat_id, message_id)) = chat_message(&send) else {
+        return;
+    };
+    let kind = describe_tweet(&send.tweet);
+    let sent = send_post(
+        shared_state,
+        &send.chat_id,
+        &send.account_chat_id,
+        &send.x_id,
+        send.tweet,
+    )
+    .await;
+    let (text, keyboard) = match sent {
+        Ok((url, tweet_id)) => (
+            format!("{} sent: {}", kind, url),
+            Some(undo_keyboard(Undo::Delete, &send.x_id, &tweet_id)),
+        ),
+        Err(e) => {
+            log::error!("Failed to send scheduled post: {:?}", e);
+            let reason = match e.downcast_ref::<TwitterError>() {
+                Some(twitter_error) => twitter_error_message(twitter_error),
+                None => e.to_string(),
+            };
+            (format!("{} was not sent: {}", kind, reason), None)
+        }
+    };
+    let text = FinalText {
+        chat_id,
+        message_id,
+        text,
+        keyboard,
+    };
+    if let Err(e) = finish_send(shared_state, id, text).await {
+        log::warn!("Failed to update chat {}: {:?}", send.chat_id, e);
+    }
+}
+
+/// Handles a press of Cancel or Send now, whose callback data follows the
+/// [`SEND`] prefix, and returns the text to show the member who pressed it.
+pub async fn handle_send_button(
+    bot: &Bot,
+    shared_state: &SharedState,
+    q: &CallbackQuery,
+    data: &str,
+) -> eyre::Result<String> {
+    let Some(message) = &q.message else {
+        return Ok("Unknown button".to_string());
+    };
+    let Some((action, id)) = data.split_once(':') else {
+        return Ok("Unknown button".to_string());
+    };
+    if action != CANCEL && action != NOW {
+        return Ok("Unknown button".to_string());
+    }
+    let Some(send) = shared_state.db.get_scheduled_send(id)? else {
+        return Ok("This tweet was already sent or cancelled".to_string());
+    };
+    let role = member_role(bot, shared_state.db.as_ref(), &message.chat, q.from.id).await?;
+    if role < Role::Poster && send.author_id != Some(q.from.id.0) {
+        return Ok("You need the poster role to change others' tweets".to_string());
+    }
+    let Some(send) = shared_state.db.take_scheduled_send(id)? else {
+        return Ok("This tweet was already sent or cancelled".to_string());
+    };
+    if action == CANCEL {
+        let text = FinalText {
+            chat_id: message.chat.id,
+            message_id: message.id,
+            text: format!(
+                "{} cancelled by {}:\n\n{}",
+                describe_tweet(&send.tweet),
+                q.from.full_name(),
+                send.tweet.text()
+            ),
+            keyboard: None,
+        };
+        finish_send(shared_state, id, text).await?;
+        return Ok("Cancelled".to_string());
+    }
+    deliver(shared_state, id, send).await;
+    Ok("Sent".to_string())
+}
+
+/// Picks up the sends that were pending when the bot stopped: those due long
+/// ago are cancelled, the others are posted when due.
+pub async fn resume_scheduled_sends(shared_state: SharedState) {
+    let sends = match shared_state.db.list_scheduled_sends() {
+        Ok(sends) => sends,
+        Err(e) => {
+            log::error!("Failed to load scheduled sends: {:?}", e);
+            return;
+        }
+    };
+    let now = unix_now();
+    for (id, send) in sends {
+        if send.send_at + MAX_LATE_SECS >= now {
+            tokio::spawn(run_countdown(shared_state.clone(), id, send.send_at));
+            continue;
+        }
+        let Ok(Some(send)) = shared_state.db.take_scheduled_send(&id) else {
+            continue;
+        };
+        let Ok((chat_id, message_id)) = chat_message(&send) else {
+            continue;
+        };
+        let text = format!(
+            "{} was not sent, the bot was offline when it was due:\n\n{}",
+            describe_tweet(&send.tweet),
+            send.tweet.text()
+        );
+        if let Err(e) = shared_state
+            .bot
+            .edit_message_text(chat_id, message_id, text)
+            .await
+        {
+            log::warn!("Failed to notify chat {}: {:?}", send.chat_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn final_text(text: &str) -> FinalText {
+        FinalText {
+            chat_id: ChatId(1),
+            message_id: MessageId(2),
+            text: text.to_string(),
+            keyboard: None,
+        }
+    }
+
+    #[test]
+    fn records_final_texts_of_running_countdowns_only() {
+        let countdowns = Countdowns::default();
+        countdowns.finish("a", &final_text("cancelled"));
+        assert!(countdowns.stop("a").is_none());
+
+        countdowns.start("a");
+        assert!(countdowns.stop("a").is_none());
+        countdowns.start("a");
+        countdowns.finish("a", &final_text("cancelled"));
+        assert_eq!(countdowns.stop("a").unwrap().text, "cancelled");
+        // Stopping forgets the countdown.
+        assert!(countdowns.stop("a").is_none());
+        assert!(countdowns.running().is_empty());
+    }
+}
@@ -0,0 +1,331 @@
+use teloxide::{requests::Requester, types::ChatId, Bot};
+
+use crate::{
+    handlers::twitter_commands::twitter_error_message,
+    twitter::{
+        builder::TwitterClient,
+        error::TwitterError,
+        media::{validate_attachments, MediaFile},
+        text::{tweet_length, MAX_TWEET_LENGTH},
+        tweet::Tweet,
+    },
+};
+
+/// Most tweets a thread can have.
+const MAX_PARTS: usize = 25;
+
+/// One tweet of a thread, with the attached files it carries by index.
+#[derive(Debug, Clone)]
+pub struct ThreadPart {
+    pub text: String,
+    pub media: Vec<usize>,
+}
+
+/// The file numbers after a leading `media:`, in any case.
+fn strip_media_prefix(line: &str) -> Option<&str> {
+    let prefix = line.get(..6)?;
+    prefix
+        .eq_ignore_ascii_case("media:")
+        .then(|| line[6..].trim())
+}
+
+/// Splits `text` into the tweets of a thread. Lines of `---` separate
+/// tweets, and a tweet over the length limit is split further. A line like
+/// `media: 1 2` attaches those files to its tweet, otherwise every file
+/// goes to the first one.
+pub fn split_thread(text: &str, media: &[MediaFile]) -> eyre::Result<Vec<ThreadPart>> {
+    let mut parts: Vec<ThreadPart> = Vec::new();
+    let mut assigned = false;
+    for section in text
+        .split('\n')
+        .collect::<Vec<_>>()
+        .split(|line| line.trim() == "---")
+    {
+        let mut files = Vec::new();
+        let mut lines = Vec::new();
+        for line in section {
+            let Some(numbers) = strip_media_prefix(line.trim_start()) else {
+                lines.push(*line);
+                continue;
+            };
+            for number in numbers.split([',', ' ']).filter(|n| !n.is_empty()) {
+                let index = match number.parse::<usize>() {
+                    Ok(n) if (1..=media.len()).contains(&n) => n - 1,
+                    _ => eyre::bail!("There is no attached file {}", number),
+                };
+                files.push(index);
+            }
+            assigned = true;
+        }
+        let chunks = split_to_fit(&lines.join("\n"));
+        if chunks.is_empty() && !files.is_empty() {
+            eyre::bail!("A tweet of the thread has media but no text");
+        }
+        for (i, text) in chunks.into_iter().enumerate() {
+            let media = if i == 0 { files.clone() } else { Vec::new() };
+            parts.push(ThreadPart { text, media });
+        }
+    }
+    if parts.is_empty() {
+        eyre::bail!("Provide the text of the thread");
+    }
+    if parts.len() > MAX_PARTS {
+        eyre::bail!(
+            "A thread can have at most {} tweets, this one has {}",
+            MAX_PARTS,
+            parts.len()
+        );
+    }
+    if !assigned {
+        parts[0].media = (0..media.len()).collect();
+    }
+    for index in 0..media.len() {
+        if !parts.iter().any(|part| part.media.contains(&index)) {
+            eyre::bail!(
+                "File {} is not attached to any tweet of the thread",
+                index + 1
+            );
+        }
+    }
+    // Check every tweet up front, so nothing is drawn, uploaded or posted
+    // for a thread that cannot be posted whole.
+    for (n, part) in parts.iter().enumerate() {
+        Tweet::new(part.text.clone())
+            .validate()
+            .map_err(|e| eyre::eyre!("Tweet {}: {}", n + 1, e))?;
+        let files = part
+            .media
+            .iter()
+            .map(|&i| media[i].clone())
+            .collect::<Vec<_>>();
+        validate_attachments(&files).map_err(|e| eyre::eyre!("Tweet {}: {}", n + 1, e))?;
+    }
+    Ok(parts)
+}
+
+/// Splits `text` into tweets that fit the length limit.
+fn split_to_fit(text: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text.trim();
+    while tweet_length(rest) > MAX_TWEET_LENGTH {
+        let end = break_point(rest);
+        chunks.push(rest[..end].trim_end().to_string());
+        rest = rest[end..].trim_start();
+    }
+    if !rest.is_empty() {
+        chunks.push(rest.to_string());
+    }
+    chunks
+}
+
+/// Where to end the tweet that starts `text`: at the last paragraph,
+/// sentence or word boundary in the second half of what fits, or else
+/// wherever the limit is reached.
+fn break_point(text: &str) -> usize {
+    let ends = text
+        .char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .collect::<Vec<_>>();
+    let fitting = ends.partition_point(|&end| tweet_length(&text[..end]) <= MAX_TWEET_LENGTH);
+    let fits = ends[fitting.max(1) - 1];
+    // A separator may start right where the limit is reached, as the
+    // whitespace in it is trimmed anyway.
+    let window = text
+        .get(..fits + 2)
+        .or_else(|| text.get(..fits + 1))
+        .unwrap_or(&text[..fits]);
+    let min = (fits / 2).max(1);
+    for (separator, keep) in [
+        ("\n\n", 0),
+        ("\n", 0),
+        (". ", 1),
+        ("! ", 1),
+        ("? ", 1),
+        (" ", 0),
+    ] {
+        let end = window
+            .rmatch_indices(separator)
+            .map(|(pos, _)| pos + keep)
+            .find(|&end| end <= fits)
+            .filter(|&end| end >= min);
+        if let Some(end) = end {
+            return end;
+        }
+    }
+    fits
+}
+
+/// Posts `tweets` as a thread, each replying to the one before, and returns
+/// the id of the first. If a tweet fails, the chat is told which ones were
+/// posted and `None` is returned.
+pub async fn post_thread(
+    bot: &Bot,
+    chat_id: ChatId,
+    client: &TwitterClient<'_>,
+    username: &str,
+    tweets: Vec<Tweet>,
+) -> eyre::Result<Option<String>> {
+    let total = tweets.len();
+    let mut posted: Vec<String> = Vec::new();
+    for mut tweet in tweets {
+        if let Some(previous) = posted.last() {
+            tweet.set_reply_tweet_id(previous.clone());
+        }
+        let error = match client.raw_tweet(tweet).await {
+            Ok(id) => {
+                posted.push(id);
+                continue;
+            }
+            Err(e) => e,
+        };
+        log::warn!(
+            "Thread stopped after {} of {} tweets: {:?}",
+            posted.len(),
+            total,
+            error
+        );
+        let reason = match error.downcast_ref::<TwitterError>() {
+            Some(twitter_error) => twitter_error_message(twitter_error),
+            None => error.to_string(),
+        };
+        let mut to_send = format!(
+            "Tweet {} of {} of the thread failed: {}",
+            posted.len() + 1,
+            total,
+            reason
+        );
+        if let Some(last) = posted.last() {
+            to_send.push_str(&format!(
+                "\n\nPosted so far:\n{}\n\nContinue the thread with /reply https://x.com/{}/status/{} <text>",
+                posted
+                    .iter()
+                    .map(|id| format!("https://x.com/{}/status/{}", username, id))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                username,
+                last
+            ));
+        }
+        bot.send_message(chat_id, to_send).await?;
+        return Ok(None);
+    }
+    Ok(posted.into_iter().next())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> MediaFile {
+        MediaFile {
+            bytes: b"\x89PNG".to_vec(),
+            mime_type: None,
+            duration_secs: None,
+            alt_text: None,
+        }
+    }
+
+    fn texts(parts: &[ThreadPart]) -> Vec<&str> {
+        parts.iter().map(|part| part.text.as_str()).collect()
+    }
+
+    #[test]
+    fn splits_on_separators() {
+        let parts = split_thread("first\n---\nsecond\n ---  \nthird", &[]).unwrap();
+        assert_eq!(texts(&parts), ["first", "second", "third"]);
+        // Empty sections are skipped.
+        let parts = split_thread("first\n---\n\n---\nsecond", &[]).unwrap();
+        assert_eq!(texts(&parts), ["first", "second"]);
+        assert!(split_thread(" \n---\n ", &[]).is_err());
+    }
+
+    #[test]
+    fn splits_long_tweets_at_words() {
+        let text = "word ".repeat(100);
+        let parts = split_thread(&text, &[]).unwrap();
+        assert_eq!(parts.len(), 2);
+        for part in &parts {
+            assert!(tweet_length(&part.text) <= MAX_TWEET_LENGTH);
+            assert!(part.text.split(' ').all(|word| word == "word"));
+        }
+        assert_eq!(
+            parts[0].text.len() + parts[1].text.len() + 1,
+            text.trim().len()
+        );
+    }
+
+    #[test]
+    fn prefers_sentence_and_paragraph_breaks() {
+        let first = format!("{}.", "a ".repeat(100).trim_end());
+        let text = format!("{} {}", first, "b ".repeat(100));
+        let parts = split_thread(&text, &[]).unwrap();
+        assert_eq!(parts[0].text, first);
+
+        let text = format!("{}\n\n{}", "c ".repeat(80).trim_end(), "d ".repeat(80));
+        let parts = split_thread(&text, &[]).unwrap();
+        assert_eq!(parts[0].text, "c ".repeat(80).trim_end());
+
+        // A paragraph that fills the tweet exactly ends it.
+        let first = format!("{}aa", "a ".repeat(139));
+        let text = format!("{}\n\n{}", first, "b ".repeat(50));
+        assert_eq!(split_thread(&text, &[]).unwrap()[0].text, first);
+    }
+
+    #[test]
+    fn keeps_links_whole() {
+        // The link counts as 23 characters however long it is, so this fits.
+        let link = format!("https://example.com/{}", "x".repeat(200));
+        let text = format!("{}{}", "word ".repeat(50), link);
+        assert_eq!(split_thread(&text, &[]).unwrap().len(), 1);
+
+        // A link straddling the limit moves to the next tweet.
+        let text = format!("{}{}", "word ".repeat(53), link);
+        let parts = split_thread(&text, &[]).unwrap();
+        assert_eq!(texts(&parts)[1], link);
+        assert!(tweet_length(&parts[0].text) <= MAX_TWEET_LENGTH);
+    }
+
+    #[test]
+    fn cuts_text_without_breaks() {
+        let text = "漢".repeat(200);
+        let parts = split_thread(&text, &[]).unwrap();
+        assert_eq!(
+            parts
+                .iter()
+                .map(|p| p.text.chars().count())
+                .collect::<Vec<_>>(),
+            [140, 60]
+        );
+    }
+
+    #[test]
+    fn limits_the_number_of_tweets() {
+        let text = vec!["tweet"; MAX_PARTS].join("\n---\n");
+        assert_eq!(split_thread(&text, &[]).unwrap().len(), MAX_PARTS);
+        let text = format!("{}\n---\none too many", text);
+        assert!(split_thread(&text, &[]).is_err());
+        // Tweets split for length count too.
+        let text = "word ".repeat(60 * MAX_PARTS);
+        assert!(split_thread(&text, &[]).is_err());
+    }
+
+    #[test]
+    fn assigns_media() {
+        let media = [image(), image()];
+        let parts = split_thread("first\n---\nsecond", &media).unwrap();
+        assert_eq!(parts[0].media, [0, 1]);
+        assert!(parts[1].media.is_empty());
+
+        let parts = split_thread("first\nMedia: 2\n---\nsecond\nmedia: 1", &media).unwrap();
+        assert_eq!(texts(&parts), ["first", "second"]);
+        assert_eq!(
+            (parts[0].media.as_slice(), parts[1].media.as_slice()),
+            (&[1][..], &[0][..])
+        );
+
+        assert!(split_thread("first\nmedia: 3", &media).is_err());
+        // Every file has to go somewhere.
+        assert!(split_thread("first\nmedia: 1\n---\nsecond", &media).is_err());
+        assert!(split_thread("first\n---\nmedia: 1 2", &media).is_err());
+    }
+}
@@ -1,12 +1,45 @@
 use eyre::OptionExt;
-use teloxide::{macros::BotCommands, requests::Requester, types::ChatId, Bot};
+use futures_util::future::try_join_all;
+use teloxide::{
+    macros::BotCommands,
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, Message},
+    Bot,
+};
 
-use crate::{endpoints::SharedState, twitter::tweet::Tweet};
+use crate::{
+    accounts::{delegated_accounts, find_account, ActingAccount},
+    db::{unix_now, Action, Role},
+    durations::{format_wait, parse_duration},
+    endpoints::SharedState,
+    handlers::{
+        approvals::submit_for_approval,
+        scheduled::schedule_send,
+        threads::{post_thread, split_thread},
+        undo::{undo_keyboard, Undo},
+    },
+    permissions::require_role,
+    rate_limits::LimitedAction,
+    twitter::{
+        error::TwitterError,
+        media::{validate_attachments, MediaFile},
+        poll::PollResults,
+        tweet::Tweet,
+    },
+};
 
+/// How long a poll runs unless its command says otherwise.
+const DEFAULT_POLL_MINUTES: u32 = 24 * 60;
+
+/// Every command can start with `@handle` to act as a linked account other
+/// than the active one, e.g. `/tweet @brand hello`.
 #[derive(BotCommands, Clone, Debug)]
 #[command(rename_rule = "lowercase")]
 pub enum TwitterCommand {
-    #[command(description = "Post a tweet by providing the tweet text")]
+    #[command(
+        description = "Post a tweet by providing the tweet text, also as the caption of a photo, GIF or video"
+    )]
     Tweet(String),
     #[command(description = "Like a tweet by providing the tweet URL")]
     Like(String),
@@ -16,6 +49,175 @@ pub enum TwitterCommand {
     Reply(String),
     #[command(description = "Quote a tweet by providing the tweet URL and the tweet text")]
     Quote(String),
+    #[command(
+        description = "Post a thread, splitting the text at lines of --- or at the length limit. A line \"media: 1 2\" attaches those files of an album to its tweet"
+    )]
+    Thread(String),
+    #[command(
+        description = "Post a poll with the question on the first line and 2 to 4 options on the next ones. A line \"duration: 3d\" sets how long it runs, one day by default"
+    )]
+    Poll(String),
+    #[command(description = "Show the results of a closed poll by providing the tweet URL")]
+    PollResults(String),
+    #[command(description = "Delete a tweet of the account by providing the tweet URL")]
+    Delete(String),
+    #[command(description = "Remove a like by providing the tweet URL")]
+    Unlike(String),
+    #[command(description = "Remove a retweet by providing the tweet URL")]
+    Unretweet(String),
+}
+
+impl TwitterCommand {
+    fn required_role(&self) -> Role {
+        match self {
+            Self::PollResults(_) => Role::Viewer,
+            Self::Like(_) | Self::Retweet(_) | Self::Unlike(_) | Self::Unretweet(_) => {
+                Role::Reacter
+            }
+            Self::Delete(_) => Role::Poster,
+            Self::Tweet(_) | Self::Reply(_) | Self::Quote(_) | Self::Thread(_) | Self::Poll(_) => {
+                Role::Poster
+            }
+        }
+    }
+
+    /// What the command does as the account, or `None` if it only reads.
+    /// Undoing a like or retweet needs the same permission as taking it.
+    fn action(&self) -> Option<Action> {
+        match self {
+            Self::Tweet(_) | Self::Thread(_) | Self::Poll(_) => Some(Action::Tweet),
+            Self::Like(_) => Some(Action::Like),
+            Self::Retweet(_) => Some(Action::Retweet),
+            Self::Reply(_) => Some(Action::Reply),
+            Self::Quote(_) => Some(Action::Quote),
+            Self::PollResults(_) => None,
+            Self::Delete(_) => Some(Action::Delete),
+            Self::Unlike(_) => Some(Action::Like),
+            Self::Unretweet(_) => Some(Action::Retweet),
+        }
+    }
+
+    /// What the command undoes, if it is an inverse.
+    fn undo(&self) -> Option<Undo> {
+        match self {
+            Self::Delete(_) => Some(Undo::Delete),
+            Self::Unlike(_) => Some(Undo::Unlike),
+            Self::Unretweet(_) => Some(Undo::Unretweet),
+            _ => None,
+        }
+    }
+
+    fn argument_mut(&mut self) -> &mut String {
+        match self {
+            Self::Tweet(arg)
+            | Self::Like(arg)
+            | Self::Retweet(arg)
+            | Self::Reply(arg)
+            | Self::Quote(arg)
+            | Self::Thread(arg)
+            | Self::Poll(arg)
+            | Self::PollResults(arg)
+            | Self::Delete(arg)
+            | Self::Unlike(arg)
+            | Self::Unretweet(arg) => arg,
+        }
+    }
+}
+
+/// Resolves the account a command acts as: the linked or delegated account
+/// named by a leading `@handle`, which is stripped from `argument`, or else
+/// the chat's active account. A chat without accounts of its own acts as the
+/// account delegated to it, if there is exactly one. A leading mention of an
+/// account that is not linked is left in place as part of the text.
+fn resolve_account(
+    shared_state: &SharedState,
+    chat_id: &str,
+    argument: &mut String,
+) -> eyre::Result<Option<ActingAccount>> {
+    let own = |user| ActingAccount {
+        user,
+        chat_id: chat_id.to_string(),
+        delegation: None,
+    };
+    let db = shared_state.db.as_ref();
+    if let Some((first, rest)) = argument.split_once(char::is_whitespace) {
+        if let Some(handle) = first.strip_prefix('@') {
+            let account = match find_account(db.get_chat_users(chat_id)?, handle) {
+                Some(user) => Some(own(user)),
+                None => delegated_accounts(db, chat_id)?
+                    .into_iter()
+                    .find(|a| a.user.username.eq_ignore_ascii_case(handle)),
+            };
+            if let Some(account) = account {
+                *argument = rest.trim_start().to_string();
+                return Ok(Some(account));
+            }
+        }
+    }
+    if let Some(user) = db.get_active_user(chat_id)? {
+        return Ok(Some(own(user)));
+    }
+    let mut delegated = delegated_accounts(db, chat_id)?;
+    Ok(if delegated.len() == 1 {
+        delegated.pop()
+    } else {
+        None
+    })
+}
+
+/// Counts `count` actions against the delegation `account` is used through,
+/// if any, telling the chat when not enough are left.
+async fn consume_delegation(
+    bot: &Bot,
+    shared_state: &SharedState,
+    chat_id: ChatId,
+    account: &ActingAccount,
+    count: u32,
+) -> eyre::Result<bool> {
+    let Some((code, _)) = &account.delegation else {
+        return Ok(true);
+    };
+    if shared_state.db.consume_delegation(code, count)?.is_some() {
+        return Ok(true);
+    }
+    let to_send = if count > 1 {
+        format!(
+            "The delegation of @{} expired or has fewer than {} actions left",
+            account.user.username, count
+        )
+    } else {
+        format!(
+            "The delegation of @{} expired or has no actions left",
+            account.user.username
+        )
+    };
+    bot.send_message(chat_id, to_send).await?;
+    Ok(false)
+}
+
+/// The description after a leading `alt:`, in any case.
+pub fn strip_alt_prefix(line: &str) -> Option<&str> {
+    let prefix = line.get(..4)?;
+    prefix
+        .eq_ignore_ascii_case("alt:")
+        .then(|| line[4..].trim())
+}
+
+/// Removes the `alt: <description>` lines from `text` and returns the
+/// descriptions, which apply to the attached images in order.
+fn split_alt_text(text: &mut String) -> Vec<String> {
+    let mut alt_texts = Vec::new();
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        match strip_alt_prefix(line.trim_start()) {
+            Some(alt_text) => alt_texts.push(alt_text.to_string()),
+            None => lines.push(line),
+        }
+    }
+    if !alt_texts.is_empty() {
+        *text = lines.join("\n").trim().to_string();
+    }
+    alt_texts
 }
 
 fn build_twitter_command_message(cmd: TwitterCommand, url: String) -> String {
@@ -25,27 +227,56 @@ fn build_twitter_command_message(cmd: TwitterCommand, url: String) -> String {
         TwitterCommand::Retweet(_) => "Tweet retweeted".to_string(),
         TwitterCommand::Reply(_) => format!("Reply sent: {}", url),
         TwitterCommand::Quote(_) => format!("Quote tweet sent: {}", url),
+        TwitterCommand::Thread(_) => format!("Thread sent: {}", url),
+        TwitterCommand::Poll(_) => format!("Poll sent: {}", url),
+        TwitterCommand::PollResults(_) => url,
+        TwitterCommand::Delete(_) => Undo::Delete.done_message().to_string(),
+        TwitterCommand::Unlike(_) => Undo::Unlike.done_message().to_string(),
+        TwitterCommand::Unretweet(_) => Undo::Unretweet.done_message().to_string(),
     }
 }
 
 fn extract_tweet_id(tweet_url: &str) -> eyre::Result<String> {
     let url = url::Url::parse(tweet_url)?;
-    let path_segments = url
+    let mut path_segments = url
         .path_segments()
         .ok_or_eyre("Failed to extract base_url")?;
     let tweet_id = path_segments
-        .last()
+        .next_back()
         .ok_or_eyre("Failed to extract tweet_id from tweet_url")?;
     Ok(tweet_id.to_string())
 }
 
-fn build_raw_tweet(
-    cmd: TwitterCommand,
-    raw_text: String,
-    media: Option<Vec<String>>,
-) -> eyre::Result<Tweet> {
+/// Builds a poll from its question on the first line and one option per
+/// following line. A `duration: 2d` line sets how long it runs.
+fn build_poll(text: &str) -> eyre::Result<Tweet> {
+    let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+    let question = lines
+        .next()
+        .ok_or_eyre("Provide the question on the first line and one option per line")?;
+    let mut options = Vec::new();
+    let mut duration_minutes = DEFAULT_POLL_MINUTES;
+    for line in lines {
+        let is_duration = line
+            .get(..9)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("duration:"));
+        if !is_duration {
+            options.push(line.to_string());
+            continue;
+        }
+        let secs = parse_duration(line[9..].trim())
+            .ok_or_eyre("Invalid poll duration, use e.g. 30m, 12h or 3d")?;
+        duration_minutes = u32::try_from(secs / 60).unwrap_or(u32::MAX);
+    }
+    let mut tweet = Tweet::new(question.to_string());
+    tweet.set_poll(options, duration_minutes);
+    Ok(tweet)
+}
+
+fn build_raw_tweet(cmd: TwitterCommand, raw_text: String) -> eyre::Result<Tweet> {
     let (tweet_text, tweet_id) = match cmd {
         TwitterCommand::Tweet(_) => (raw_text, "".to_string()),
+        TwitterCommand::Poll(_) => return build_poll(&raw_text),
         TwitterCommand::Reply(_) | TwitterCommand::Quote(_) => {
             let (tweet_url, tweet_text) = raw_text
                 .split_once(' ')
@@ -63,10 +294,6 @@ fn build_raw_tweet(
     } else if let TwitterCommand::Quote(_) = cmd {
         tweet.set_quote_tweet_id(tweet_id);
     }
-
-    if let Some(media) = media {
-        tweet.set_media_ids(media);
-    }
     Ok(tweet)
 }
 
@@ -74,46 +301,342 @@ pub async fn twitter_command_handler(
     bot: Bot,
     shared_state: SharedState,
     cmd: TwitterCommand,
-    chat_id: ChatId,
-    media: Option<Vec<u8>>,
+    msg: Message,
+    media: Vec<MediaFile>,
+) -> eyre::Result<()> {
+    let chat_id = msg.chat.id;
+    let result = run_twitter_command(bot.clone(), shared_state, cmd, msg, media).await;
+    let Err(e) = result else {
+        return Ok(());
+    };
+    let Some(twitter_error) = e.downcast_ref::<TwitterError>() else {
+        return Err(e);
+    };
+    log::warn!("Twitter rejected a command: {}", twitter_error);
+    bot.send_message(chat_id, twitter_error_message(twitter_error))
+        .await?;
+    Ok(())
+}
+
+/// What to tell the chat when Twitter rejects an action.
+pub fn twitter_error_message(error: &TwitterError) -> String {
+    match error {
+        TwitterError::Unauthorized { .. } => {
+            "Twitter rejected this account's credentials. If the bot's access was revoked, link the account again with /auth.".to_string()
+        }
+        TwitterError::Duplicate => {
+            "Twitter rejected this as a duplicate of a recent post from this account.".to_string()
+        }
+        TwitterError::Forbidden { detail } => {
+            format!("Twitter does not allow this: {}", detail)
+        }
+        TwitterError::NotFound { .. } => {
+            "That tweet does not exist or is not visible to this account.".to_string()
+        }
+        TwitterError::RateLimited { reset_at } => format!(
+            "Twitter is rate limiting this account, try again in {}.",
+            format_wait(reset_at.saturating_sub(unix_now()))
+        ),
+        TwitterError::ServerError { .. } => {
+            "Twitter is having problems right now, please try again later.".to_string()
+        }
+        TwitterError::Other { detail, .. } => format!("Twitter returned an error: {}", detail),
+    }
+}
+
+/// The vote counts of a closed poll, or when an open one closes.
+fn poll_results_message(poll: &PollResults) -> String {
+    if !poll.is_closed() {
+        return match poll.end_datetime.as_deref().and_then(|end| end.get(..16)) {
+            Some(end) => format!(
+                "The poll is open until {} UTC, the results are shown once it closes",
+                end.replace('T', " ")
+            ),
+            None => "The poll is still open, the results are shown once it closes".to_string(),
+        };
+    }
+    let total: u64 = poll.options.iter().map(|option| option.votes).sum();
+    let mut options = poll.options.iter().collect::<Vec<_>>();
+    options.sort_by_key(|option| option.position);
+    let lines = options
+        .iter()
+        .map(|option| {
+            let percent = (option.votes * 100 + total / 2)
+                .checked_div(total)
+                .unwrap_or(0);
+            format!("{}: {} ({}%)", option.label, option.votes, percent)
+        })
+        .collect::<Vec<_>>();
+    format!("Final results, {} votes:\n{}", total, lines.join("\n"))
+}
+
+async fn run_twitter_command(
+    bot: Bot,
+    shared_state: SharedState,
+    mut cmd: TwitterCommand,
+    msg: Message,
+    mut media: Vec<MediaFile>,
 ) -> eyre::Result<()> {
-    let db = shared_state.db.lock().await;
-    let user = db
-        .access_tokens
-        .get(&chat_id.to_string())
-        .map(|u| u.clone());
-    drop(db);
-    if user.is_none() {
+    let chat_id = msg.chat.id;
+    if !require_role(&bot, shared_state.db.as_ref(), &msg, cmd.required_role()).await? {
+        return Ok(());
+    }
+    let mut alt_texts = split_alt_text(cmd.argument_mut()).into_iter();
+    for file in media.iter_mut().filter(|file| file.alt_text.is_none()) {
+        file.alt_text = alt_texts.next();
+    }
+    if alt_texts.next().is_some() {
+        bot.send_message(chat_id, "There are more alt: lines than attached files")
+            .await?;
+        return Ok(());
+    }
+    let settings = shared_state.db.get_chat_settings(&chat_id.to_string())?;
+    if settings.require_alt_text && media.iter().any(MediaFile::needs_alt_text) {
+        bot.send_message(
+            chat_id,
+            "This chat requires alt text on every image. Add a line \"alt: <description>\" per image to the caption.",
+        )
+        .await?;
+        return Ok(());
+    }
+    let account = resolve_account(&shared_state, &chat_id.to_string(), cmd.argument_mut())?;
+    let Some(account) = account else {
         bot.send_message(chat_id, "Please /auth first").await?;
         return Ok(());
+    };
+    let user = account.user.clone();
+    if user.revoked {
+        bot.send_message(
+            chat_id,
+            "The bot's access to this account was revoked, please /auth again",
+        )
+        .await?;
+        return Ok(());
+    }
+    if let (Some((_, delegation)), Some(action)) = (&account.delegation, cmd.action()) {
+        if !delegation.actions.contains(&action) {
+            let to_send = format!(
+                "The delegation of @{} does not allow {}",
+                user.username,
+                action.as_str()
+            );
+            bot.send_message(chat_id, to_send).await?;
+            return Ok(());
+        }
+    }
+    let thread = match &cmd {
+        TwitterCommand::Thread(text) => split_thread(text, &media).map(Some),
+        _ => validate_attachments(&media).map(|_| None),
+    };
+    let thread = match thread {
+        Ok(thread) => thread,
+        Err(e) => {
+            bot.send_message(chat_id, e.to_string()).await?;
+            return Ok(());
+        }
+    };
+    if thread.is_some() && settings.approvals_required > 0 {
+        bot.send_message(
+            chat_id,
+            "Threads cannot go through approval yet, post the tweets one by one instead",
+        )
+        .await?;
+        return Ok(());
+    }
+    if thread.is_some() && settings.send_delay_secs > 0 {
+        bot.send_message(
+            chat_id,
+            "Threads cannot wait out the send delay yet, post the tweets one by one instead",
+        )
+        .await?;
+        return Ok(());
+    }
+    let draft = match &cmd {
+        TwitterCommand::Tweet(text)
+        | TwitterCommand::Reply(text)
+        | TwitterCommand::Quote(text)
+        | TwitterCommand::Poll(text) => Some(build_raw_tweet(cmd.clone(), text.clone()).and_then(
+            |tweet| {
+                tweet.validate()?;
+                if tweet.has_poll() && !media.is_empty() {
+                    eyre::bail!("A tweet cannot have both a poll and media");
+                }
+                Ok(tweet)
+            },
+        )),
+        _ => None,
+    };
+    let draft = match draft.transpose() {
+        Ok(draft) => draft,
+        Err(e) => {
+            bot.send_message(chat_id, e.to_string()).await?;
+            return Ok(());
+        }
+    };
+    // Check the policy before drawing rate limits or uploading anything, so a
+    // rejected post costs nothing.
+    let delegated = account.delegation.is_some();
+    let violation = match (&draft, &thread) {
+        (Some(tweet), _) => shared_state
+            .policy
+            .check_draft(tweet, !media.is_empty(), delegated)
+            .err()
+            .map(|violation| format!("Not posted, {}", violation)),
+        (None, Some(parts)) => parts.iter().enumerate().find_map(|(n, part)| {
+            let tweet = Tweet::new(part.text.clone());
+            shared_state
+                .policy
+                .check_draft(&tweet, !part.media.is_empty(), delegated)
+                .err()
+                .map(|violation| format!("Not posted, tweet {}: {}", n + 1, violation))
+        }),
+        (None, None) => None,
+    };
+    if let Some(to_send) = violation {
+        bot.send_message(chat_id, to_send).await?;
+        return Ok(());
+    }
+    let posts = thread.as_ref().map_or(1, |parts| parts.len() as u32);
+    let mut limited = cmd
+        .action()
+        .map(|action| (LimitedAction::from(action), posts))
+        .into_iter()
+        .collect::<Vec<_>>();
+    if !media.is_empty() {
+        limited.push((LimitedAction::Upload, media.len() as u32));
+    }
+    let draws =
+        shared_state
+            .rate_limits
+            .draws(&user.x_id, msg.from().map(|from| from.id.0), &limited);
+    if let Some(wait_secs) = shared_state.db.draw_rate_limits(&draws)? {
+        let to_send = format!(
+            "Slow down, too many actions. Try again in {}.",
+            format_wait(wait_secs)
+        );
+        bot.send_message(chat_id, to_send).await?;
+        return Ok(());
     }
-    let user = user.unwrap();
-    let client = shared_state.twitter.with_auth(user.token_pair);
+    let user = shared_state
+        .refresh_credentials(&account.chat_id, user)
+        .await?;
+    let client = shared_state.twitter.with_auth(user.credentials.clone());
     let id = match cmd.clone() {
         TwitterCommand::Like(tweet_url) | TwitterCommand::Retweet(tweet_url) => {
             let tweet_id = extract_tweet_id(&tweet_url)?;
-            let _ = if let TwitterCommand::Like(_) = cmd {
-                client.like(user.x_id, tweet_id.to_string()).await?
+            if !consume_delegation(&bot, &shared_state, chat_id, &account, 1).await? {
+                return Ok(());
+            }
+            if let TwitterCommand::Like(_) = cmd {
+                client.like(user.x_id.clone(), tweet_id.clone()).await?
             } else {
-                client.retweet(user.x_id, tweet_id.to_string()).await?
+                client.retweet(user.x_id.clone(), tweet_id.clone()).await?
+            }
+            tweet_id
+        }
+        TwitterCommand::Delete(tweet_url)
+        | TwitterCommand::Unlike(tweet_url)
+        | TwitterCommand::Unretweet(tweet_url) => {
+            let tweet_id = extract_tweet_id(&tweet_url)?;
+            let undo = cmd.undo().ok_or_eyre("The command undoes nothing")?;
+            if !consume_delegation(&bot, &shared_state, chat_id, &account, 1).await? {
+                return Ok(());
+            }
+            undo.apply(&client, user.x_id.clone(), tweet_id.clone())
+                .await?;
+            tweet_id
+        }
+        TwitterCommand::Quote(_)
+        | TwitterCommand::Reply(_)
+        | TwitterCommand::Tweet(_)
+        | TwitterCommand::Poll(_) => {
+            let mut tweet = draft.ok_or_eyre("The command has no tweet")?;
+            if !media.is_empty() {
+                tweet.set_media_ids(
+                    try_join_all(media.iter().map(|file| client.upload(file))).await?,
+                );
+            }
+            if !consume_delegation(&bot, &shared_state, chat_id, &account, 1).await? {
+                return Ok(());
+            }
+            if settings.approvals_required > 0 {
+                submit_for_approval(
+                    &bot,
+                    &shared_state,
+                    &msg,
+                    &account,
+                    tweet,
+                    settings.approvals_required,
+                )
+                .await?;
+                return Ok(());
+            }
+            if settings.send_delay_secs > 0 {
+                schedule_send(
+                    &bot,
+                    &shared_state,
+                    &msg,
+                    &account,
+                    tweet,
+                    settings.send_delay_secs,
+                )
+                .await?;
+                return Ok(());
+            }
+            client.raw_tweet(tweet).await?
+        }
+        TwitterCommand::PollResults(tweet_url) => {
+            let tweet_id = extract_tweet_id(&tweet_url)?;
+            let to_send = match client.get_poll(&tweet_id).await? {
+                Some(poll) => poll_results_message(&poll),
+                None => "That tweet has no poll".to_string(),
             };
-            "".to_string()
+            bot.send_message(chat_id, to_send).await?;
+            return Ok(());
         }
-        TwitterCommand::Quote(text) | TwitterCommand::Reply(text) | TwitterCommand::Tweet(text) => {
-            let media_ids = if let Some(media) = media {
-                let media_id = client.upload_media(media).await?;
-                Some(vec![media_id])
-            } else {
-                None
+        TwitterCommand::Thread(_) => {
+            // Every tweet of the thread counts against the delegation.
+            if !consume_delegation(&bot, &shared_state, chat_id, &account, posts).await? {
+                return Ok(());
+            }
+            let media_ids = try_join_all(media.iter().map(|file| client.upload(file))).await?;
+            let mut tweets = Vec::new();
+            for part in thread.unwrap_or_default() {
+                let mut tweet = Tweet::new(part.text);
+                if !part.media.is_empty() {
+                    tweet.set_media_ids(part.media.iter().map(|&i| media_ids[i].clone()).collect());
+                }
+                tweet.validate()?;
+                tweets.push(tweet);
+            }
+            let Some(id) = post_thread(&bot, chat_id, &client, &user.username, tweets).await?
+            else {
+                return Ok(());
             };
-            let tweet = build_raw_tweet(cmd.clone(), text, media_ids)?;
-            client.raw_tweet(tweet).await?
+            id
         }
     };
 
     let url = format!("https://x.com/{}/status/{}", user.username, id);
-    bot.send_message(chat_id, build_twitter_command_message(cmd, url))
-        .await?;
+    // Threads get no Undo: the callback data cannot hold the id of every
+    // tweet, and deleting only the first would leave the rest orphaned.
+    let undo = match cmd {
+        TwitterCommand::Tweet(_)
+        | TwitterCommand::Reply(_)
+        | TwitterCommand::Quote(_)
+        | TwitterCommand::Poll(_) => Some(Undo::Delete),
+        TwitterCommand::Like(_) => Some(Undo::Unlike),
+        TwitterCommand::Retweet(_) => Some(Undo::Unretweet),
+        _ => None,
+    };
+    let send = bot.send_message(chat_id, build_twitter_command_message(cmd, url));
+    match undo {
+        Some(undo) => {
+            send.reply_markup(undo_keyboard(undo, &user.x_id, &id))
+                .await?
+        }
+        None => send.await?,
+    };
 
     Ok(())
 }
@@ -0,0 +1,183 @@
+use teloxide::{
+    requests::Requester,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup},
+    Bot,
+};
+
+use crate::{
+    accounts::{delegated_accounts, ActingAccount},
+    db::{Action, Role},
+    durations::format_wait,
+    endpoints::SharedState,
+    permissions::member_role,
+    rate_limits::LimitedAction,
+    twitter::builder::TwitterClient,
+};
+
+/// Prefix of the callback data of Undo buttons.
+pub const UNDO: &str = "undo";
+
+/// The inverse of a tweet, like or retweet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Undo {
+    Delete,
+    Unlike,
+    Unretweet,
+}
+
+impl Undo {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Delete => "delete",
+            Self::Unlike => "unlike",
+            Self::Unretweet => "unretweet",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "delete" => Some(Self::Delete),
+            "unlike" => Some(Self::Unlike),
+            "unretweet" => Some(Self::Unretweet),
+            _ => None,
+        }
+    }
+
+    /// Undoing needs the same role as doing.
+    pub fn required_role(&self) -> Role {
+        match self {
+            Self::Delete => Role::Poster,
+            Self::Unlike | Self::Unretweet => Role::Reacter,
+        }
+    }
+
+    pub fn action(&self) -> Action {
+        match self {
+            Self::Delete => Action::Delete,
+            Self::Unlike => Action::Like,
+            Self::Unretweet => Action::Retweet,
+        }
+    }
+
+    pub fn done_message(&self) -> &'static str {
+        match self {
+            Self::Delete => "Tweet deleted",
+            Self::Unlike => "Like removed",
+            Self::Unretweet => "Retweet removed",
+        }
+    }
+
+    pub async fn apply(
+        &self,
+        client: &TwitterClient<'_>,
+        x_id: String,
+        tweet_id: String,
+    ) -> eyre::Result<()> {
+        match self {
+            Self::Delete => client.delete_tweet(tweet_id).await,
+            Self::Unlike => client.unlike(x_id, tweet_id).await,
+            Self::Unretweet => client.unretweet(x_id, tweet_id).await,
+        }
+    }
+}
+
+/// An Undo button for what `x_id` just did to `tweet_id`.
+pub fn undo_keyboard(undo: Undo, x_id: &str, tweet_id: &str) -> InlineKeyboardMarkup {
+    let data = format!("{}:{}:{}:{}", UNDO, undo.as_str(), x_id, tweet_id);
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback("Undo", data)]])
+}
+
+/// The account `x_id` as this chat may use it, either linked to the chat or
+/// delegated to it.
+fn chat_account(
+    shared_state: &SharedState,
+    chat_id: &str,
+    x_id: &str,
+) -> eyre::Result<Option<ActingAccount>> {
+    let db = shared_state.db.as_ref();
+    if let Some(user) = db
+        .get_chat_users(chat_id)?
+        .into_iter()
+        .find(|user| user.x_id == x_id)
+    {
+        return Ok(Some(ActingAccount {
+            user,
+            chat_id: chat_id.to_string(),
+            delegation: None,
+        }));
+    }
+    Ok(delegated_accounts(db, chat_id)?
+        .into_iter()
+        .find(|account| account.user.x_id == x_id))
+}
+
+/// Handles a press of Undo, whose callback data follows the [`UNDO`] prefix,
+/// and returns the text to show the member who pressed it.
+pub async fn handle_undo(
+    bot: &Bot,
+    shared_state: &SharedState,
+    q: &CallbackQuery,
+    data: &str,
+) -> eyre::Result<String> {
+    let Some(message) = &q.message else {
+        return Ok("Unknown button".to_string());
+    };
+    let mut fields = data.splitn(3, ':');
+    let (Some(undo), Some(x_id), Some(tweet_id)) = (
+        fields.next().and_then(Undo::parse),
+        fields.next(),
+        fields.next(),
+    ) else {
+        return Ok("Unknown button".to_string());
+    };
+    let role = member_role(bot, shared_state.db.as_ref(), &message.chat, q.from.id).await?;
+    if role < undo.required_role() {
+        return Ok(format!(
+            "You need the {} role to undo this",
+            undo.required_role().as_str()
+        ));
+    }
+    let chat_id = message.chat.id.to_string();
+    let Some(account) = chat_account(shared_state, &chat_id, x_id)? else {
+        return Ok("The account is no longer available to this chat".to_string());
+    };
+    if account.user.revoked {
+        return Ok("The bot's access to this account was revoked".to_string());
+    }
+    if let Some((_, delegation)) = &account.delegation {
+        if !delegation.actions.contains(&undo.action()) {
+            return Ok("The delegation no longer allows this".to_string());
+        }
+    }
+    // Undoing counts like doing, so the button is no way around the limits.
+    let draws = shared_state.rate_limits.draws(
+        x_id,
+        Some(q.from.id.0),
+        &[(LimitedAction::from(undo.action()), 1)],
+    );
+    if let Some(wait_secs) = shared_state.db.draw_rate_limits(&draws)? {
+        return Ok(format!(
+            "Slow down, too many actions. Try again in {}.",
+            format_wait(wait_secs)
+        ));
+    }
+    if let Some((code, _)) = &account.delegation {
+        if shared_state.db.consume_delegation(code, 1)?.is_none() {
+            return Ok("The delegation expired or has no actions left".to_string());
+        }
+    }
+    let user = shared_state
+        .refresh_credentials(&account.chat_id, account.user)
+        .await?;
+    let client = shared_state.twitter.with_auth(user.credentials.clone());
+    undo.apply(&client, user.x_id, tweet_id.to_string()).await?;
+    let text = format!(
+        "{}\n\n{} by {}",
+        message.text().unwrap_or_default(),
+        undo.done_message(),
+        q.from.full_name()
+    );
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .await?;
+    Ok(undo.done_message().to_string())
+}
@@ -1,27 +1,35 @@
-use std::sync::Arc;
-
-use db::InMemoryDB;
-use endpoints::{callback, SharedState};
-use futures_util::StreamExt;
+use accounts::validate_accounts;
+use db::{StorageBackend, TokenCipher};
+use endpoints::{callback, oauth2_callback, purge_expired_oauth_tokens, SharedState};
 use handlers::{
+    approvals::{callback_handler, expire_pending_posts},
     basic_commands::{command_handler, BasicCommand},
+    media::{has_attachment, media_message_handler, AlbumCollector},
+    scheduled::resume_scheduled_sends,
     twitter_commands::{twitter_command_handler, TwitterCommand},
 };
+use policy::Policy;
+use rate_limits::RateLimits;
+use signed_state::StateSigner;
+use std::sync::Arc;
 use teloxide::{
     dispatching::{HandlerExt, UpdateFilterExt},
     dptree,
-    net::Download,
     prelude::Dispatcher,
     requests::Requester,
     types::{Message, Update},
-    utils::command::BotCommands,
     Bot,
 };
-use tokio::sync::Mutex;
-use twitter::builder::TwitterBuilder;
+use twitter::{auth::OOB_CALLBACK, builder::TwitterBuilder, oauth2::OAuth2Config};
+mod accounts;
 mod db;
+mod durations;
 mod endpoints;
 mod handlers;
+mod permissions;
+mod policy;
+mod rate_limits;
+mod signed_state;
 mod twitter;
 
 #[tokio::main]
@@ -44,27 +52,120 @@ async fn main() {
     let app_secret =
         std::env::var("TWITTER_CONSUMER_SECRET").expect("TWITTER_CONSUMER_SECRET not set");
     let db_path = std::env::var("DB_PATH").expect("DB_PATH not set");
+    let db_backend = std::env::var("DB_BACKEND")
+        .map(|b| b.parse::<StorageBackend>().expect("Invalid DB_BACKEND"))
+        .unwrap_or(StorageBackend::Memory);
+
+    let db_key = std::env::var("DB_ENCRYPTION_KEY").expect("DB_ENCRYPTION_KEY not set");
+    let previous_db_keys: Vec<String> = std::env::var("DB_PREVIOUS_ENCRYPTION_KEYS")
+        .map(|keys| {
+            keys.split(',')
+                .filter(|k| !k.trim().is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    let cipher = TokenCipher::new(&db_key, &previous_db_keys).expect("Invalid encryption key");
+    let rotate_keys = cipher.has_previous_keys();
+
+    let db = db::open(db_backend, &db_path, cipher).expect("Failed to open database");
+    if rotate_keys {
+        let count = db
+            .reencrypt_tokens()
+            .expect("Failed to re-encrypt tokens with DB_ENCRYPTION_KEY");
+        log::info!(
+            "Re-encrypted {} accounts with the new key, DB_PREVIOUS_ENCRYPTION_KEYS can be removed",
+            count
+        );
+    }
+
+    let oauth_token_ttl_secs = std::env::var("OAUTH_TOKEN_TTL_SECS")
+        .map(|ttl| ttl.parse().expect("Invalid OAUTH_TOKEN_TTL_SECS"))
+        .unwrap_or(15 * 60);
+    tokio::spawn(purge_expired_oauth_tokens(db.clone(), oauth_token_ttl_secs));
+
+    // Without a publicly reachable callback, Twitter shows the user a PIN.
+    let callback_url = std::env::var("CALLBACK_URL")
+        .ok()
+        .filter(|url| !url.is_empty() && url != OOB_CALLBACK);
+
+    let state_signer = match std::env::var("CALLBACK_STATE_SECRET") {
+        Ok(secret) => StateSigner::new(&secret).expect("Invalid CALLBACK_STATE_SECRET"),
+        Err(_) if callback_url.is_none() => StateSigner::random(),
+        Err(_) => panic!("CALLBACK_STATE_SECRET not set"),
+    };
+
+    // Media attached to a held post must still be valid when it is sent, and
+    // Twitter expires uploads after 24 hours.
+    let approval_ttl_secs = std::env::var("APPROVAL_TTL_SECS")
+        .map(|ttl| ttl.parse().expect("Invalid APPROVAL_TTL_SECS"))
+        .unwrap_or(6 * 60 * 60);
 
-    let db = InMemoryDB::load_or_create(&db_path);
+    let policy = match std::env::var("POLICY_PATH") {
+        Ok(path) if !path.is_empty() => Policy::load(&path).expect("Invalid POLICY_PATH"),
+        _ => Policy::default(),
+    };
+
+    let rate_limits = std::env::var("RATE_LIMITS")
+        .map(|limits| limits.parse().expect("Invalid RATE_LIMITS"))
+        .unwrap_or_else(|_| RateLimits::default());
+
+    // OAuth 2.0 logins need both a registered client and a reachable callback.
+    let mut twitter = TwitterBuilder::new(app_key, app_secret);
+    let client_id = std::env::var("TWITTER_CLIENT_ID")
+        .ok()
+        .filter(|id| !id.is_empty());
+    match (client_id, &callback_url) {
+        (Some(client_id), Some(callback_url)) => {
+            twitter = twitter.with_oauth2(OAuth2Config {
+                client_id,
+                client_secret: std::env::var("TWITTER_CLIENT_SECRET")
+                    .ok()
+                    .filter(|secret| !secret.is_empty()),
+                redirect_uri: format!("{}/oauth2/callback", callback_url),
+            });
+        }
+        (Some(_), None) => log::warn!("TWITTER_CLIENT_ID is ignored without CALLBACK_URL"),
+        _ => {}
+    }
 
     let shared_state = SharedState {
-        db: Arc::new(Mutex::new(db)),
+        db,
         bot: bot.clone(),
         bot_name,
-        twitter: TwitterBuilder::new(app_key, app_secret),
+        twitter,
+        oauth_token_ttl_secs,
+        state_signer,
+        callback_url,
+        approval_ttl_secs,
+        policy: Arc::new(policy),
+        rate_limits,
+        albums: AlbumCollector::default(),
+        countdowns: Default::default(),
+        refresh_locks: Default::default(),
     };
+    tokio::spawn(expire_pending_posts(shared_state.clone()));
 
-    let app = axum::Router::new()
-        .route("/callback", axum::routing::get(callback))
-        // .layer(CorsLayer::very_permissive())
-        .with_state(shared_state.clone());
+    tokio::spawn(resume_scheduled_sends(shared_state.clone()));
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:4000").await.unwrap();
-    tokio::spawn(async move {
-        axum::serve(listener, app).await.unwrap();
-    });
+    tokio::spawn(validate_accounts(shared_state.clone()));
 
-    let handler = Update::filter_message()
+    if shared_state.callback_url.is_some() {
+        let app = axum::Router::new()
+            .route("/callback", axum::routing::get(callback))
+            .route("/oauth2/callback", axum::routing::get(oauth2_callback))
+            // .layer(CorsLayer::very_permissive())
+            .with_state(shared_state.clone());
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:4000").await.unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+    } else {
+        log::info!("CALLBACK_URL not set, logins are completed with /verify");
+    }
+
+    let messages = Update::filter_message()
         .branch(
             dptree::entry()
                 .filter_command::<BasicCommand>()
@@ -72,62 +173,23 @@ async fn main() {
         )
         .branch(dptree::entry().filter_command::<TwitterCommand>().endpoint(
             |bot: Bot, shared_state: SharedState, msg: Message, cmd: TwitterCommand| async move {
-                let res = twitter_command_handler(bot, shared_state, cmd, msg.chat.id, None).await;
+                let res = twitter_command_handler(bot, shared_state, cmd, msg, Vec::new()).await;
                 if let Err(e) = res {
                     log::error!("Error handling twitter command: {:?}", e);
                 }
                 Ok(())
             },
         ))
-        .branch(
-            dptree::filter(|msg: Message| msg.photo().is_some()).endpoint(
-                |bot: Bot, msg: Message, shared_state: SharedState| async move {
-                    let photos = msg.photo().unwrap();
-                    let photo = photos.iter().max_by_key(|p| p.file.size).unwrap().clone();
-                    let file = bot.get_file(&photo.file.id).await?;
-                    let download = bot.download_file_stream(&file.path);
-                    let buffer = download
-                        .fold(Vec::new(), |mut vec, chunk| {
-                            vec.extend_from_slice(&chunk.unwrap());
-                            async { vec }
-                        })
-                        .await;
-
-                    let caption: Option<TwitterCommand> = msg
-                        .caption()
-                        .map(|c| TwitterCommand::parse(c, &shared_state.bot_name).unwrap());
-
-                    if let Some(cmd) = caption {
-                        let res = twitter_command_handler(
-                            bot,
-                            shared_state,
-                            cmd,
-                            msg.chat.id,
-                            Some(buffer),
-                        )
-                        .await;
-                        if let Err(e) = res {
-                            log::error!("Error handling twitter command: {:?}", e);
-                        }
-                    }
-                    Ok(())
-                },
-            ),
-        );
+        .branch(dptree::filter(has_attachment).endpoint(media_message_handler));
+
+    let handler = dptree::entry()
+        .branch(messages)
+        .branch(Update::filter_callback_query().endpoint(callback_handler));
 
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![shared_state.clone()])
+        .dependencies(dptree::deps![shared_state])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
         .await;
-
-    shared_state
-        .db
-        .lock()
-        .await
-        .save(&db_path)
-        .expect("Failed to save db");
-
-    log::info!("Saved db to {}", db_path);
 }
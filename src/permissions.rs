@@ -0,0 +1,101 @@
+use teloxide::{
+    prelude::Requester,
+    types::{Chat, Message, UserId},
+    Bot,
+};
+
+use crate::db::{Role, Storage};
+
+/// The role of whoever sent `msg`. In private chats that is always the owner.
+/// In groups an explicitly granted role wins, except that the chat's creator
+/// is always an owner; other chat admins default to owner and everyone else
+/// to viewer.
+pub async fn caller_role(bot: &Bot, db: &dyn Storage, msg: &Message) -> eyre::Result<Role> {
+    if msg.chat.is_private() {
+        return Ok(Role::Owner);
+    }
+    // Admins posting anonymously appear as the group itself.
+    if msg.sender_chat().is_some_and(|c| c.id == msg.chat.id) {
+        return Ok(Role::Owner);
+    }
+    let Some(from) = msg.from() else {
+        return Ok(Role::Viewer);
+    };
+    member_role(bot, db, &msg.chat, from.id).await
+}
+
+/// The role of Telegram user `user_id` in `chat`, by the same rules as
+/// [`caller_role`].
+pub async fn member_role(
+    bot: &Bot,
+    db: &dyn Storage,
+    chat: &Chat,
+    user_id: UserId,
+) -> eyre::Result<Role> {
+    if chat.is_private() {
+        return Ok(Role::Owner);
+    }
+    let member = bot.get_chat_member(chat.id, user_id).await?;
+    if member.is_owner() {
+        return Ok(Role::Owner);
+    }
+    if let Some(role) = db.get_role(&chat.id.to_string(), user_id.0)? {
+        return Ok(role);
+    }
+    Ok(if member.is_administrator() {
+        Role::Owner
+    } else {
+        Role::Viewer
+    })
+}
+
+/// The role Telegram user `user_id` falls back to in `chat` without a granted
+/// one.
+pub async fn default_role(bot: &Bot, chat: &Chat, user_id: UserId) -> eyre::Result<Role> {
+    if chat.is_private() {
+        return Ok(Role::Owner);
+    }
+    let member = bot.get_chat_member(chat.id, user_id).await?;
+    Ok(if member.is_privileged() {
+        Role::Owner
+    } else {
+        Role::Viewer
+    })
+}
+
+/// Whether the sender of `msg` created the chat. Only the creator can make
+/// or unmake owners, so owners cannot hand out or take away their own role.
+/// Anonymous admins cannot be told apart, so they never count as the creator.
+pub async fn is_chat_creator(bot: &Bot, msg: &Message) -> eyre::Result<bool> {
+    if msg.chat.is_private() {
+        return Ok(true);
+    }
+    let Some(from) = msg.from() else {
+        return Ok(false);
+    };
+    if msg.sender_chat().is_some() {
+        return Ok(false);
+    }
+    Ok(bot.get_chat_member(msg.chat.id, from.id).await?.is_owner())
+}
+
+/// Checks that the sender of `msg` has at least `required`, telling them if
+/// they do not.
+pub async fn require_role(
+    bot: &Bot,
+    db: &dyn Storage,
+    msg: &Message,
+    required: Role,
+) -> eyre::Result<bool> {
+    let role = caller_role(bot, db, msg).await?;
+    if role >= required {
+        return Ok(true);
+    }
+    let to_send = format!(
+        "This needs the {} role, you are a {}",
+        required.as_str(),
+        role.as_str()
+    );
+    bot.send_message(msg.chat.id, to_send).await?;
+    Ok(false)
+}
@@ -0,0 +1,411 @@
+use std::{fmt, sync::OnceLock};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::twitter::{text::BARE_TLDS, tweet::Tweet};
+
+/// Rules from the `POLICY_PATH` JSON file. Every field is optional, e.g.
+///
+/// ```json
+/// {
+///     "banned_words": ["giveaway"],
+///     "banned_patterns": ["(?i)dm me"],
+///     "required_hashtags": ["ad"],
+///     "forbidden_hashtags": ["nsfw"],
+///     "allowed_domains": ["example.com"],
+///     "denied_domains": ["bit.ly"],
+///     "media": "required",
+///     "delegates": { "media": "forbidden" }
+/// }
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct PolicyConfig {
+    /// Whole words, matched case-insensitively.
+    banned_words: Vec<String>,
+    banned_patterns: Vec<String>,
+    /// Each post must carry all of these, with or without the `#`.
+    required_hashtags: Vec<String>,
+    forbidden_hashtags: Vec<String>,
+    /// When not empty, links may only point to these domains or their
+    /// subdomains.
+    allowed_domains: Vec<String>,
+    denied_domains: Vec<String>,
+    media: MediaRule,
+    /// Additional rules for posts made through a delegation.
+    delegates: Option<Box<PolicyConfig>>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum MediaRule {
+    #[default]
+    Any,
+    Required,
+    Forbidden,
+}
+
+/// Why a post was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    BannedWord(String),
+    BannedPattern(String),
+    MissingHashtag(String),
+    ForbiddenHashtag(String),
+    DomainNotAllowed(String),
+    DomainDenied(String),
+    MediaRequired,
+    MediaForbidden,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BannedWord(word) => write!(f, "the word \"{}\" is not allowed", word),
+            Self::BannedPattern(pattern) => {
+                write!(f, "the text matches the banned pattern {}", pattern)
+            }
+            Self::MissingHashtag(tag) => write!(f, "posts must include #{}", tag),
+            Self::ForbiddenHashtag(tag) => write!(f, "the hashtag #{} is not allowed", tag),
+            Self::DomainNotAllowed(domain) => {
+                write!(f, "links to {} are not on the allow list", domain)
+            }
+            Self::DomainDenied(domain) => write!(f, "links to {} are not allowed", domain),
+            Self::MediaRequired => write!(f, "posts must include media"),
+            Self::MediaForbidden => write!(f, "posts cannot include media"),
+        }
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+#[derive(Debug)]
+struct Rules {
+    banned_words: Vec<(String, Regex)>,
+    banned_patterns: Vec<Regex>,
+    required_hashtags: Vec<String>,
+    forbidden_hashtags: Vec<String>,
+    allowed_domains: Vec<String>,
+    denied_domains: Vec<String>,
+    media: MediaRule,
+}
+
+/// Content rules every post is checked against before it is sent or held
+/// for approval.
+#[derive(Debug, Default)]
+pub struct Policy {
+    rules: Option<Rules>,
+    delegates: Option<Rules>,
+}
+
+fn normalize_hashtag(tag: &str) -> String {
+    tag.trim_start_matches(['#', '＃']).to_lowercase()
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_start_matches("www.").to_lowercase()
+}
+
+/// Whether `host` is `domain` or one of its subdomains.
+fn host_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.'))
+}
+
+impl Rules {
+    fn compile(config: &PolicyConfig) -> eyre::Result<Self> {
+        let banned_words = config
+            .banned_words
+            .iter()
+            .map(|word| {
+                let regex = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(word)))?;
+                Ok((word.clone(), regex))
+            })
+            .collect::<eyre::Result<_>>()?;
+        let banned_patterns = config
+            .banned_patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            banned_words,
+            banned_patterns,
+            required_hashtags: config
+                .required_hashtags
+                .iter()
+                .map(|t| normalize_hashtag(t))
+                .collect(),
+            forbidden_hashtags: config
+                .forbidden_hashtags
+                .iter()
+                .map(|t| normalize_hashtag(t))
+                .collect(),
+            allowed_domains: config
+                .allowed_domains
+                .iter()
+                .map(|d| normalize_domain(d))
+                .collect(),
+            denied_domains: config
+                .denied_domains
+                .iter()
+                .map(|d| normalize_domain(d))
+                .collect(),
+            media: config.media,
+        })
+    }
+
+    fn check(
+        &self,
+        tweet: &Tweet,
+        has_media: bool,
+        patterns: &Patterns,
+    ) -> Result<(), PolicyViolation> {
+        let text = tweet.text();
+        if let Some((word, _)) = self.banned_words.iter().find(|(_, re)| re.is_match(text)) {
+            return Err(PolicyViolation::BannedWord(word.clone()));
+        }
+        if let Some(pattern) = self.banned_patterns.iter().find(|re| re.is_match(text)) {
+            return Err(PolicyViolation::BannedPattern(pattern.to_string()));
+        }
+
+        let hashtags: Vec<String> = patterns
+            .hashtag
+            .captures_iter(text)
+            .map(|c| c[1].to_lowercase())
+            .collect();
+        if let Some(tag) = self
+            .required_hashtags
+            .iter()
+            .find(|tag| !hashtags.contains(tag))
+        {
+            return Err(PolicyViolation::MissingHashtag(tag.clone()));
+        }
+        if let Some(tag) = hashtags
+            .iter()
+            .find(|tag| self.forbidden_hashtags.contains(tag))
+        {
+            return Err(PolicyViolation::ForbiddenHashtag(tag.clone()));
+        }
+
+        for host in patterns.link_hosts(text) {
+            let host = host.trim_start_matches("www.");
+            if self.denied_domains.iter().any(|d| host_matches(host, d)) {
+                return Err(PolicyViolation::DomainDenied(host.to_string()));
+            }
+            if !self.allowed_domains.is_empty()
+                && !self.allowed_domains.iter().any(|d| host_matches(host, d))
+            {
+                return Err(PolicyViolation::DomainNotAllowed(host.to_string()));
+            }
+        }
+
+        match (self.media, has_media) {
+            (MediaRule::Required, false) => Err(PolicyViolation::MediaRequired),
+            (MediaRule::Forbidden, true) => Err(PolicyViolation::MediaForbidden),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Extractors shared by every rule set.
+struct Patterns {
+    hashtag: Regex,
+    /// Links with a scheme, capturing the host, or bare domains on one of
+    /// the top level domains Twitter links without a scheme.
+    link: Regex,
+}
+
+impl Patterns {
+    /// The lowercased hosts of the links in `text`. Domains of email
+    /// addresses are not links.
+    fn link_hosts<'a>(&'a self, text: &'a str) -> impl Iterator<Item = String> + 'a {
+        self.link.captures_iter(text).filter_map(move |c| {
+            if let Some(host) = c.get(1) {
+                return Some(host.as_str().trim_end_matches('.').to_lowercase());
+            }
+            let domain = c.get(2)?;
+            if text[..domain.start()].ends_with('@') {
+                return None;
+            }
+            Some(domain.as_str().to_lowercase())
+        })
+    }
+}
+
+fn patterns() -> &'static Patterns {
+    static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+    PATTERNS.get_or_init(|| Patterns {
+        hashtag: Regex::new(r"(?:^|[^\w&])[#＃](\w+)").unwrap(),
+        link: Regex::new(&format!(
+            r"(?i)\bhttps?://(?:[^\s/?#@]*@)?([^\s/?#:@]+)|\b((?:[a-z0-9-]+\.)+(?:{}))\b",
+            BARE_TLDS
+        ))
+        .unwrap(),
+    })
+}
+
+impl Policy {
+    /// Reads and compiles the policy at `path`.
+    pub fn load(path: &str) -> eyre::Result<Self> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    fn parse(json: &[u8]) -> eyre::Result<Self> {
+        let config: PolicyConfig = serde_json::from_slice(json)?;
+        let delegates = match &config.delegates {
+            Some(delegates) if delegates.delegates.is_some() => {
+                eyre::bail!("Delegate rules cannot be nested");
+            }
+            Some(delegates) => Some(Rules::compile(delegates)?),
+            None => None,
+        };
+        Ok(Self {
+            rules: Some(Rules::compile(&config)?),
+            delegates,
+        })
+    }
+
+    /// Checks `tweet` against the policy, and also against the delegate
+    /// rules when it is posted through a delegation.
+    pub fn check(&self, tweet: &Tweet, delegated: bool) -> Result<(), PolicyViolation> {
+        self.check_draft(tweet, tweet.has_media(), delegated)
+    }
+
+    /// Like [`Policy::check`], for a tweet whose media is not uploaded yet.
+    pub fn check_draft(
+        &self,
+        tweet: &Tweet,
+        has_media: bool,
+        delegated: bool,
+    ) -> Result<(), PolicyViolation> {
+        let patterns = patterns();
+        if let Some(rules) = &self.rules {
+            rules.check(tweet, has_media, patterns)?;
+        }
+        match &self.delegates {
+            Some(rules) if delegated => rules.check(tweet, has_media, patterns),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(json: &str) -> Policy {
+        Policy::parse(json.as_bytes()).unwrap()
+    }
+
+    fn check(policy: &Policy, text: &str) -> Result<(), PolicyViolation> {
+        policy.check_draft(&Tweet::new(text.to_string()), false, false)
+    }
+
+    #[test]
+    fn bans_whole_words_only() {
+        let policy = policy(r#"{ "banned_words": ["scam"], "banned_patterns": ["(?i)dm me"] }"#);
+        assert_eq!(
+            check(&policy, "Not a SCAM, promise"),
+            Err(PolicyViolation::BannedWord("scam".to_string()))
+        );
+        assert!(check(&policy, "Scampi for dinner").is_ok());
+        assert!(matches!(
+            check(&policy, "DM me for details"),
+            Err(PolicyViolation::BannedPattern(_))
+        ));
+    }
+
+    #[test]
+    fn checks_hashtags() {
+        let policy =
+            policy(r##"{ "required_hashtags": ["#Ad"], "forbidden_hashtags": ["＃nsfw"] }"##);
+        assert!(check(&policy, "New release #ad").is_ok());
+        assert!(check(&policy, "New release ＃AD").is_ok());
+        assert_eq!(
+            check(&policy, "New release"),
+            Err(PolicyViolation::MissingHashtag("ad".to_string()))
+        );
+        // Only a # at the start of a word makes a hashtag.
+        assert!(check(&policy, "New release a#ad &#ad").is_err());
+        assert_eq!(
+            check(&policy, "#ad #NSFW"),
+            Err(PolicyViolation::ForbiddenHashtag("nsfw".to_string()))
+        );
+    }
+
+    #[test]
+    fn matches_domains_and_subdomains() {
+        assert!(host_matches("example.com", "example.com"));
+        assert!(host_matches("blog.example.com", "example.com"));
+        assert!(!host_matches("badexample.com", "example.com"));
+        assert!(!host_matches("example.com.evil.io", "example.com"));
+
+        let policy =
+            policy(r#"{ "allowed_domains": ["www.Example.com"], "denied_domains": ["bit.ly"] }"#);
+        assert!(check(&policy, "See https://blog.example.com/post").is_ok());
+        assert!(check(&policy, "See www.example.com.").is_ok());
+        assert_eq!(
+            check(&policy, "See http://bit.ly/x"),
+            Err(PolicyViolation::DomainDenied("bit.ly".to_string()))
+        );
+        assert_eq!(
+            check(&policy, "See other.io/page"),
+            Err(PolicyViolation::DomainNotAllowed("other.io".to_string()))
+        );
+        // The host of a link with a scheme counts, whatever its top level
+        // domain, and credentials before it do not hide it.
+        assert_eq!(
+            check(&policy, "https://example.com@evil.example"),
+            Err(PolicyViolation::DomainNotAllowed(
+                "evil.example".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn ignores_text_that_is_not_a_link() {
+        let policy = policy(r#"{ "allowed_domains": ["example.com"] }"#);
+        assert!(check(&policy, "Built with node.js, e.g. for file.txt").is_ok());
+        assert!(check(&policy, "Mail me at me@other.com").is_ok());
+        assert!(check(&policy, "Mail me at me@mail.other.com").is_ok());
+    }
+
+    #[test]
+    fn checks_media() {
+        let policy = policy(r#"{ "media": "required" }"#);
+        let tweet = Tweet::new("photo".to_string());
+        assert_eq!(
+            policy.check_draft(&tweet, false, false),
+            Err(PolicyViolation::MediaRequired)
+        );
+        assert!(policy.check_draft(&tweet, true, false).is_ok());
+    }
+
+    #[test]
+    fn applies_delegate_rules_to_delegates_only() {
+        let policy =
+            policy(r#"{ "banned_words": ["spam"], "delegates": { "media": "forbidden" } }"#);
+        let tweet = Tweet::new("photo".to_string());
+        assert!(policy.check_draft(&tweet, true, false).is_ok());
+        assert_eq!(
+            policy.check_draft(&tweet, true, true),
+            Err(PolicyViolation::MediaForbidden)
+        );
+        // The base rules still apply to delegates.
+        let spam = Tweet::new("spam".to_string());
+        assert!(policy.check_draft(&spam, false, true).is_err());
+        // Without a policy file nothing is checked.
+        assert!(Policy::default().check_draft(&spam, false, true).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_policies() {
+        assert!(Policy::parse(br#"{ "delegates": { "delegates": {} } }"#).is_err());
+        assert!(Policy::parse(br#"{ "banned_patterns": ["("] }"#).is_err());
+        assert!(Policy::parse(br#"{ "media": "sometimes" }"#).is_err());
+        assert!(Policy::parse(br#"{ "banned_word": ["typo"] }"#).is_err());
+    }
+}
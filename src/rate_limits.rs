@@ -0,0 +1,230 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    db::{Action, BucketDraw, RateLimit},
+    durations::parse_duration,
+};
+
+/// What a rate limit counts. Tweets, replies, quotes and deletes are all
+/// posts, so deleting and reposting cannot get around the post limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitedAction {
+    Post,
+    Like,
+    Retweet,
+    Upload,
+}
+
+impl LimitedAction {
+    const ALL: &'static [LimitedAction] = &[Self::Post, Self::Like, Self::Retweet, Self::Upload];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Post => "post",
+            Self::Like => "like",
+            Self::Retweet => "retweet",
+            Self::Upload => "upload",
+        }
+    }
+}
+
+impl From<Action> for LimitedAction {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Tweet | Action::Reply | Action::Quote | Action::Delete => Self::Post,
+            Action::Like => Self::Like,
+            Action::Retweet => Self::Retweet,
+        }
+    }
+}
+
+/// Who a rate limit applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Scope {
+    /// A linked Twitter account, across every chat using it.
+    Account,
+    /// A Telegram user, across every chat and account.
+    Member,
+}
+
+impl Scope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Account => "account",
+            Self::Member => "member",
+        }
+    }
+}
+
+/// Token bucket limits by scope and action, overridable with `RATE_LIMITS`.
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    limits: HashMap<(Scope, LimitedAction), RateLimit>,
+}
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+impl Default for RateLimits {
+    /// Well below Twitter's own per-user limits, so a busy chat is slowed
+    /// down before the app's keys are.
+    fn default() -> Self {
+        let limit = |capacity, period_secs| RateLimit {
+            capacity,
+            period_secs,
+        };
+        Self {
+            limits: HashMap::from([
+                ((Scope::Account, LimitedAction::Post), limit(100, DAY)),
+                ((Scope::Account, LimitedAction::Like), limit(200, DAY)),
+                ((Scope::Account, LimitedAction::Retweet), limit(100, DAY)),
+                ((Scope::Account, LimitedAction::Upload), limit(100, DAY)),
+                ((Scope::Member, LimitedAction::Post), limit(20, HOUR)),
+                ((Scope::Member, LimitedAction::Like), limit(60, HOUR)),
+                ((Scope::Member, LimitedAction::Retweet), limit(30, HOUR)),
+                ((Scope::Member, LimitedAction::Upload), limit(20, HOUR)),
+            ]),
+        }
+    }
+}
+
+impl FromStr for RateLimits {
+    type Err = eyre::Report;
+
+    /// Parses comma separated overrides of the defaults such as
+    /// `account:post=50/1d,member:like=off`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = Self::default();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parsed = entry.split_once('=').and_then(|(target, limit)| {
+                let (scope, action) = target.split_once(':')?;
+                let scope = [Scope::Account, Scope::Member]
+                    .into_iter()
+                    .find(|s| s.as_str() == scope)?;
+                let action = LimitedAction::ALL
+                    .iter()
+                    .copied()
+                    .find(|a| a.as_str() == action)?;
+                let limit = match limit {
+                    "off" => None,
+                    limit => {
+                        let (capacity, period) = limit.split_once('/')?;
+                        Some(RateLimit {
+                            capacity: capacity.parse().ok().filter(|c| *c > 0)?,
+                            period_secs: parse_duration(period)?,
+                        })
+                    }
+                };
+                Some(((scope, action), limit))
+            });
+            match parsed {
+                Some((key, Some(limit))) => {
+                    limits.limits.insert(key, limit);
+                }
+                Some((key, None)) => {
+                    limits.limits.remove(&key);
+                }
+                None => eyre::bail!("Invalid rate limit: {}", entry),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+impl RateLimits {
+    /// The buckets a command by Telegram user `member` acting as `x_id`
+    /// draws from, `count` tokens per action. Anonymous senders are only
+    /// limited per account.
+    pub fn draws(
+        &self,
+        x_id: &str,
+        member: Option<u64>,
+        actions: &[(LimitedAction, u32)],
+    ) -> Vec<BucketDraw> {
+        let mut draws = Vec::new();
+        for &(action, cost) in actions {
+            let mut targets = vec![(Scope::Account, x_id.to_string())];
+            if let Some(member) = member {
+                targets.push((Scope::Member, member.to_string()));
+            }
+            for (scope, id) in targets {
+                if let Some(limit) = self.limits.get(&(scope, action)) {
+                    draws.push(BucketDraw {
+                        key: format!("{}:{}:{}", scope.as_str(), id, action.as_str()),
+                        limit: *limit,
+                        cost,
+                    });
+                }
+            }
+        }
+        draws
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(draws: &[BucketDraw]) -> Vec<(&str, u32, u32)> {
+        draws
+            .iter()
+            .map(|d| (d.key.as_str(), d.limit.capacity, d.cost))
+            .collect()
+    }
+
+    #[test]
+    fn parses_overrides() {
+        let limits: RateLimits = " account:post=50/1d, member:like=off,".parse().unwrap();
+        let post = limits.limits[&(Scope::Account, LimitedAction::Post)];
+        assert_eq!((post.capacity, post.period_secs), (50, DAY));
+        assert!(!limits
+            .limits
+            .contains_key(&(Scope::Member, LimitedAction::Like)));
+        // Everything else keeps its default.
+        assert_eq!(
+            limits.limits[&(Scope::Member, LimitedAction::Post)],
+            RateLimits::default().limits[&(Scope::Member, LimitedAction::Post)]
+        );
+        assert_eq!("".parse::<RateLimits>().unwrap().limits.len(), 8);
+    }
+
+    #[test]
+    fn rejects_bad_entries() {
+        for entry in [
+            "account:post=0/1d",
+            "account:post=5/0s",
+            "account:post=5",
+            "account:post=5/1w",
+            "chat:post=5/1d",
+            "account:delete=5/1d",
+            "account:post",
+            "post=5/1d",
+        ] {
+            assert!(entry.parse::<RateLimits>().is_err(), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn draws_per_account_and_member() {
+        let limits: RateLimits = "member:upload=off".parse().unwrap();
+        let draws = limits.draws(
+            "42",
+            Some(7),
+            &[
+                (LimitedAction::from(Action::Delete), 1),
+                (LimitedAction::Upload, 3),
+            ],
+        );
+        assert_eq!(
+            keys(&draws),
+            [
+                ("account:42:post", 100, 1),
+                ("member:7:post", 20, 1),
+                ("account:42:upload", 100, 3),
+            ]
+        );
+        // Anonymous senders are only limited per account.
+        let draws = limits.draws("42", None, &[(LimitedAction::Like, 1)]);
+        assert_eq!(keys(&draws), [("account:42:like", 200, 1)]);
+    }
+}
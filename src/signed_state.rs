@@ -0,0 +1,115 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use teloxide::types::ChatId;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_LEN: usize = 8;
+const MAC_LEN: usize = 32;
+
+/// Issues and checks the `state` value carried through an OAuth callback URL,
+/// so a callback can only ever complete a login for the chat that started it.
+#[derive(Clone)]
+pub struct StateSigner {
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for StateSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateSigner").finish_non_exhaustive()
+    }
+}
+
+impl StateSigner {
+    pub fn new(key: &str) -> eyre::Result<Self> {
+        if key.len() < 32 {
+            eyre::bail!("State signing key must be at least 32 characters long");
+        }
+        Ok(Self {
+            key: key.as_bytes().to_vec(),
+        })
+    }
+
+    /// A key that only lives as long as the process, for deployments without a
+    /// callback URL where no state outlives a restart.
+    pub fn random() -> Self {
+        let mut key = vec![0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self { key }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length")
+    }
+
+    pub fn sign(&self, chat_id: ChatId) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let payload = [chat_id.0.to_le_bytes().as_slice(), &nonce].concat();
+        let mut mac = self.mac();
+        mac.update(&payload);
+        let tag = mac.finalize().into_bytes();
+        URL_SAFE_NO_PAD.encode([payload.as_slice(), &tag].concat())
+    }
+
+    /// Returns the chat the state was issued to, or an error if it was not
+    /// issued by this bot.
+    pub fn verify(&self, state: &str) -> eyre::Result<ChatId> {
+        let bytes = URL_SAFE_NO_PAD.decode(state)?;
+        if bytes.len() != 8 + NONCE_LEN + MAC_LEN {
+            eyre::bail!("Malformed callback state");
+        }
+        let (payload, tag) = bytes.split_at(8 + NONCE_LEN);
+        let mut mac = self.mac();
+        mac.update(payload);
+        mac.verify_slice(tag)
+            .map_err(|_| eyre::eyre!("Callback state has an invalid signature"))?;
+        let chat_id = i64::from_le_bytes(payload[..8].try_into()?);
+        Ok(ChatId(chat_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "a callback state secret of 32 chars";
+
+    #[test]
+    fn verifies_its_own_states() {
+        let signer = StateSigner::new(SECRET).unwrap();
+        let state = signer.sign(ChatId(-1001234));
+        assert_eq!(signer.verify(&state).unwrap(), ChatId(-1001234));
+        // Every state is unique, so it can key a pending login.
+        assert_ne!(signer.sign(ChatId(-1001234)), state);
+    }
+
+    #[test]
+    fn refuses_tampered_states() {
+        let signer = StateSigner::new(SECRET).unwrap();
+        let mut bytes = URL_SAFE_NO_PAD.decode(signer.sign(ChatId(1))).unwrap();
+        // Point the state at another chat.
+        bytes[0] ^= 2;
+        let err = signer.verify(&URL_SAFE_NO_PAD.encode(&bytes)).unwrap_err();
+        assert!(err.to_string().contains("invalid signature"));
+
+        let state = signer.sign(ChatId(1));
+        assert!(signer.verify(&state[..state.len() - 2]).is_err());
+        assert!(signer.verify("not base64!").is_err());
+    }
+
+    #[test]
+    fn refuses_states_of_another_secret() {
+        let state = StateSigner::new(SECRET).unwrap().sign(ChatId(1));
+        let other = StateSigner::new("another callback state secret, 32+").unwrap();
+        assert!(other.verify(&state).is_err());
+        assert!(StateSigner::random().verify(&state).is_err());
+    }
+
+    #[test]
+    fn rejects_short_secrets() {
+        assert!(StateSigner::new("too short").is_err());
+    }
+}
@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
 
+use super::{
+    builder::{Signing, TwitterClient},
+    oauth2::{self, OAuth2Token},
+    request::Retry,
+};
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 struct RequestTokenRequestQuery {
     oauth_callback: String,
@@ -12,28 +18,33 @@ struct RequestTokenResponseBody {
     oauth_callback_confirmed: bool,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
-struct CallbackUrlQuery {
-    oauth_token: String,
-    oauth_verifier: String,
-}
-
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TwitterTokenPair {
     pub token: String,
     pub secret: String,
 }
 
-pub async fn request_oauth_token(chat_id: String) -> eyre::Result<TwitterTokenPair> {
+/// How a linked account signs its requests.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum Credentials {
+    /// OAuth 1.0a access token from `/auth`.
+    OAuth1(TwitterTokenPair),
+    /// OAuth 2.0 user context token from `/auth oauth2`.
+    OAuth2(OAuth2Token),
+}
+
+/// Out-of-band callback: instead of redirecting, Twitter shows the user a PIN
+/// that is passed to [`authorize_token`] as the verifier.
+pub const OOB_CALLBACK: &str = "oob";
+
+/// `oauth_callback` is either the URL Twitter redirects to after the user
+/// authorizes the app, or [`OOB_CALLBACK`].
+pub async fn request_oauth_token(oauth_callback: String) -> eyre::Result<TwitterTokenPair> {
     let app_key = std::env::var("TWITTER_CONSUMER_KEY").expect("TWITTER_CONSUMER_KEY not set");
     let app_secret =
         std::env::var("TWITTER_CONSUMER_SECRET").expect("TWITTER_CONSUMER_SECRET not set");
-    let callback_url = std::env::var("CALLBACK_URL").expect("CALLBACK_URL not set");
-    let callback_url = format!("{}/callback?chat_id={}", callback_url, chat_id);
     let secrets = reqwest_oauth1::Secrets::new(app_key, app_secret);
-    let query = RequestTokenRequestQuery {
-        oauth_callback: callback_url.to_string(),
-    };
+    let query = RequestTokenRequestQuery { oauth_callback };
     let response = reqwest_oauth1::Client::new()
         .post("https://api.twitter.com/oauth/request_token")
         .sign(secrets)
@@ -48,7 +59,9 @@ pub async fn request_oauth_token(chat_id: String) -> eyre::Result<TwitterTokenPa
     let response_bytes = response.bytes().await?;
     let request_token_body =
         serde_urlencoded::from_bytes::<RequestTokenResponseBody>(&response_bytes)?;
-    assert!(request_token_body.oauth_callback_confirmed);
+    if !request_token_body.oauth_callback_confirmed {
+        eyre::bail!("Twitter did not confirm the OAuth callback");
+    }
     Ok(TwitterTokenPair {
         token: request_token_body.oauth_token,
         secret: request_token_body.oauth_token_secret,
@@ -105,6 +118,26 @@ pub async fn authorize_token(
     })
 }
 
+impl TwitterClient<'_> {
+    /// Invalidates the credentials the client signs with, so the grant no
+    /// longer works on Twitter's side either.
+    pub async fn invalidate_token(&self) -> eyre::Result<()> {
+        match &self.signing {
+            Signing::OAuth1(_) => {
+                self.execute(Retry::Idempotent, || {
+                    self.post("https://api.twitter.com/1.1/oauth/invalidate_token")
+                })
+                .await?;
+                Ok(())
+            }
+            Signing::OAuth2 { token, config, .. } => {
+                let config = config.ok_or_else(|| eyre::eyre!("OAuth 2.0 is not configured"))?;
+                oauth2::revoke_token(config, token).await
+            }
+        }
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -1,16 +1,30 @@
 use oauth1_request::signature_method::HmacSha1;
 use reqwest_oauth1::{Client, OAuthClientProvider, Secrets, Signer};
 
-use super::auth::TwitterTokenPair;
+use super::{
+    auth::Credentials,
+    oauth2::{OAuth2Config, OAuth2Token},
+};
 
 #[derive(Debug, Clone)]
 pub struct TwitterBuilder {
     pub consumer_key: String,
     pub consumer_secret: String,
+    /// Set when the app is also registered as an OAuth 2.0 client.
+    pub oauth2: Option<OAuth2Config>,
+}
+
+pub(super) enum Signing<'a> {
+    OAuth1(Client<Signer<'a, Secrets<'a>, HmacSha1>>),
+    OAuth2 {
+        client: reqwest::Client,
+        token: OAuth2Token,
+        config: Option<&'a OAuth2Config>,
+    },
 }
 
 pub struct TwitterClient<'a> {
-    pub client: Client<Signer<'a, Secrets<'a>, HmacSha1>>,
+    pub(super) signing: Signing<'a>,
 }
 
 impl TwitterBuilder {
@@ -18,17 +32,41 @@ impl TwitterBuilder {
         Self {
             consumer_key,
             consumer_secret,
+            oauth2: None,
         }
     }
 
-    pub fn with_auth(&self, tokens: TwitterTokenPair) -> TwitterClient {
-        let secrets = Secrets::new(self.consumer_key.clone(), self.consumer_secret.clone())
-            .token(tokens.token, tokens.secret);
+    pub fn with_oauth2(mut self, config: OAuth2Config) -> Self {
+        self.oauth2 = Some(config);
+        self
+    }
 
+    pub fn with_auth(&self, credentials: Credentials) -> TwitterClient<'_> {
         let client = reqwest::Client::new();
-        // client.oauth1(secrets)
-        TwitterClient {
-            client: client.oauth1(secrets),
+        let signing = match credentials {
+            Credentials::OAuth1(tokens) => {
+                let secrets = Secrets::new(self.consumer_key.clone(), self.consumer_secret.clone())
+                    .token(tokens.token, tokens.secret);
+                Signing::OAuth1(client.oauth1(secrets))
+            }
+            Credentials::OAuth2(token) => Signing::OAuth2 {
+                client,
+                token,
+                config: self.oauth2.as_ref(),
+            },
+        };
+        TwitterClient { signing }
+    }
+}
+
+impl TwitterClient<'_> {
+    /// The v1.1 media endpoints only accept OAuth 1.0a user tokens.
+    pub fn require_oauth1(&self) -> eyre::Result<()> {
+        match self.signing {
+            Signing::OAuth1(_) => Ok(()),
+            Signing::OAuth2 { .. } => {
+                eyre::bail!("Media upload requires an account linked with OAuth 1.0a (/auth)")
+            }
         }
     }
 }
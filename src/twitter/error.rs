@@ -0,0 +1,316 @@
+use std::fmt;
+
+use reqwest::{header::HeaderMap, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{db::unix_now, durations::format_wait};
+
+/// Length of Twitter's rate limit windows, assumed when a 429 response does
+/// not say when the limit resets.
+const WINDOW_SECS: u64 = 15 * 60;
+
+/// An unsuccessful response from the Twitter API.
+#[derive(Debug)]
+pub enum TwitterError {
+    /// The credentials were rejected, usually because the app's access was
+    /// revoked.
+    Unauthorized {
+        detail: String,
+    },
+    /// The post is identical to one the account made recently.
+    Duplicate,
+    /// Twitter refused the action, e.g. because the account is suspended,
+    /// the tweet is protected or the content breaks Twitter's rules.
+    Forbidden {
+        detail: String,
+    },
+    /// The tweet or user does not exist or is not visible to the account.
+    NotFound {
+        detail: String,
+    },
+    /// Twitter refused the request until `reset_at`, a Unix timestamp in
+    /// seconds.
+    RateLimited {
+        reset_at: u64,
+    },
+    ServerError {
+        status: StatusCode,
+    },
+    Other {
+        status: StatusCode,
+        detail: String,
+    },
+}
+
+impl fmt::Display for TwitterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unauthorized { detail } => {
+                write!(f, "Twitter rejected the credentials: {}", detail)
+            }
+            Self::Duplicate => write!(f, "Twitter rejected a duplicate post"),
+            Self::Forbidden { detail } => write!(f, "Twitter refused the request: {}", detail),
+            Self::NotFound { detail } => write!(f, "Twitter could not find it: {}", detail),
+            Self::RateLimited { reset_at } => write!(
+                f,
+                "Twitter's rate limit was reached, try again in {}",
+                format_wait(reset_at.saturating_sub(unix_now()))
+            ),
+            Self::ServerError { status } => write!(f, "Twitter failed with {}", status),
+            Self::Other { status, detail } => write!(f, "Twitter returned {}: {}", status, detail),
+        }
+    }
+}
+
+impl std::error::Error for TwitterError {}
+
+/// One entry of the `errors` array, as sent by both API versions.
+#[derive(Deserialize, Debug)]
+struct ProblemError {
+    message: Option<String>,
+    detail: Option<String>,
+    /// v1.1 error code.
+    code: Option<u32>,
+    /// v2 problem type URI.
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+/// The error body of the v2 API, which also covers the `errors` array of
+/// v1.1.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Problem {
+    title: Option<String>,
+    detail: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    errors: Vec<ProblemError>,
+}
+
+/// v1.1 codes for a duplicate status.
+const DUPLICATE_CODES: &[u32] = &[187];
+/// v1.1 codes for a missing status, user or page.
+const NOT_FOUND_CODES: &[u32] = &[8, 34, 50, 63, 144, 421, 422];
+
+impl Problem {
+    fn detail(&self, body: &str) -> String {
+        self.detail
+            .clone()
+            .or_else(|| {
+                self.errors
+                    .iter()
+                    .find_map(|e| e.message.clone().or_else(|| e.detail.clone()))
+            })
+            .or_else(|| self.title.clone())
+            .unwrap_or_else(|| body.chars().take(200).collect())
+    }
+
+    fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.kind
+            .iter()
+            .chain(self.errors.iter().filter_map(|e| e.kind.as_ref()))
+            .map(String::as_str)
+    }
+
+    fn has_code(&self, codes: &[u32]) -> bool {
+        self.errors
+            .iter()
+            .any(|e| e.code.is_some_and(|code| codes.contains(&code)))
+    }
+}
+
+impl TwitterError {
+    /// Classifies an unsuccessful, non rate limited response from its status
+    /// and problem body.
+    fn from_body(status: StatusCode, body: &str) -> Self {
+        let problem: Problem = serde_json::from_str(body).unwrap_or_default();
+        let detail = problem.detail(body);
+        if status.is_server_error() {
+            return Self::ServerError { status };
+        }
+        if status == StatusCode::UNAUTHORIZED {
+            return Self::Unauthorized { detail };
+        }
+        if problem.has_code(DUPLICATE_CODES) || detail.to_lowercase().contains("duplicate") {
+            return Self::Duplicate;
+        }
+        if status == StatusCode::NOT_FOUND
+            || problem.has_code(NOT_FOUND_CODES)
+            || problem
+                .kinds()
+                .any(|kind| kind.ends_with("/resource-not-found"))
+        {
+            return Self::NotFound { detail };
+        }
+        if status == StatusCode::FORBIDDEN {
+            return Self::Forbidden { detail };
+        }
+        Self::Other { status, detail }
+    }
+}
+
+/// The rate limit headers Twitter sends with every response.
+#[derive(Debug, Default)]
+pub struct RateLimitHeaders {
+    pub remaining: Option<u32>,
+    pub reset_at: Option<u64>,
+    /// The daily cap on posts per user and per app, which a 429 may be about
+    /// instead of the endpoint's window.
+    daily: Vec<(Option<u32>, Option<u64>)>,
+    retry_after: Option<u64>,
+}
+
+impl RateLimitHeaders {
+    pub fn parse(headers: &HeaderMap) -> Self {
+        fn number<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+            headers.get(name)?.to_str().ok()?.trim().parse().ok()
+        }
+        Self {
+            remaining: number(headers, "x-rate-limit-remaining"),
+            reset_at: number(headers, "x-rate-limit-reset"),
+            daily: ["x-user-limit-24hour", "x-app-limit-24hour"]
+                .iter()
+                .map(|prefix| {
+                    (
+                        number(headers, &format!("{}-remaining", prefix)),
+                        number(headers, &format!("{}-reset", prefix)),
+                    )
+                })
+                .collect(),
+            retry_after: number(headers, "retry-after"),
+        }
+    }
+
+    /// When every exhausted limit has reset.
+    pub fn reset_at(&self) -> u64 {
+        let exhausted = [(self.remaining, self.reset_at)]
+            .iter()
+            .chain(&self.daily)
+            .filter(|(remaining, _)| *remaining == Some(0))
+            .filter_map(|(_, reset_at)| *reset_at)
+            .max();
+        exhausted
+            .or(self.reset_at)
+            .or_else(|| self.retry_after.map(|secs| unix_now() + secs))
+            .unwrap_or_else(|| unix_now() + WINDOW_SECS)
+    }
+}
+
+/// Turns an unsuccessful response into a [`TwitterError`].
+pub async fn error_for_status(resp: Response) -> eyre::Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let reset_at = RateLimitHeaders::parse(resp.headers()).reset_at();
+        return Err(TwitterError::RateLimited { reset_at }.into());
+    }
+    let body = resp.text().await?;
+    Err(TwitterError::from_body(status, &body).into())
+}
+
+/// Decodes the body of a successful response. Twitter sometimes answers
+/// with a problem instead of the expected data, which becomes a
+/// [`TwitterError`] rather than a decoding error.
+pub async fn decode<T: DeserializeOwned>(resp: Response) -> eyre::Result<T> {
+    let status = resp.status();
+    let body = resp.text().await?;
+    match serde_json::from_str(&body) {
+        Ok(value) => Ok(value),
+        Err(e) => {
+            log::warn!("Unexpected Twitter response {}: {} ({})", status, body, e);
+            Err(TwitterError::from_body(status, &body).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn from_body(status: u16, body: &str) -> TwitterError {
+        TwitterError::from_body(StatusCode::from_u16(status).unwrap(), body)
+    }
+
+    #[test]
+    fn reads_v1_errors() {
+        let body = r#"{"errors":[{"code":187,"message":"Status is a duplicate."}]}"#;
+        assert!(matches!(from_body(403, body), TwitterError::Duplicate));
+
+        let body = r#"{"errors":[{"code":144,"message":"No status found with that ID."}]}"#;
+        let TwitterError::NotFound { detail } = from_body(404, body) else {
+            panic!("Expected NotFound");
+        };
+        assert_eq!(detail, "No status found with that ID.");
+
+        let body =
+            r#"{"errors":[{"code":261,"message":"Application cannot perform write actions."}]}"#;
+        let TwitterError::Forbidden { detail } = from_body(403, body) else {
+            panic!("Expected Forbidden");
+        };
+        assert_eq!(detail, "Application cannot perform write actions.");
+    }
+
+    #[test]
+    fn reads_v2_problems() {
+        let body = r#"{"title":"Forbidden","detail":"You are not allowed to create a Tweet with duplicate content.","type":"about:blank","status":403}"#;
+        assert!(matches!(from_body(403, body), TwitterError::Duplicate));
+
+        // Missing resources can come back as a 200 with only errors.
+        let body = r#"{"errors":[{"detail":"Could not find tweet with id: [1].","title":"Not Found Error","type":"https://api.twitter.com/2/problems/resource-not-found"}]}"#;
+        let TwitterError::NotFound { detail } = from_body(200, body) else {
+            panic!("Expected NotFound");
+        };
+        assert_eq!(detail, "Could not find tweet with id: [1].");
+
+        let body =
+            r#"{"title":"Unauthorized","type":"about:blank","status":401,"detail":"Unauthorized"}"#;
+        assert!(matches!(
+            from_body(401, body),
+            TwitterError::Unauthorized { .. }
+        ));
+
+        let body = r#"{"title":"Invalid Request","type":"https://api.twitter.com/2/problems/invalid-request"}"#;
+        let TwitterError::Other { status, detail } = from_body(400, body) else {
+            panic!("Expected Other");
+        };
+        assert_eq!((status.as_u16(), detail.as_str()), (400, "Invalid Request"));
+    }
+
+    #[test]
+    fn keeps_bodies_that_are_not_json() {
+        assert!(matches!(
+            from_body(503, "<html>Over capacity</html>"),
+            TwitterError::ServerError { .. }
+        ));
+        let TwitterError::Other { detail, .. } = from_body(400, "Bad request") else {
+            panic!("Expected Other");
+        };
+        assert_eq!(detail, "Bad request");
+        let TwitterError::Forbidden { detail } = from_body(403, &"x".repeat(500)) else {
+            panic!("Expected Forbidden");
+        };
+        assert_eq!(detail.len(), 200);
+    }
+
+    #[test]
+    fn waits_for_the_exhausted_limit() {
+        let mut headers = HeaderMap::new();
+        let mut set = |name: &'static str, value: &str| {
+            headers.insert(name, HeaderValue::from_str(value).unwrap());
+        };
+        set("x-rate-limit-remaining", "10");
+        set("x-rate-limit-reset", "1000");
+        set("x-user-limit-24hour-remaining", "0");
+        set("x-user-limit-24hour-reset", "5000");
+        assert_eq!(RateLimitHeaders::parse(&headers).reset_at(), 5000);
+
+        // Without an exhausted limit the endpoint's window is assumed.
+        headers.remove("x-user-limit-24hour-remaining");
+        assert_eq!(RateLimitHeaders::parse(&headers).reset_at(), 1000);
+    }
+}
@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
 
-use super::builder::TwitterClient;
+use super::{
+    builder::TwitterClient,
+    error::{decode, TwitterError},
+    request::Retry,
+};
 
 #[derive(Debug, Deserialize)]
 struct UserInfoResponse {
@@ -16,18 +20,40 @@ pub struct UserInfo {
     // pub most_recent_tweet_id: Option<String>,
 }
 
+/// What Twitter currently says about the account behind a client.
+#[derive(Debug)]
+pub enum AccountStatus {
+    Active(UserInfo),
+    /// The credentials were rejected, usually because the user revoked the
+    /// app's access.
+    Revoked,
+}
+
 impl TwitterClient<'_> {
     pub async fn get_user_info(&self) -> eyre::Result<UserInfo> {
-        let resp = self.client
-            .get(
-            "https://api.twitter.com/2/users/me?user.fields=profile_image_url,most_recent_tweet_id"
-                .to_string(),
-        )
-        .send()
-        .await?;
-        let user_info: UserInfoResponse = resp.json().await?;
+        match self.get_account_status().await? {
+            AccountStatus::Active(user_info) => Ok(user_info),
+            AccountStatus::Revoked => eyre::bail!("Twitter rejected the account's credentials"),
+        }
+    }
+
+    pub async fn get_account_status(&self) -> eyre::Result<AccountStatus> {
+        let resp = self
+            .execute(Retry::Idempotent, || {
+                self.get(
+                    "https://api.twitter.com/2/users/me?user.fields=profile_image_url,most_recent_tweet_id",
+                )
+            })
+            .await;
+        let resp = match resp {
+            Err(e) if matches!(e.downcast_ref(), Some(TwitterError::Unauthorized { .. })) => {
+                return Ok(AccountStatus::Revoked);
+            }
+            resp => resp?,
+        };
+        let user_info: UserInfoResponse = decode(resp).await?;
         let user_info = user_info.data;
         log::info!("Fetched x_info: {:?}", user_info);
-        Ok(user_info)
+        Ok(AccountStatus::Active(user_info))
     }
 }
@@ -0,0 +1,370 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{builder::TwitterClient, error::decode, request::Retry};
+
+const UPLOAD_URL: &str = "https://upload.twitter.com/1.1/media/upload.json";
+const METADATA_URL: &str = "https://upload.twitter.com/1.1/media/metadata/create.json";
+/// Twitter accepts APPEND segments of up to 5 MB.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// How long to wait for Twitter to finish processing a video.
+const MAX_PROCESSING_SECS: u64 = 5 * 60;
+
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+const MAX_GIF_BYTES: usize = 15 * 1024 * 1024;
+const MAX_VIDEO_BYTES: usize = 512 * 1024 * 1024;
+const MAX_VIDEO_SECS: u32 = 140;
+pub const MAX_ALT_TEXT_CHARS: usize = 1000;
+/// Images per tweet. A GIF or video has to be a tweet's only media.
+pub const MAX_IMAGES: usize = 4;
+/// `ftyp` brands of MP4 and M4V videos. Other ISO media files, such as HEIC
+/// and AVIF images, share the container but not the brands.
+const MP4_BRANDS: &[&[u8; 4]] = &[
+    b"isom", b"iso2", b"iso3", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ",
+    b"M4VH", b"M4VP", b"dash", b"mmp4",
+];
+
+/// How Twitter should treat an upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaCategory {
+    Image,
+    Gif,
+    Video,
+}
+
+impl MediaCategory {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Image => "tweet_image",
+            Self::Gif => "tweet_gif",
+            Self::Video => "tweet_video",
+        }
+    }
+}
+
+/// A file sent to the bot, to be attached to a tweet.
+#[derive(Debug, Clone)]
+pub struct MediaFile {
+    pub bytes: Vec<u8>,
+    /// As reported by Telegram, if it did.
+    pub mime_type: Option<String>,
+    pub duration_secs: Option<u32>,
+    /// A description for people who cannot see the image.
+    pub alt_text: Option<String>,
+}
+
+impl MediaFile {
+    /// The MIME type from the file's magic bytes, falling back to the one
+    /// Telegram reported.
+    fn media_type(&self) -> Option<&str> {
+        let b = &self.bytes;
+        let sniffed = if b.starts_with(b"GIF8") {
+            Some("image/gif")
+        } else if b.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some("image/jpeg")
+        } else if b.starts_with(b"\x89PNG") {
+            Some("image/png")
+        } else if b.len() >= 12 && &b[..4] == b"RIFF" && &b[8..12] == b"WEBP" {
+            Some("image/webp")
+        } else if b.len() >= 12 && &b[4..8] == b"ftyp" {
+            Some(match &b[8..12] {
+                b"qt  " => "video/quicktime",
+                brand if MP4_BRANDS.iter().any(|mp4| mp4.as_slice() == brand) => "video/mp4",
+                b"heic" | b"heix" | b"mif1" | b"msf1" => "image/heic",
+                b"avif" | b"avis" => "image/avif",
+                // Whatever Telegram says, it is not an MP4 Twitter can take.
+                _ => "",
+            })
+        } else {
+            None
+        };
+        sniffed.or(self.mime_type.as_deref())
+    }
+
+    /// Detects what the file is and checks it against Twitter's limits.
+    /// Telegram turns GIFs into silent MP4s, which are posted as videos.
+    pub fn category(&self) -> eyre::Result<MediaCategory> {
+        let media_type = self.media_type().unwrap_or_default();
+        let (category, max_bytes) = match media_type {
+            "image/gif" => (MediaCategory::Gif, MAX_GIF_BYTES),
+            "image/jpeg" | "image/png" | "image/webp" => (MediaCategory::Image, MAX_IMAGE_BYTES),
+            "video/mp4" | "video/quicktime" => (MediaCategory::Video, MAX_VIDEO_BYTES),
+            "" => eyre::bail!("Unrecognized file type, send a photo, GIF or MP4 video"),
+            other => eyre::bail!("Twitter does not accept {} files", other),
+        };
+        if self.bytes.len() > max_bytes {
+            eyre::bail!(
+                "The file is {:.1} MB, Twitter accepts up to {} MB for this type",
+                self.bytes.len() as f64 / (1024.0 * 1024.0),
+                max_bytes / (1024 * 1024)
+            );
+        }
+        if let (MediaCategory::Video, Some(secs)) = (category, self.duration_secs) {
+            if secs > MAX_VIDEO_SECS {
+                eyre::bail!("Videos can be at most {} seconds long", MAX_VIDEO_SECS);
+            }
+        }
+        if self
+            .alt_text
+            .as_ref()
+            .is_some_and(|alt| alt.chars().count() > MAX_ALT_TEXT_CHARS)
+        {
+            eyre::bail!(
+                "Alt text can be at most {} characters long",
+                MAX_ALT_TEXT_CHARS
+            );
+        }
+        Ok(category)
+    }
+
+    /// Whether the chat's alt text rule applies to this file. Videos have
+    /// subtitles instead.
+    pub fn needs_alt_text(&self) -> bool {
+        self.alt_text.is_none()
+            && self
+                .category()
+                .is_ok_and(|category| category != MediaCategory::Video)
+    }
+}
+
+/// Checks that `files` can be attached to the same tweet.
+pub fn validate_attachments(files: &[MediaFile]) -> eyre::Result<()> {
+    let categories = files
+        .iter()
+        .map(MediaFile::category)
+        .collect::<eyre::Result<Vec<_>>>()?;
+    if categories.len() > 1 && categories.iter().any(|c| *c != MediaCategory::Image) {
+        eyre::bail!("A GIF or video has to be the only media of a tweet");
+    }
+    if categories.len() > MAX_IMAGES {
+        eyre::bail!(
+            "A tweet can have at most {} images, this one has {}",
+            MAX_IMAGES,
+            categories.len()
+        );
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct InitQuery<'a> {
+    command: &'static str,
+    total_bytes: usize,
+    media_type: &'a str,
+    media_category: &'static str,
+}
+
+#[derive(Serialize)]
+struct MediaQuery<'a> {
+    command: &'static str,
+    media_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    segment_index: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct AltText<'a> {
+    text: &'a str,
+}
+
+#[derive(Serialize)]
+struct MediaMetadata<'a> {
+    media_id: &'a str,
+    alt_text: AltText<'a>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProcessingError {
+    message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProcessingInfo {
+    /// `pending`, `in_progress`, `succeeded` or `failed`.
+    state: String,
+    check_after_secs: Option<u64>,
+    error: Option<ProcessingError>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChunkedUploadResponse {
+    media_id_string: String,
+    processing_info: Option<ProcessingInfo>,
+}
+
+impl TwitterClient<'_> {
+    /// Uploads `file` along with its alt text and returns its media id,
+    /// using the chunked flow for GIFs and videos.
+    pub async fn upload(&self, file: &MediaFile) -> eyre::Result<String> {
+        let media_id = match file.category()? {
+            MediaCategory::Image => self.upload_media(file.bytes.clone()).await?,
+            category => self.upload_chunked(file, category).await?,
+        };
+        if let Some(alt_text) = &file.alt_text {
+            self.set_alt_text(&media_id, alt_text).await?;
+        }
+        Ok(media_id)
+    }
+
+    pub async fn set_alt_text(&self, media_id: &str, alt_text: &str) -> eyre::Result<()> {
+        self.require_oauth1()?;
+        let body = serde_json::to_string(&MediaMetadata {
+            media_id,
+            alt_text: AltText { text: alt_text },
+        })?;
+        self.execute(Retry::Idempotent, || {
+            self.post(METADATA_URL)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone())
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn upload_chunked(
+        &self,
+        file: &MediaFile,
+        category: MediaCategory,
+    ) -> eyre::Result<String> {
+        self.require_oauth1()?;
+        let media_type = file.media_type().unwrap_or_default();
+        let init = InitQuery {
+            command: "INIT",
+            total_bytes: file.bytes.len(),
+            media_type,
+            media_category: category.as_str(),
+        };
+        let resp = self
+            .execute(Retry::Idempotent, || self.post(UPLOAD_URL).query(&init))
+            .await?;
+        let media_id = decode::<ChunkedUploadResponse>(resp).await?.media_id_string;
+
+        for (segment_index, chunk) in file.bytes.chunks(CHUNK_SIZE).enumerate() {
+            let query = MediaQuery {
+                command: "APPEND",
+                media_id: &media_id,
+                segment_index: Some(segment_index),
+            };
+            // Appending the same segment again replaces it.
+            self.execute(Retry::Idempotent, || {
+                let form = reqwest::multipart::Form::new()
+                    .part("media", reqwest::multipart::Part::bytes(chunk.to_vec()));
+                self.post(UPLOAD_URL).query(&query).multipart(form)
+            })
+            .await?;
+        }
+
+        let finalize = MediaQuery {
+            command: "FINALIZE",
+            media_id: &media_id,
+            segment_index: None,
+        };
+        let resp = self
+            .execute(Retry::Idempotent, || self.post(UPLOAD_URL).query(&finalize))
+            .await?;
+        let mut processing = decode::<ChunkedUploadResponse>(resp).await?.processing_info;
+
+        let status = MediaQuery {
+            command: "STATUS",
+            media_id: &media_id,
+            segment_index: None,
+        };
+        let mut waited_secs = 0;
+        while let Some(info) = processing {
+            match info.state.as_str() {
+                "succeeded" => break,
+                "failed" => eyre::bail!(
+                    "Twitter could not process the media: {}",
+                    info.error
+                        .and_then(|e| e.message)
+                        .unwrap_or_else(|| "unknown error".to_string())
+                ),
+                _ => {}
+            }
+            let wait_secs = info.check_after_secs.unwrap_or(1).max(1);
+            waited_secs += wait_secs;
+            if waited_secs > MAX_PROCESSING_SECS {
+                eyre::bail!("Twitter took too long to process the media");
+            }
+            tokio::time::sleep(Duration::from_secs(wait_secs)).await;
+            let resp = self
+                .execute(Retry::Idempotent, || self.get(UPLOAD_URL).query(&status))
+                .await?;
+            processing = decode::<ChunkedUploadResponse>(resp).await?.processing_info;
+        }
+        Ok(media_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(bytes: &[u8], mime_type: Option<&str>) -> MediaFile {
+        MediaFile {
+            bytes: bytes.to_vec(),
+            mime_type: mime_type.map(str::to_string),
+            duration_secs: None,
+            alt_text: None,
+        }
+    }
+
+    fn iso_media(brand: &[u8; 4]) -> MediaFile {
+        let bytes = [b"\0\0\0\x18ftyp".as_slice(), brand, b"\0\0\0\0"].concat();
+        // What Telegram reports does not matter once the brand is known.
+        file(&bytes, Some("video/mp4"))
+    }
+
+    #[test]
+    fn sniffs_images() {
+        let category = |bytes: &[u8]| file(bytes, None).category().unwrap();
+        assert_eq!(category(b"GIF89a"), MediaCategory::Gif);
+        assert_eq!(category(b"\xFF\xD8\xFF\xE0"), MediaCategory::Image);
+        assert_eq!(category(b"\x89PNG\r\n"), MediaCategory::Image);
+        assert_eq!(category(b"RIFF\0\0\0\0WEBPVP8 "), MediaCategory::Image);
+        // Unknown bytes fall back to the reported type.
+        let reported = file(b"data", Some("image/png"));
+        assert_eq!(reported.category().unwrap(), MediaCategory::Image);
+        assert!(file(b"data", None).category().is_err());
+    }
+
+    #[test]
+    fn takes_mp4_and_quicktime_videos() {
+        for brand in [b"isom", b"mp42", b"avc1", b"M4V ", b"qt  "] {
+            assert_eq!(
+                iso_media(brand).category().unwrap(),
+                MediaCategory::Video,
+                "{}",
+                String::from_utf8_lossy(brand)
+            );
+        }
+        assert_eq!(iso_media(b"qt  ").media_type(), Some("video/quicktime"));
+    }
+
+    #[test]
+    fn refuses_other_iso_media() {
+        let err = iso_media(b"heic").category().unwrap_err();
+        assert!(err.to_string().contains("image/heic"));
+        let err = iso_media(b"avif").category().unwrap_err();
+        assert!(err.to_string().contains("image/avif"));
+        assert!(iso_media(b"mif1").category().is_err());
+        assert!(iso_media(b"3gp4").category().is_err());
+    }
+
+    #[test]
+    fn checks_size_length_and_alt_text() {
+        let mut video = iso_media(b"isom");
+        video.duration_secs = Some(MAX_VIDEO_SECS + 1);
+        assert!(video.category().is_err());
+        assert!(!video.needs_alt_text());
+
+        let mut image = file(b"\x89PNG", None);
+        assert!(image.needs_alt_text());
+        image.alt_text = Some("a".repeat(MAX_ALT_TEXT_CHARS + 1));
+        assert!(image.category().is_err());
+        image.bytes.resize(MAX_IMAGE_BYTES + 1, 0);
+        image.alt_text = None;
+        assert!(image.category().is_err());
+    }
+}
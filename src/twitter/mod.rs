@@ -1,8 +1,14 @@
 pub mod auth;
 pub mod builder;
+pub mod error;
 pub mod info;
+pub mod media;
+pub mod oauth2;
+pub mod poll;
 pub mod post;
 pub mod react;
+pub mod request;
+pub mod text;
 pub mod tweet;
 
 // #[cfg(test)]
@@ -0,0 +1,258 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::db::unix_now;
+
+const AUTHORIZE_URL: &str = "https://twitter.com/i/oauth2/authorize";
+const TOKEN_URL: &str = "https://api.twitter.com/2/oauth2/token";
+const REVOKE_URL: &str = "https://api.twitter.com/2/oauth2/revoke";
+
+/// Scopes every OAuth 2.0 login asks for on top of the ones the user picks, so
+/// the bot can identify the account and keep the login alive.
+pub const REQUIRED_SCOPES: &[&str] = &["users.read", "tweet.read", "offline.access"];
+
+/// Scopes requested when the user does not pick any.
+pub const DEFAULT_SCOPES: &[&str] = &["tweet.write", "like.write"];
+
+pub const KNOWN_SCOPES: &[&str] = &[
+    "tweet.read",
+    "tweet.write",
+    "tweet.moderate.write",
+    "users.read",
+    "follows.read",
+    "follows.write",
+    "offline.access",
+    "space.read",
+    "mute.read",
+    "mute.write",
+    "like.read",
+    "like.write",
+    "list.read",
+    "list.write",
+    "block.read",
+    "block.write",
+    "bookmark.read",
+    "bookmark.write",
+    "media.write",
+];
+
+/// Access tokens are refreshed once they are this close to expiring.
+const REFRESH_MARGIN_SECS: u64 = 5 * 60;
+
+#[derive(Debug, Clone)]
+pub struct OAuth2Config {
+    pub client_id: String,
+    /// Only confidential clients have a secret.
+    pub client_secret: Option<String>,
+    /// Must match a callback URL registered for the app.
+    pub redirect_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OAuth2Token {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Unix timestamp in seconds.
+    pub expires_at: u64,
+    pub scopes: Vec<String>,
+}
+
+impl OAuth2Token {
+    pub fn needs_refresh(&self) -> bool {
+        self.expires_at <= unix_now() + REFRESH_MARGIN_SECS
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: u64,
+    scope: String,
+}
+
+impl From<TokenResponse> for OAuth2Token {
+    fn from(resp: TokenResponse) -> Self {
+        Self {
+            access_token: resp.access_token,
+            refresh_token: resp.refresh_token,
+            expires_at: unix_now() + resp.expires_in,
+            scopes: resp.scope.split(' ').map(str::to_string).collect(),
+        }
+    }
+}
+
+/// A PKCE code verifier and its S256 challenge.
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let verifier = URL_SAFE_NO_PAD.encode(bytes);
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+/// Parses a space separated scope list, falling back to [`DEFAULT_SCOPES`],
+/// and adds the [`REQUIRED_SCOPES`].
+pub fn parse_scopes(input: &str) -> eyre::Result<Vec<String>> {
+    let mut scopes: Vec<String> = input.split_whitespace().map(str::to_string).collect();
+    if let Some(unknown) = scopes.iter().find(|s| !KNOWN_SCOPES.contains(&s.as_str())) {
+        eyre::bail!("Unknown scope: {}", unknown);
+    }
+    if scopes.is_empty() {
+        scopes = DEFAULT_SCOPES.iter().map(|s| s.to_string()).collect();
+    }
+    for scope in REQUIRED_SCOPES {
+        if !scopes.iter().any(|s| s == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+    Ok(scopes)
+}
+
+pub fn authorize_url(
+    config: &OAuth2Config,
+    scopes: &[String],
+    state: &str,
+    code_challenge: &str,
+) -> eyre::Result<String> {
+    let url = url::Url::parse_with_params(
+        AUTHORIZE_URL,
+        &[
+            ("response_type", "code"),
+            ("client_id", &config.client_id),
+            ("redirect_uri", &config.redirect_uri),
+            ("scope", &scopes.join(" ")),
+            ("state", state),
+            ("code_challenge", code_challenge),
+            ("code_challenge_method", "S256"),
+        ],
+    )?;
+    Ok(url.to_string())
+}
+
+/// A token endpoint answered with an error status.
+#[derive(Debug)]
+pub struct TokenEndpointError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for TokenEndpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "OAuth 2.0 request failed with {}: {}",
+            self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for TokenEndpointError {}
+
+/// Whether `e` means Twitter no longer accepts the grant, as opposed to a
+/// transient failure.
+pub fn is_grant_rejected(e: &eyre::Report) -> bool {
+    e.downcast_ref::<TokenEndpointError>().is_some_and(|e| {
+        matches!(
+            e.status,
+            reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::UNAUTHORIZED
+        )
+    })
+}
+
+/// Posts `params` to one of the token endpoints, authenticating as the client.
+async fn post_form(
+    config: &OAuth2Config,
+    url: &str,
+    params: &[(&str, &str)],
+) -> eyre::Result<reqwest::Response> {
+    let mut params = params.to_vec();
+    let mut req = reqwest::Client::new().post(url);
+    match &config.client_secret {
+        Some(secret) => req = req.basic_auth(&config.client_id, Some(secret)),
+        None => params.push(("client_id", &config.client_id)),
+    }
+    let resp = req.form(&params).send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await?;
+        return Err(TokenEndpointError { status, body }.into());
+    }
+    Ok(resp)
+}
+
+async fn request_token(
+    config: &OAuth2Config,
+    params: &[(&str, &str)],
+) -> eyre::Result<OAuth2Token> {
+    let token: TokenResponse = post_form(config, TOKEN_URL, params).await?.json().await?;
+    Ok(token.into())
+}
+
+/// Exchanges the authorization code from the callback for tokens.
+pub async fn exchange_code(
+    config: &OAuth2Config,
+    code: &str,
+    code_verifier: &str,
+) -> eyre::Result<OAuth2Token> {
+    request_token(
+        config,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &config.redirect_uri),
+            ("code_verifier", code_verifier),
+        ],
+    )
+    .await
+}
+
+pub async fn refresh_token(
+    config: &OAuth2Config,
+    token: &OAuth2Token,
+) -> eyre::Result<OAuth2Token> {
+    let refresh_token = token
+        .refresh_token
+        .as_deref()
+        .ok_or_else(|| eyre::eyre!("Access token expired and there is no refresh token"))?;
+    let mut refreshed = request_token(
+        config,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ],
+    )
+    .await?;
+    if refreshed.refresh_token.is_none() {
+        refreshed.refresh_token = token.refresh_token.clone();
+    }
+    Ok(refreshed)
+}
+
+/// Revokes the grant behind `token`. Revoking the refresh token also
+/// invalidates the access tokens issued with it.
+pub async fn revoke_token(config: &OAuth2Config, token: &OAuth2Token) -> eyre::Result<()> {
+    let (token, hint) = match &token.refresh_token {
+        Some(refresh_token) => (refresh_token, "refresh_token"),
+        None => (&token.access_token, "access_token"),
+    };
+    post_form(
+        config,
+        REVOKE_URL,
+        &[("token", token), ("token_type_hint", hint)],
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use serde::{de::IgnoredAny, Deserialize};
+
+use super::{
+    builder::TwitterClient,
+    error::{decode, TwitterError},
+    request::Retry,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct PollOption {
+    pub position: u32,
+    pub label: String,
+    pub votes: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PollResults {
+    pub options: Vec<PollOption>,
+    /// `open` or `closed`.
+    pub voting_status: String,
+    /// ISO 8601, e.g. `2024-06-01T12:00:00.000Z`.
+    pub end_datetime: Option<String>,
+}
+
+impl PollResults {
+    pub fn is_closed(&self) -> bool {
+        self.voting_status == "closed"
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct Includes {
+    #[serde(default)]
+    polls: Vec<PollResults>,
+}
+
+/// Twitter answers a lookup of a missing tweet with `errors` instead of
+/// `data`.
+#[derive(Debug, Deserialize)]
+struct PollTweetResponse {
+    data: Option<IgnoredAny>,
+    #[serde(default)]
+    includes: Includes,
+}
+
+impl TwitterClient<'_> {
+    /// Fetches the poll of `tweet_id` with its vote counts, if the tweet has
+    /// one.
+    pub async fn get_poll(&self, tweet_id: &str) -> eyre::Result<Option<PollResults>> {
+        let url = format!(
+            "https://api.twitter.com/2/tweets/{}?expansions=attachments.poll_ids&poll.fields=end_datetime,voting_status",
+            tweet_id
+        );
+        let resp = self
+            .execute(Retry::Idempotent, || self.get(url.clone()))
+            .await?;
+        let poll_tweet: PollTweetResponse = decode(resp).await?;
+        if poll_tweet.data.is_none() {
+            return Err(TwitterError::NotFound {
+                detail: format!("Tweet {} not found", tweet_id),
+            }
+            .into());
+        }
+        Ok(poll_tweet.includes.polls.into_iter().next())
+    }
+}
@@ -1,6 +1,11 @@
 use serde::Deserialize;
 
-use super::{builder::TwitterClient, tweet::Tweet};
+use super::{
+    builder::TwitterClient,
+    error::{decode, TwitterError},
+    request::Retry,
+    tweet::Tweet,
+};
 
 #[derive(Debug, Deserialize)]
 struct SendTweetData {
@@ -14,6 +19,16 @@ struct SendTweetResponse {
     data: SendTweetData,
 }
 
+#[derive(Debug, Deserialize)]
+struct DeleteTweetData {
+    deleted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteTweetResponse {
+    data: DeleteTweetData,
+}
+
 #[derive(Deserialize, Debug)]
 struct MediaUploadResponse {
     // media_data: String,
@@ -25,28 +40,48 @@ impl TwitterClient<'_> {
         tweet.validate()?;
         let body = serde_json::to_string(&tweet)?;
         let resp = self
-            .client
-            .post("https://api.twitter.com/2/tweets".to_string())
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .body(body)
-            .send()
+            .execute(Retry::Once, || {
+                self.post("https://api.twitter.com/2/tweets")
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body.clone())
+            })
             .await?;
 
-        let tweet_response: SendTweetResponse = resp.json().await?;
+        let tweet_response: SendTweetResponse = decode(resp).await?;
         log::info!("Tweet response: {:?}", tweet_response);
         Ok(tweet_response.data.id)
     }
 
+    pub async fn delete_tweet(&self, tweet_id: String) -> eyre::Result<()> {
+        let url = format!("https://api.twitter.com/2/tweets/{}", tweet_id);
+        // A repeated delete fails as not found, which is what it reports.
+        let resp = self
+            .execute(Retry::Idempotent, || self.delete(url.clone()))
+            .await?;
+        let response: DeleteTweetResponse = decode(resp).await?;
+        if !response.data.deleted {
+            return Err(TwitterError::Forbidden {
+                detail: "Twitter did not delete it".to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
     pub async fn upload_media(&self, media_bytes: Vec<u8>) -> eyre::Result<String> {
-        let form = reqwest::multipart::Form::new()
-            .part("media", reqwest::multipart::Part::bytes(media_bytes));
+        self.require_oauth1()?;
+        // An upload that is sent twice only leaves an unused media id behind.
         let resp = self
-            .client
-            .post("https://upload.twitter.com/1.1/media/upload.json".to_string())
-            .multipart(form)
-            .send()
+            .execute(Retry::Idempotent, || {
+                let form = reqwest::multipart::Form::new().part(
+                    "media",
+                    reqwest::multipart::Part::bytes(media_bytes.clone()),
+                );
+                self.post("https://upload.twitter.com/1.1/media/upload.json")
+                    .multipart(form)
+            })
             .await?;
-        let media_upload_response: MediaUploadResponse = resp.json().await?;
+        let media_upload_response: MediaUploadResponse = decode(resp).await?;
         Ok(media_upload_response.media_id_string)
     }
 
@@ -1,32 +1,90 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use super::builder::TwitterClient;
+use super::{
+    builder::TwitterClient,
+    error::{decode, TwitterError},
+    request::Retry,
+};
 
 #[derive(Debug, Serialize)]
 struct LikeTweet {
     tweet_id: String,
 }
 
+/// `liked` or `retweeted`, depending on the endpoint.
+#[derive(Debug, Deserialize)]
+struct ReactionData {
+    liked: Option<bool>,
+    retweeted: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReactionResponse {
+    data: ReactionData,
+}
+
 impl TwitterClient<'_> {
-    pub async fn like(&self, x_id: String, tweet_id: String) -> eyre::Result<()> {
-        let _ = self
-            .client
-            .post(format!("https://api.twitter.com/2/users/{}/likes", x_id))
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .body(serde_json::to_string(&LikeTweet { tweet_id })?)
-            .send()
+    /// Posts `tweet_id` to a like or retweet endpoint and checks that Twitter
+    /// applied it.
+    async fn react(&self, url: String, tweet_id: String) -> eyre::Result<()> {
+        let body = serde_json::to_string(&LikeTweet { tweet_id })?;
+        // Liking or retweeting twice has no further effect.
+        let resp = self
+            .execute(Retry::Idempotent, || {
+                self.post(url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body.clone())
+            })
             .await?;
-        Ok(())
+        let reaction: ReactionResponse = decode(resp).await?;
+        if reaction.data.liked == Some(true) || reaction.data.retweeted == Some(true) {
+            return Ok(());
+        }
+        Err(TwitterError::Forbidden {
+            detail: "Twitter did not apply it".to_string(),
+        }
+        .into())
     }
 
-    pub async fn retweet(&self, x_id: String, tweet_id: String) -> eyre::Result<()> {
-        let _ = self
-            .client
-            .post(format!("https://api.twitter.com/2/users/{}/retweets", x_id))
-            .header(reqwest::header::CONTENT_TYPE, "application/json")
-            .body(serde_json::to_string(&LikeTweet { tweet_id })?)
-            .send()
+    /// Deletes a like or retweet at `url` and checks that Twitter removed it.
+    async fn unreact(&self, url: String) -> eyre::Result<()> {
+        // Removing a like or retweet twice has no further effect.
+        let resp = self
+            .execute(Retry::Idempotent, || self.delete(url.clone()))
             .await?;
-        Ok(())
+        let reaction: ReactionResponse = decode(resp).await?;
+        if reaction.data.liked == Some(false) || reaction.data.retweeted == Some(false) {
+            return Ok(());
+        }
+        Err(TwitterError::Forbidden {
+            detail: "Twitter did not remove it".to_string(),
+        }
+        .into())
+    }
+
+    pub async fn like(&self, x_id: String, tweet_id: String) -> eyre::Result<()> {
+        let url = format!("https://api.twitter.com/2/users/{}/likes", x_id);
+        self.react(url, tweet_id).await
+    }
+
+    pub async fn retweet(&self, x_id: String, tweet_id: String) -> eyre::Result<()> {
+        let url = format!("https://api.twitter.com/2/users/{}/retweets", x_id);
+        self.react(url, tweet_id).await
+    }
+
+    pub async fn unlike(&self, x_id: String, tweet_id: String) -> eyre::Result<()> {
+        let url = format!(
+            "https://api.twitter.com/2/users/{}/likes/{}",
+            x_id, tweet_id
+        );
+        self.unreact(url).await
+    }
+
+    pub async fn unretweet(&self, x_id: String, tweet_id: String) -> eyre::Result<()> {
+        let url = format!(
+            "https://api.twitter.com/2/users/{}/retweets/{}",
+            x_id, tweet_id
+        );
+        self.unreact(url).await
     }
 }
@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use oauth1_request::signature_method::HmacSha1;
+use reqwest::{header::HeaderValue, multipart::Form, Body, IntoUrl, Method, Response, StatusCode};
+use reqwest_oauth1::{Secrets, Signer};
+use serde::Serialize;
+
+use super::{
+    builder::{Signing, TwitterClient},
+    error::{error_for_status, RateLimitHeaders},
+};
+use crate::db::unix_now;
+
+const MAX_ATTEMPTS: u32 = 3;
+/// Longer rate limit waits are reported to the chat instead of waited out.
+const MAX_RATE_LIMIT_WAIT_SECS: u64 = 30;
+const BACKOFF_BASE_MILLIS: u64 = 500;
+
+/// Whether a request may be sent again after a server error or a dropped
+/// connection, when Twitter may already have acted on it. Requests rejected
+/// with 429 were not acted on and are always retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    Idempotent,
+    Once,
+}
+
+/// Exponential backoff with full jitter before attempt `attempt + 1`.
+fn backoff(attempt: u32) -> Duration {
+    let max = BACKOFF_BASE_MILLIS << attempt;
+    Duration::from_millis(OsRng.next_u64() % (max + 1))
+}
+
+type OAuth1RequestBuilder<'a> = reqwest_oauth1::RequestBuilder<Signer<'a, Secrets<'a>, HmacSha1>>;
+
+/// A request being built by a [`TwitterClient`], signed with whichever
+/// credentials the client was created with when it is sent.
+// Builders only live for the duration of a single call.
+#[allow(clippy::large_enum_variant)]
+pub enum TwitterRequest<'a> {
+    OAuth1(OAuth1RequestBuilder<'a>),
+    OAuth2(reqwest::RequestBuilder),
+}
+
+impl<'a> TwitterClient<'a> {
+    pub fn request<U: IntoUrl + Clone>(&self, method: Method, url: U) -> TwitterRequest<'a> {
+        match &self.signing {
+            Signing::OAuth1(client) => TwitterRequest::OAuth1(client.request(method, url)),
+            Signing::OAuth2 { client, token, .. } => {
+                TwitterRequest::OAuth2(client.request(method, url).bearer_auth(&token.access_token))
+            }
+        }
+    }
+
+    pub fn get<U: IntoUrl + Clone>(&self, url: U) -> TwitterRequest<'a> {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post<U: IntoUrl + Clone>(&self, url: U) -> TwitterRequest<'a> {
+        self.request(Method::POST, url)
+    }
+
+    pub fn delete<U: IntoUrl + Clone>(&self, url: U) -> TwitterRequest<'a> {
+        self.request(Method::DELETE, url)
+    }
+
+    /// Sends the request made by `build`, rebuilding it for every attempt so
+    /// it is signed afresh. Waits out short rate limits and, for idempotent
+    /// requests, retries server errors with jittered backoff. Unsuccessful
+    /// responses become a [`super::error::TwitterError`].
+    pub async fn execute(
+        &self,
+        retry: Retry,
+        build: impl Fn() -> TwitterRequest<'a>,
+    ) -> eyre::Result<Response> {
+        let mut attempt = 1;
+        loop {
+            let can_retry = attempt < MAX_ATTEMPTS;
+            let resp = match build().send().await {
+                Ok(resp) => resp,
+                Err(e) if can_retry && retry == Retry::Idempotent => {
+                    log::warn!("Twitter request failed, retrying: {:?}", e);
+                    tokio::time::sleep(backoff(attempt)).await;
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let status = resp.status();
+            let limits = RateLimitHeaders::parse(resp.headers());
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let wait_secs = limits.reset_at().saturating_sub(unix_now());
+                if can_retry && wait_secs <= MAX_RATE_LIMIT_WAIT_SECS {
+                    log::info!("Rate limited by Twitter, retrying in {}s", wait_secs);
+                    let wait = Duration::from_secs(wait_secs) + backoff(attempt);
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                    continue;
+                }
+            } else if status.is_server_error() && can_retry && retry == Retry::Idempotent {
+                log::warn!("Twitter returned {}, retrying", status);
+                tokio::time::sleep(backoff(attempt)).await;
+                attempt += 1;
+                continue;
+            } else if limits.remaining == Some(0) {
+                log::warn!(
+                    "Twitter rate limit exhausted for {}, resets at {:?}",
+                    resp.url().path(),
+                    limits.reset_at
+                );
+            }
+            return error_for_status(resp).await;
+        }
+    }
+}
+
+impl TwitterRequest<'_> {
+    pub fn header(self, key: reqwest::header::HeaderName, value: &'static str) -> Self {
+        let value = HeaderValue::from_static(value);
+        match self {
+            Self::OAuth1(req) => Self::OAuth1(req.header(key, value)),
+            Self::OAuth2(req) => Self::OAuth2(req.header(key, value)),
+        }
+    }
+
+    pub fn query<T: Serialize + ?Sized>(self, query: &T) -> Self {
+        match self {
+            Self::OAuth1(req) => Self::OAuth1(req.query(query)),
+            Self::OAuth2(req) => Self::OAuth2(req.query(query)),
+        }
+    }
+
+    pub fn body<T: Into<Body>>(self, body: T) -> Self {
+        match self {
+            Self::OAuth1(req) => Self::OAuth1(req.body(body)),
+            Self::OAuth2(req) => Self::OAuth2(req.body(body)),
+        }
+    }
+
+    pub fn multipart(self, form: Form) -> Self {
+        match self {
+            Self::OAuth1(req) => Self::OAuth1(req.multipart(form)),
+            Self::OAuth2(req) => Self::OAuth2(req.multipart(form)),
+        }
+    }
+
+    pub async fn send(self) -> eyre::Result<Response> {
+        let resp = match self {
+            Self::OAuth1(req) => req.send().await?,
+            Self::OAuth2(req) => req.send().await?,
+        };
+        Ok(resp)
+    }
+}
@@ -0,0 +1,183 @@
+use std::{ops::RangeInclusive, sync::OnceLock};
+
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The most characters Twitter accepts in a tweet.
+pub const MAX_TWEET_LENGTH: usize = 280;
+
+/// Weights are in hundredths of a character, as in twitter-text.
+const SCALE: usize = 100;
+const DEFAULT_WEIGHT: usize = 200;
+/// Code points that count as one character: Latin, Greek, Cyrillic and
+/// other scripts up to Georgian, plus some spaces and punctuation. Everything
+/// else, including CJK, counts as two.
+const LIGHT_RANGES: [RangeInclusive<u32>; 4] = [0..=4351, 8192..=8205, 8208..=8223, 8242..=8247];
+/// Every link is shortened to a t.co URL of this length.
+const URL_LENGTH: usize = 23;
+
+/// Top level domains that Twitter links without a scheme. Its full list is
+/// much longer; these cover what people usually write.
+pub const BARE_TLDS: &str =
+    "com|net|org|edu|gov|io|co|me|ai|app|dev|info|biz|xyz|ly|gg|tv|uk|de|fr|jp|ru|us|ca|eu";
+
+fn url_regex() -> &'static Regex {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    URL.get_or_init(|| {
+        Regex::new(&format!(
+            r"(?i)\bhttps?://\S+|\b(?:[a-z0-9-]+\.)+(?:{})\b(?:[/?#]\S*)?",
+            BARE_TLDS
+        ))
+        .unwrap()
+    })
+}
+
+/// The byte ranges of the links in `text`, without trailing punctuation.
+/// Domains of email addresses are not links.
+fn links(text: &str) -> Vec<(usize, usize)> {
+    url_regex()
+        .find_iter(text)
+        .filter(|m| !text[..m.start()].ends_with('@'))
+        .map(|m| {
+            let url = m
+                .as_str()
+                .trim_end_matches(['.', ',', ':', ';', '!', '?', '\'', '"', ')', ']']);
+            (m.start(), m.start() + url.len())
+        })
+        .collect()
+}
+
+fn is_emoji(grapheme: &str) -> bool {
+    grapheme.chars().any(|c| {
+        matches!(
+            c as u32,
+            0xFE0F | 0x20E3 | 0x2300..=0x23FF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x1F000..=0x1FAFF
+        )
+    })
+}
+
+fn char_weight(c: char) -> usize {
+    if LIGHT_RANGES.iter().any(|range| range.contains(&(c as u32))) {
+        SCALE
+    } else {
+        DEFAULT_WEIGHT
+    }
+}
+
+/// The weight of text without links. An emoji counts as two characters,
+/// however many code points it is made of.
+fn text_weight(text: &str) -> usize {
+    text.graphemes(true)
+        .map(|grapheme| {
+            if is_emoji(grapheme) {
+                DEFAULT_WEIGHT
+            } else {
+                grapheme.chars().map(char_weight).sum()
+            }
+        })
+        .sum()
+}
+
+/// The length Twitter counts for `text`, following twitter-text's v3 rules:
+/// the text is NFC normalized, links count as [`URL_LENGTH`] and characters
+/// are weighted by script.
+pub fn tweet_length(text: &str) -> usize {
+    let text = text.nfc().collect::<String>();
+    let mut weight = 0;
+    let mut last = 0;
+    for (start, end) in links(&text) {
+        weight += text_weight(&text[last..start]) + URL_LENGTH * SCALE;
+        last = end;
+    }
+    weight += text_weight(&text[last..]);
+    weight / SCALE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_latin_as_one() {
+        assert_eq!(tweet_length(""), 0);
+        assert_eq!(tweet_length("hello world"), 11);
+        assert_eq!(tweet_length("Ελληνικά кириллица"), 18);
+        assert_eq!(tweet_length(&"a".repeat(MAX_TWEET_LENGTH)), 280);
+    }
+
+    #[test]
+    fn counts_cjk_as_two() {
+        assert_eq!(tweet_length("日本語"), 6);
+        assert_eq!(tweet_length("한국어"), 6);
+        assert_eq!(tweet_length(&"字".repeat(140)), MAX_TWEET_LENGTH);
+        assert_eq!(tweet_length("日本 abc"), 8);
+    }
+
+    #[test]
+    fn counts_light_punctuation_as_one() {
+        // Curly quotes and dashes, but not the ellipsis.
+        assert_eq!(tweet_length("\u{201C}hi\u{201D}"), 4);
+        assert_eq!(tweet_length("a\u{2014}b"), 3);
+        assert_eq!(tweet_length("\u{2026}"), 2);
+        // A prime is in the light ranges, a check mark is not.
+        assert_eq!(tweet_length("\u{2032}"), 1);
+        assert_eq!(tweet_length("\u{2713}"), 2);
+    }
+
+    #[test]
+    fn normalizes_before_counting() {
+        // "é" written as "e" and a combining acute accent.
+        assert_eq!(tweet_length("cafe\u{301}"), 4);
+        assert_eq!(tweet_length("caf\u{E9}"), 4);
+        // Hangul jamo compose into one syllable.
+        assert_eq!(tweet_length("\u{1100}\u{1161}"), 2);
+    }
+
+    #[test]
+    fn counts_emoji_sequences_as_two() {
+        assert_eq!(tweet_length("😀"), 2);
+        // Skin tone modifier.
+        assert_eq!(tweet_length("👍🏽"), 2);
+        // Family joined with zero width joiners.
+        assert_eq!(tweet_length("👨\u{200D}👩\u{200D}👧\u{200D}👦"), 2);
+        // Regional indicator flag.
+        assert_eq!(tweet_length("🇯🇵"), 2);
+        // Keycap.
+        assert_eq!(tweet_length("1\u{FE0F}\u{20E3}"), 2);
+        // Subdivision flag built from tag characters.
+        assert_eq!(
+            tweet_length("🏴\u{E0067}\u{E0062}\u{E0073}\u{E0063}\u{E0074}\u{E007F}"),
+            2
+        );
+        assert_eq!(tweet_length("❤\u{FE0F} ok"), 5);
+    }
+
+    #[test]
+    fn keeps_joiners_in_scripts() {
+        // Devanagari with a zero width joiner is four light code points.
+        assert_eq!(tweet_length("\u{915}\u{94D}\u{200D}\u{937}"), 4);
+        assert_eq!(tweet_length("नमस्ते"), 6);
+    }
+
+    #[test]
+    fn counts_links_as_url_length() {
+        assert_eq!(tweet_length("https://example.com"), 23);
+        let long = format!("https://example.com/{}", "a".repeat(100));
+        assert_eq!(tweet_length(&long), 23);
+        assert_eq!(tweet_length("see example.com."), 4 + 23 + 1);
+        assert_eq!(tweet_length("(https://example.com/a)"), 1 + 23 + 1);
+        assert_eq!(tweet_length("https://例え.jp/日本"), 23);
+        assert_eq!(
+            tweet_length("a http://a.io b https://b.io"),
+            2 + 23 + 3 + 23
+        );
+    }
+
+    #[test]
+    fn leaves_non_links_alone() {
+        assert_eq!(tweet_length("me@example.com"), 14);
+        assert_eq!(tweet_length("node.js"), 7);
+        assert_eq!(tweet_length("e.g. this"), 9);
+    }
+}
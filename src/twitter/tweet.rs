@@ -1,22 +1,38 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+use super::text::{tweet_length, MAX_TWEET_LENGTH};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 struct Reply {
     in_reply_to_tweet_id: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 struct Media {
     media_ids: Vec<String>,
 }
 
+/// Twitter's limits on polls.
+const MIN_POLL_OPTIONS: usize = 2;
+const MAX_POLL_OPTIONS: usize = 4;
+const MAX_POLL_OPTION_LENGTH: usize = 25;
+const MIN_POLL_MINUTES: u32 = 5;
+const MAX_POLL_MINUTES: u32 = 7 * 24 * 60;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Poll {
+    options: Vec<String>,
+    duration_minutes: u32,
+}
+
 #[serde_with::skip_serializing_none]
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Tweet {
     text: String,
     quote_tweet_id: Option<String>,
     reply: Option<Reply>,
     media: Option<Media>,
+    poll: Option<Poll>,
 }
 
 impl Tweet {
@@ -26,6 +42,7 @@ impl Tweet {
             quote_tweet_id: None,
             reply: None,
             media: None,
+            poll: None,
         }
     }
 
@@ -33,15 +50,69 @@ impl Tweet {
         if self.text.is_empty() {
             eyre::bail!("Tweet text cannot be empty");
         }
+        let length = tweet_length(&self.text);
+        if length > MAX_TWEET_LENGTH {
+            eyre::bail!(
+                "The tweet is too long: {}/{} characters",
+                length,
+                MAX_TWEET_LENGTH
+            );
+        }
         if self.quote_tweet_id.is_some() && self.reply.is_some() {
             eyre::bail!("Tweet cannot be both a quote and a reply");
         }
         if self.media.is_some() && self.media.as_ref().unwrap().media_ids.is_empty() {
             eyre::bail!("Media IDs cannot be empty");
         }
+        if let Some(poll) = &self.poll {
+            if self.media.is_some() {
+                eyre::bail!("A tweet cannot have both a poll and media");
+            }
+            if self.quote_tweet_id.is_some() {
+                eyre::bail!("A quote tweet cannot have a poll");
+            }
+            if !(MIN_POLL_OPTIONS..=MAX_POLL_OPTIONS).contains(&poll.options.len()) {
+                eyre::bail!(
+                    "A poll needs {} to {} options, this one has {}",
+                    MIN_POLL_OPTIONS,
+                    MAX_POLL_OPTIONS,
+                    poll.options.len()
+                );
+            }
+            for option in &poll.options {
+                let length = option.chars().count();
+                if length == 0 || length > MAX_POLL_OPTION_LENGTH {
+                    eyre::bail!(
+                        "Poll options must be 1 to {} characters long, \"{}\" is {}",
+                        MAX_POLL_OPTION_LENGTH,
+                        option,
+                        length
+                    );
+                }
+            }
+            if !(MIN_POLL_MINUTES..=MAX_POLL_MINUTES).contains(&poll.duration_minutes) {
+                eyre::bail!("A poll can run from 5 minutes to 7 days");
+            }
+        }
         Ok(())
     }
 
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_reply(&self) -> bool {
+        self.reply.is_some()
+    }
+
+    pub fn is_quote(&self) -> bool {
+        self.quote_tweet_id.is_some()
+    }
+
+    pub fn has_media(&self) -> bool {
+        self.media.is_some()
+    }
+
     pub fn set_quote_tweet_id(&mut self, quote_tweet_id: String) {
         self.quote_tweet_id = Some(quote_tweet_id);
     }
@@ -52,6 +123,17 @@ impl Tweet {
         });
     }
 
+    pub fn has_poll(&self) -> bool {
+        self.poll.is_some()
+    }
+
+    pub fn set_poll(&mut self, options: Vec<String>, duration_minutes: u32) {
+        self.poll = Some(Poll {
+            options,
+            duration_minutes,
+        });
+    }
+
     pub fn set_media_ids(&mut self, media_ids: Vec<String>) {
         self.media = Some(Media { media_ids });
     }